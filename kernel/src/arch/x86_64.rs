@@ -1,3 +1,6 @@
 pub use init::init_x86_64;
+pub mod apic;
 pub mod devices;
 pub mod init;
+pub mod smp;
+pub mod tlb;
@@ -0,0 +1,129 @@
+//! Routing the interrupts through the local and I/O APICs, instead of the legacy 8259 PICs.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use essentials::address::{PhysicalAddress, VirtualAddress};
+use essentials::sync::{PanicOnce, SpinMutex};
+use x86_64::acpi::{Acpi, Madt, MadtEntry};
+use x86_64::devices::io_apic::{IoApic, Redirection};
+use x86_64::devices::local_apic::LocalApic;
+use x86_64::paging::{PageSize, PageTableEntryFlags, VirtualPage};
+
+use crate::arch::x86_64::devices::{
+    IO_APIC, LOCAL_APIC, PIC_CHAIN, SPURIOUS_INT_INDEX, TICK_INT_INDEX, TIMER_IRQ,
+};
+use crate::memory::{MemoryMapper, NewMappingError, TableCacheFlush};
+
+const LOCAL_APIC_ADDRESS: VirtualAddress = VirtualAddress::new(0x_5555_5555_0000);
+const IO_APIC_ADDRESS: VirtualAddress = VirtualAddress::new(0x_5555_5555_1000);
+
+/// Whether the interrupts are routed through the APICs, which changes how they are acknowledged.
+static APIC_ROUTING: AtomicBool = AtomicBool::new(false);
+
+static MADT: PanicOnce<Madt> = PanicOnce::new();
+
+#[derive(Debug, Clone, Copy)]
+pub enum ApicError {
+    AcpiNotFound,
+    MadtNotFound,
+    IoApicNotFound,
+    Mapping(NewMappingError),
+}
+
+impl From<NewMappingError> for ApicError {
+    fn from(value: NewMappingError) -> Self {
+        Self::Mapping(value)
+    }
+}
+
+/// Switch from the 8259 PICs to the APICs, and route the timer to the executing CPU.
+///
+/// The I/O APIC is found through the ACPI MADT, only the one handling the first global system interrupts is used.
+/// Returns the number of processors listed in the MADT.
+pub fn init_apic(mapper: &mut MemoryMapper) -> Result<usize, ApicError> {
+    let acpi =
+        unsafe { Acpi::find(mapper.physical_memory_offset()) }.ok_or(ApicError::AcpiNotFound)?;
+    let madt = acpi.madt().ok_or(ApicError::MadtNotFound)?;
+
+    let (io_apic_address, gsi_base) = madt
+        .entries()
+        .filter_map(|entry| match entry {
+            MadtEntry::IoApic {
+                address, gsi_base, ..
+            } => Some((address, gsi_base)),
+            _ => None,
+        })
+        .min_by_key(|(_, gsi_base)| *gsi_base)
+        .ok_or(ApicError::IoApicNotFound)?;
+
+    unsafe {
+        map_registers(mapper, LOCAL_APIC_ADDRESS, LocalApic::physical_base())?;
+        map_registers(mapper, IO_APIC_ADDRESS, io_apic_address)?;
+
+        LOCAL_APIC.initialize_with(LocalApic::new(LOCAL_APIC_ADDRESS));
+        IO_APIC.initialize_with(SpinMutex::new(IoApic::new(IO_APIC_ADDRESS, gsi_base)));
+    }
+
+    LOCAL_APIC.enable(SPURIOUS_INT_INDEX as u8);
+    IO_APIC.lock().mask_all();
+
+    if madt.has_8259() {
+        PIC_CHAIN.lock().disable();
+    }
+
+    let processors = madt.processor_count();
+    MADT.initialize_with(madt);
+    APIC_ROUTING.store(true, Ordering::Release);
+
+    route_isa_irq(TIMER_IRQ, TICK_INT_INDEX as u8);
+
+    Ok(processors)
+}
+
+/// Deliver the ISA `irq` on `vector` to the executing CPU, following the overrides in the MADT.
+pub fn route_isa_irq(irq: u8, vector: u8) {
+    let route = MADT.route_irq(irq);
+
+    IO_APIC.lock().redirect(
+        route.gsi,
+        Redirection {
+            vector,
+            destination: LOCAL_APIC.id(),
+            active_low: route.active_low,
+            level_triggered: route.level_triggered,
+        },
+    );
+}
+
+/// Acknowledge the device interrupt on `vector` to the interrupt controller that raised it.
+pub fn end_of_interrupt(vector: u8) {
+    if APIC_ROUTING.load(Ordering::Acquire) {
+        LOCAL_APIC.end_of_interrupt();
+    } else {
+        PIC_CHAIN.lock().end_of_interrupt(vector);
+    }
+}
+
+/// Map the memory mapped registers of an APIC with caching disabled.
+unsafe fn map_registers(
+    mapper: &mut MemoryMapper,
+    addr: VirtualAddress,
+    physical_addr: PhysicalAddress,
+) -> Result<(), NewMappingError> {
+    let mut flags = PageTableEntryFlags::default();
+    flags.set_present(true);
+    flags.set_writable(true);
+    flags.set_cache_disabled(true);
+    flags.set_write_through(true);
+
+    let mut parent_flags = PageTableEntryFlags::default();
+    parent_flags.set_present(true);
+    parent_flags.set_writable(true);
+
+    let page = VirtualPage::new(addr, PageSize::Size4Kib);
+
+    mapper
+        .map_to(flags, parent_flags, page, physical_addr)?
+        .flush();
+
+    Ok(())
+}
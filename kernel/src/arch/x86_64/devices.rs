@@ -1,15 +1,44 @@
-use essentials::sync::{Singleton, SpinMutex};
+use essentials::sync::{PanicOnce, Singleton, SpinMutex};
+use x86_64::devices::io_apic::IoApic;
+use x86_64::devices::local_apic::LocalApic;
 use x86_64::devices::pic_8259::ChainedPic8259;
+use x86_64::devices::pit_8253::Pit8253;
 use x86_64::devices::qemu::Qemu;
 use x86_64::devices::uart_16550::Uart16550;
 use x86_64::interrupts::InterruptDescriptorTable;
 
 const PIC_CHAIN_INTS_START: usize = InterruptDescriptorTable::STANDARD_INTERRUPTS_COUNT;
-pub const PIC_CHAIN_TICK_INT_INDEX: usize = PIC_CHAIN_INTS_START;
+/// The vectors the 8259 PICs raise for spurious interrupts, which can still happen while they are masked.
+pub const PIC_CHAIN_SPURIOUS_INT_INDEXES: [usize; 2] =
+    [PIC_CHAIN_INTS_START + 7, PIC_CHAIN_INTS_START + 15];
+
+/// The ISA IRQ of the PIT.
+pub const TIMER_IRQ: u8 = 0;
+/// The timer keeps the vector the PICs assigned to it, when it is routed through the I/O APIC.
+pub const TICK_INT_INDEX: usize = PIC_CHAIN_INTS_START + TIMER_IRQ as usize;
 
 pub static PIC_CHAIN: Singleton<SpinMutex<ChainedPic8259>> =
     Singleton::new(|| SpinMutex::new(unsafe { ChainedPic8259::new(PIC_CHAIN_INTS_START as u8) }));
 
+/// Raised by a thread to give up the rest of its time slice.
+pub const YIELD_INT_INDEX: usize = 0x81;
+
+/// Sent to the other CPUs on every tick, so they switch to their next thread.
+pub const RESCHEDULE_INT_INDEX: usize = 0xF0;
+/// Sent to the CPUs which may cache mappings that were removed, see [`crate::arch::x86_64::tlb`].
+pub const TLB_SHOOTDOWN_INT_INDEX: usize = 0xF1;
+pub const SPURIOUS_INT_INDEX: usize = 0xFF;
+
+/// The local APIC of every CPU, mapped when the kernel switches to the APICs.
+pub static LOCAL_APIC: PanicOnce<LocalApic> = PanicOnce::new();
+
+pub static IO_APIC: PanicOnce<SpinMutex<IoApic>> = PanicOnce::new();
+
+/// The frequency in Hz at which the timer interrupt fires.
+pub const TICK_FREQUENCY: u32 = 100;
+
+pub static PIT: SpinMutex<Pit8253> = SpinMutex::new(unsafe { Pit8253::new() });
+
 pub static QEMU_DEVICE: SpinMutex<Qemu> = SpinMutex::new(unsafe { Qemu::new() });
 
 pub static SERIAL: Singleton<SpinMutex<Uart16550>> =
@@ -1,28 +1,53 @@
-use crate::arch::x86_64::devices::{PIC_CHAIN, PIC_CHAIN_TICK_INT_INDEX, SERIAL};
+use crate::arch::x86_64::apic::end_of_interrupt;
+use crate::arch::x86_64::devices::{
+    LOCAL_APIC, PIC_CHAIN, PIC_CHAIN_SPURIOUS_INT_INDEXES, PIT, RESCHEDULE_INT_INDEX, SERIAL,
+    SPURIOUS_INT_INDEX, TICK_FREQUENCY, TICK_INT_INDEX, TLB_SHOOTDOWN_INT_INDEX, YIELD_INT_INDEX,
+};
+use crate::arch::x86_64::smp::{online_cpus, register_cpu, CpuId, MAX_CPUS};
+use crate::arch::x86_64::tlb;
 use core::arch::asm;
 use essentials::address::VirtualAddress;
-use essentials::sync::{PanicOnce, Singleton};
+use essentials::sync::{PanicOnce, Singleton, SpinOnce};
 use x86_64::constants::MIN_STACK_SIZE;
+use x86_64::devices::local_apic::{IpiDestination, IpiKind};
+use x86_64::extended_state::ExtendedStateFormat;
 use x86_64::interrupts::context::{InterruptStackFrame, InterruptedContext};
 use x86_64::interrupts::{InterruptDescriptorTable, PageFaultErrorCode};
+use x86_64::paging::enable_write_protection;
 use x86_64::segmentation::*;
 use x86_64::PrivilegeLevel;
 
 const DOUBLE_FAULT_IST_INDEX: usize = 0;
-
-fn init_tss() -> TaskStateSegment {
+/// The faults a service can cause, their handlers may raise the yield interrupt to exit the faulting thread.
+const FAULT_IST_INDEX: usize = 1;
+/// The interrupts that switch threads, on a stack of their own so they never reset the stack of a running fault handler.
+const CONTEXT_SWITCH_IST_INDEX: usize = 2;
+const IST_STACKS: usize = 3;
+
+type InterruptStacks = [[u8; MIN_STACK_SIZE]; IST_STACKS];
+
+fn new_tss(
+    interrupt_stacks: &'static mut InterruptStacks,
+    privilege_stack: &'static mut [u8],
+) -> TaskStateSegment {
     let mut tss = TaskStateSegment::new();
-    static mut STACK: [u8; MIN_STACK_SIZE] = [0; MIN_STACK_SIZE];
-    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX] =
-        InterruptStackRef::from_slice(unsafe { &mut STACK });
 
-    static mut PSTACK: [u8; MIN_STACK_SIZE] = [0; MIN_STACK_SIZE];
+    for (index, stack) in interrupt_stacks.iter_mut().enumerate() {
+        tss.interrupt_stack_table[index] = InterruptStackRef::from_slice(stack);
+    }
 
-    tss.privilege_stack_table[0] = InterruptStackRef::from_slice(unsafe { &mut PSTACK });
+    tss.privilege_stack_table[0] = InterruptStackRef::from_slice(privilege_stack);
 
     tss
 }
 
+fn init_tss() -> TaskStateSegment {
+    static mut STACKS: InterruptStacks = [[0; MIN_STACK_SIZE]; IST_STACKS];
+    static mut PSTACK: [u8; MIN_STACK_SIZE] = [0; MIN_STACK_SIZE];
+
+    new_tss(unsafe { &mut STACKS }, unsafe { &mut PSTACK })
+}
+
 pub static TSS: Singleton<TaskStateSegment> = Singleton::new(init_tss);
 
 pub struct FullGdt {
@@ -36,7 +61,10 @@ pub struct FullGdt {
     pub sysret: SegmentSelector,
 }
 
-fn init_gdt() -> FullGdt {
+/// Every CPU has its own GDT, because the TSS cannot be shared.
+///
+/// The tables all have the same layout, so the selectors in [`GDT`] are valid on every CPU.
+fn new_gdt(tss: &'static TaskStateSegment) -> FullGdt {
     let mut table = GlobalDescriptorTable::new();
 
     let kernel_code = table.add_entry(SegmentDescriptor::KERNEL_CODE).unwrap();
@@ -48,7 +76,7 @@ fn init_gdt() -> FullGdt {
     // User code is required by sysret to be the next entry after user data.
     let user_code = table.add_entry(SegmentDescriptor::USER_CODE).unwrap();
 
-    let tss = table.add_entry(SegmentDescriptor::new_tss(&TSS)).unwrap();
+    let tss = table.add_entry(SegmentDescriptor::new_tss(tss)).unwrap();
 
     FullGdt {
         table,
@@ -62,10 +90,66 @@ fn init_gdt() -> FullGdt {
     }
 }
 
-pub static GDT: Singleton<FullGdt> = Singleton::new(init_gdt);
+pub static GDT: Singleton<FullGdt> = Singleton::new(|| new_gdt(&TSS));
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NEW_AP_TSS: SpinOnce<TaskStateSegment> = SpinOnce::new();
+#[allow(clippy::declare_interior_mutable_const)]
+const NEW_AP_GDT: SpinOnce<FullGdt> = SpinOnce::new();
+
+/// The tables of the application processors, indexed by their [`CpuId`].
+static AP_TSS: [SpinOnce<TaskStateSegment>; MAX_CPUS] = [NEW_AP_TSS; MAX_CPUS];
+static AP_GDT: [SpinOnce<FullGdt>; MAX_CPUS] = [NEW_AP_GDT; MAX_CPUS];
+
+static mut AP_INTERRUPT_STACKS: [InterruptStacks; MAX_CPUS] =
+    [[[0; MIN_STACK_SIZE]; IST_STACKS]; MAX_CPUS];
+static mut AP_PRIVILEGE_STACKS: [[u8; MIN_STACK_SIZE]; MAX_CPUS] = [[0; MIN_STACK_SIZE]; MAX_CPUS];
+
+/// How the FPU and SIMD registers of threads are saved, the same on every CPU.
+pub static EXTENDED_STATE_FORMAT: Singleton<ExtendedStateFormat> =
+    Singleton::new(ExtendedStateFormat::detect);
 
 pub struct InterruptHandlers {
+    /// Called on every timer interrupt.
     pub tick: fn(ctx: InterruptedContext) -> *const InterruptedContext,
+    /// Called when a thread voluntarily gives up the rest of its time slice.
+    pub yield_current: fn(ctx: InterruptedContext) -> *const InterruptedContext,
+    /// Called on the other CPUs when the timer interrupt fires, without advancing the clock.
+    pub preempt: fn(ctx: InterruptedContext) -> *const InterruptedContext,
+    /// Called on a page fault at the address, returns whether the fault is resolved and the access can be retried.
+    pub page_fault: fn(addr: VirtualAddress, error_code: PageFaultErrorCode) -> bool,
+    /// Called on a fault that cannot be resolved, only returns when the fault happened in kernel context.
+    pub fault: fn(fault: Fault),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum FaultKind {
+    DivideError,
+    InvalidOpcode,
+    GeneralProtection {
+        error_code: u64,
+    },
+    PageFault {
+        addr: VirtualAddress,
+        error_code: PageFaultErrorCode,
+    },
+    SimdFloatingPoint,
+}
+
+/// A fault that could not be resolved, with the instruction that caused it.
+#[derive(Debug, Clone, Copy)]
+pub struct Fault {
+    pub kind: FaultKind,
+    pub instruction_pointer: VirtualAddress,
+}
+
+impl Fault {
+    fn new(kind: FaultKind, frame: &InterruptStackFrame) -> Self {
+        Self {
+            kind,
+            instruction_pointer: frame.instruction_pointer,
+        }
+    }
 }
 
 static INT_HANDLERS: PanicOnce<InterruptHandlers> = PanicOnce::new();
@@ -74,15 +158,39 @@ extern "x86-interrupt" fn double_fault_handler(frame: InterruptStackFrame, error
     panic!("Double fault: {error_code} {frame:?}")
 }
 
+extern "x86-interrupt" fn breakpoint_handler(frame: InterruptStackFrame) {
+    crate::debug_println!("Breakpoint hit {frame:?}");
+}
+
+/// Hand a fault to the kernel, which terminates the faulting service, and panic when it happened in kernel context.
+fn unresolved_fault(fault: Fault, frame: &InterruptStackFrame) -> ! {
+    (INT_HANDLERS.fault)(fault);
+
+    panic!("{:?} in kernel context {frame:?}", fault.kind)
+}
+
+extern "x86-interrupt" fn divide_error_handler(frame: InterruptStackFrame) {
+    unresolved_fault(Fault::new(FaultKind::DivideError, &frame), &frame)
+}
+
+extern "x86-interrupt" fn invalid_opcode_handler(frame: InterruptStackFrame) {
+    unresolved_fault(Fault::new(FaultKind::InvalidOpcode, &frame), &frame)
+}
+
+extern "x86-interrupt" fn simd_floating_point_handler(frame: InterruptStackFrame) {
+    unresolved_fault(Fault::new(FaultKind::SimdFloatingPoint, &frame), &frame)
+}
+
 extern "x86-interrupt" fn general_protection_fault_handler(
     frame: InterruptStackFrame,
     error_code: u64,
 ) {
-    panic!("General protection fault interrupt {error_code} {frame:?}")
+    let kind = FaultKind::GeneralProtection { error_code };
+    unresolved_fault(Fault::new(kind, &frame), &frame)
 }
 
 extern "x86-interrupt" fn page_fault_handler(
-    _frame: InterruptStackFrame,
+    frame: InterruptStackFrame,
     error_code: PageFaultErrorCode,
 ) {
     let addr: u64;
@@ -93,72 +201,114 @@ extern "x86-interrupt" fn page_fault_handler(
 
     let addr = VirtualAddress::from(addr);
 
-    panic!("Page fault interrupt at {addr:?} because {error_code:?}")
+    if (INT_HANDLERS.page_fault)(addr, error_code) {
+        return;
+    }
+
+    let kind = FaultKind::PageFault { addr, error_code };
+    unresolved_fault(Fault::new(kind, &frame), &frame)
 }
 
 #[no_mangle]
 unsafe extern "C" fn tick_inner(ctx: *const InterruptedContext) -> *const InterruptedContext {
     let next_ctx = (INT_HANDLERS.tick)((*ctx).clone());
 
-    PIC_CHAIN
-        .lock()
-        .end_of_interrupt(PIC_CHAIN_TICK_INT_INDEX as u8);
+    end_of_interrupt(TICK_INT_INDEX as u8);
+
+    // only the bootstrap processor receives the timer interrupt.
+    if online_cpus() > 1 {
+        LOCAL_APIC.send_ipi(
+            IpiDestination::AllExcludingSelf,
+            IpiKind::Fixed(RESCHEDULE_INT_INDEX as u8),
+        );
+    }
 
     next_ctx
 }
 
-#[naked]
-extern "x86-interrupt" fn tick(_frame: InterruptStackFrame) {
-    unsafe {
-        asm!(
-            "push rax",
-            "push rbx",
-            "push rcx",
-            "push rdx",
-            "push rdi",
-            "push rsi",
-            "push rbp",
-            "push r8",
-            "push r9",
-            "push r10",
-            "push r11",
-            "push r12",
-            "push r13",
-            "push r14",
-            "push r15",
-            //
-            "mov rdi, rsp",
-            "call {handler}",
-            "cmp rax, 0",
-            "je 2f",
-            "mov rsp, rax",
-            "2:",
-            //
-            "pop r15",
-            "pop r14",
-            "pop r13",
-
-            "pop r12",
-            "pop r11",
-            "pop r10",
-            "pop r9",
-
-            "pop r8",
-            "pop rbp",
-            "pop rsi",
-            "pop rdi",
-
-            "pop rdx",
-            "pop rcx",
-            "pop rbx",
-            "pop rax",
-            "iretq",
-            handler = sym tick_inner,
-            options(noreturn)
-        )
-    }
+#[no_mangle]
+unsafe extern "C" fn reschedule_inner(ctx: *const InterruptedContext) -> *const InterruptedContext {
+    let next_ctx = (INT_HANDLERS.preempt)((*ctx).clone());
+
+    LOCAL_APIC.end_of_interrupt();
+
+    next_ctx
+}
+
+#[no_mangle]
+unsafe extern "C" fn yield_inner(ctx: *const InterruptedContext) -> *const InterruptedContext {
+    (INT_HANDLERS.yield_current)((*ctx).clone())
+}
+
+/// Define an interrupt handler that saves the interrupted context on the stack,
+/// and continues with the context returned by `$handler` (or the same context when null is returned).
+macro_rules! context_switch_handler {
+    ($name:ident, $handler:ident) => {
+        #[naked]
+        extern "x86-interrupt" fn $name(_frame: InterruptStackFrame) {
+            unsafe {
+                asm!(
+                    "push rax",
+                    "push rbx",
+                    "push rcx",
+                    "push rdx",
+                    "push rdi",
+                    "push rsi",
+                    "push rbp",
+                    "push r8",
+                    "push r9",
+                    "push r10",
+                    "push r11",
+                    "push r12",
+                    "push r13",
+                    "push r14",
+                    "push r15",
+                    //
+                    "mov rdi, rsp",
+                    "call {handler}",
+                    "cmp rax, 0",
+                    "je 2f",
+                    "mov rsp, rax",
+                    "2:",
+                    //
+                    "pop r15",
+                    "pop r14",
+                    "pop r13",
+
+                    "pop r12",
+                    "pop r11",
+                    "pop r10",
+                    "pop r9",
+
+                    "pop r8",
+                    "pop rbp",
+                    "pop rsi",
+                    "pop rdi",
+
+                    "pop rdx",
+                    "pop rcx",
+                    "pop rbx",
+                    "pop rax",
+                    "iretq",
+                    handler = sym $handler,
+                    options(noreturn)
+                )
+            }
+        }
+    };
 }
 
+context_switch_handler!(tick, tick_inner);
+context_switch_handler!(yield_current, yield_inner);
+context_switch_handler!(reschedule, reschedule_inner);
+
+extern "x86-interrupt" fn tlb_shootdown_handler(_frame: InterruptStackFrame) {
+    tlb::handle_shootdown();
+    LOCAL_APIC.end_of_interrupt();
+}
+
+extern "x86-interrupt" fn spurious_handler(_frame: InterruptStackFrame) {}
+
 fn init_idt() -> InterruptDescriptorTable {
     let kernel_segment = GDT.kernel_code;
 
@@ -168,41 +318,98 @@ fn init_idt() -> InterruptDescriptorTable {
         .set_handler(kernel_segment, double_fault_handler);
     idt.double_fault.set_stack_index(DOUBLE_FAULT_IST_INDEX);
 
+    idt.divide_error
+        .set_handler(kernel_segment, divide_error_handler);
+    idt.invalid_opcode
+        .set_handler(kernel_segment, invalid_opcode_handler);
+    idt.simd_floating_point
+        .set_handler(kernel_segment, simd_floating_point_handler);
+
     idt.general_protection_fault
         .set_handler(kernel_segment, general_protection_fault_handler);
     idt.general_protection_fault
-        .set_stack_index(DOUBLE_FAULT_IST_INDEX); // TODO
+        .set_stack_index(FAULT_IST_INDEX);
 
     idt.page_fault
         .set_handler(kernel_segment, page_fault_handler);
-    idt.page_fault.set_stack_index(DOUBLE_FAULT_IST_INDEX); // TODO
-
-    idt.breakpoint.set_handler(kernel_segment, tick);
-    idt.breakpoint.set_stack_index(DOUBLE_FAULT_IST_INDEX);
-    idt[PIC_CHAIN_TICK_INT_INDEX].set_handler(kernel_segment, tick);
-    idt[PIC_CHAIN_TICK_INT_INDEX].set_stack_index(DOUBLE_FAULT_IST_INDEX);
+    idt.page_fault.set_stack_index(FAULT_IST_INDEX);
+
+    idt.breakpoint
+        .set_handler(kernel_segment, breakpoint_handler);
+    idt.breakpoint.set_stack_index(FAULT_IST_INDEX);
+    idt[YIELD_INT_INDEX].set_handler(kernel_segment, yield_current);
+    idt[YIELD_INT_INDEX].set_stack_index(CONTEXT_SWITCH_IST_INDEX);
+    idt[TICK_INT_INDEX].set_handler(kernel_segment, tick);
+    idt[TICK_INT_INDEX].set_stack_index(CONTEXT_SWITCH_IST_INDEX);
+    idt[RESCHEDULE_INT_INDEX].set_handler(kernel_segment, reschedule);
+    idt[RESCHEDULE_INT_INDEX].set_stack_index(CONTEXT_SWITCH_IST_INDEX);
+    idt[TLB_SHOOTDOWN_INT_INDEX].set_handler(kernel_segment, tlb_shootdown_handler);
+    idt[TLB_SHOOTDOWN_INT_INDEX].set_stack_index(CONTEXT_SWITCH_IST_INDEX);
+    idt[SPURIOUS_INT_INDEX].set_handler(kernel_segment, spurious_handler);
+
+    for index in PIC_CHAIN_SPURIOUS_INT_INDEXES {
+        idt[index].set_handler(kernel_segment, spurious_handler);
+    }
 
     idt
 }
 
 pub static IDT: Singleton<InterruptDescriptorTable> = Singleton::new(init_idt);
 
+const _: () = assert!(YIELD_INT_INDEX == 0x81);
+
+/// Raise the yield interrupt, which switches to the next thread without advancing the clock.
+pub fn raise_yield_interrupt() {
+    // the vector must be an immediate, it is kept in sync with `YIELD_INT_INDEX` by the assertion above.
+    unsafe {
+        asm!("int 0x81", options(nomem, nostack));
+    }
+}
+
 /// Initialize x86_64-specific components for the kernel.
 pub fn init_x86_64(interrupt_handlers: InterruptHandlers) {
     INT_HANDLERS.initialize_with(interrupt_handlers);
+    register_cpu(0);
 
-    GDT.table.load();
+    load_gdt(&GDT);
+    IDT.load();
 
     unsafe {
-        // load GDT segments
-        GDT.kernel_code.load_into_cs();
-        GDT.kernel_data.load_into_ss();
-        GDT.kernel_data.load_into_ds();
-        GDT.tss.load_into_tss();
+        EXTENDED_STATE_FORMAT.enable();
+        enable_write_protection();
     }
 
-    IDT.load();
-
     PIC_CHAIN.lock().init();
+    PIT.lock().set_frequency(TICK_FREQUENCY);
     SERIAL.lock().init();
 }
+
+/// Initialize the x86_64-specific components of an application processor, after [`init_x86_64`] ran on the bootstrap processor.
+pub fn init_x86_64_application_processor(cpu: CpuId) {
+    let tss = AP_TSS[cpu].call_once(|| unsafe {
+        new_tss(&mut AP_INTERRUPT_STACKS[cpu], &mut AP_PRIVILEGE_STACKS[cpu])
+    });
+    let gdt = AP_GDT[cpu].call_once(|| new_gdt(tss));
+
+    load_gdt(gdt);
+    IDT.load();
+
+    unsafe {
+        EXTENDED_STATE_FORMAT.enable();
+        enable_write_protection();
+    }
+
+    LOCAL_APIC.enable(SPURIOUS_INT_INDEX as u8);
+}
+
+fn load_gdt(gdt: &'static FullGdt) {
+    gdt.table.load();
+
+    unsafe {
+        // load GDT segments
+        gdt.kernel_code.load_into_cs();
+        gdt.kernel_data.load_into_ss();
+        gdt.kernel_data.load_into_ds();
+        gdt.tss.load_into_tss();
+    }
+}
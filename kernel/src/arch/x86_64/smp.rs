@@ -0,0 +1,367 @@
+//! Starting the application processors, and telling the CPUs apart.
+//!
+//! The bootstrap processor is always CPU 0, the application processors are numbered in the order they come online.
+
+use core::arch::{asm, global_asm};
+use core::ptr::{addr_of, copy_nonoverlapping, write_volatile};
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use essentials::sync::PanicOnce;
+use x86_64::devices::local_apic::{IpiDestination, IpiKind};
+use x86_64::instructions::apic_id;
+use x86_64::msr::write_msr;
+use x86_64::paging::{PageSize, PageTableEntryFlags, PhysicalPage, VirtualPage};
+use x86_64::port::{Port, WriteOnly};
+
+use crate::arch::x86_64::devices::LOCAL_APIC;
+use crate::arch::x86_64::init::init_x86_64_application_processor;
+use crate::arch::x86_64::tlb;
+use crate::memory::{MemoryMapper, NewMappingError, TableCacheFlush, FRAME_ALLOCATOR};
+
+/// The maximum number of CPUs the kernel brings online.
+pub const MAX_CPUS: usize = 8;
+
+/// The index of a CPU, ranging from 0 to [`MAX_CPUS`].
+pub type CpuId = usize;
+
+/// The model specific register holding the base address of the GS segment.
+const GS_BASE_MSR: u32 = 0xC000_0101;
+
+/// The physical page the application processors start executing at, it has to be below 1MiB.
+const TRAMPOLINE_PAGE: u64 = 0x8000;
+
+const AP_STACK_SIZE: usize = 16 * 1024;
+
+#[repr(align(16))]
+#[allow(dead_code)] // only accessed through the trampoline
+struct ApStack([u8; AP_STACK_SIZE]);
+
+const NEW_AP_STACK: ApStack = ApStack([0; AP_STACK_SIZE]);
+
+/// The stacks used by the application processors until they switch to their first thread.
+static mut AP_STACKS: [ApStack; MAX_CPUS] = [NEW_AP_STACK; MAX_CPUS];
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_APIC_ID: AtomicU32 = AtomicU32::new(u32::MAX);
+
+/// The local APIC id of every CPU, indexed by its [`CpuId`].
+static CPU_APIC_IDS: [AtomicU32; MAX_CPUS] = [NO_APIC_ID; MAX_CPUS];
+
+static ONLINE_CPUS: AtomicUsize = AtomicUsize::new(1);
+
+/// The data of a CPU that the CPU finds through its GS base, see [`current_cpu`].
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct CpuLocal {
+    id: CpuId,
+}
+
+static CPU_LOCALS: [CpuLocal; MAX_CPUS] = {
+    let mut locals = [CpuLocal { id: 0 }; MAX_CPUS];
+    let mut cpu = 0;
+
+    while cpu < MAX_CPUS {
+        locals[cpu].id = cpu;
+        cpu += 1;
+    }
+
+    locals
+};
+
+static AP_ENTRY: PanicOnce<fn(CpuId) -> !> = PanicOnce::new();
+
+#[derive(Debug, Clone, Copy)]
+pub enum StartProcessorsError {
+    /// The trampoline page can be handed out by the frame allocator, so it cannot be overwritten.
+    TrampolineInUse,
+    Mapping(NewMappingError),
+}
+
+impl From<NewMappingError> for StartProcessorsError {
+    fn from(value: NewMappingError) -> Self {
+        Self::Mapping(value)
+    }
+}
+
+/// The CPU executing this code, it must have been registered with [`register_cpu`].
+pub fn current_cpu() -> CpuId {
+    let cpu: CpuId;
+
+    // Safety: the GS base points to the `CpuLocal` of the executing CPU, nothing loads GS after it is registered.
+    unsafe {
+        asm!(
+            "mov {}, gs:[0]",
+            out(reg) cpu,
+            options(nostack, readonly, preserves_flags)
+        );
+    }
+
+    cpu
+}
+
+/// The number of CPUs that are up and running.
+pub fn online_cpus() -> usize {
+    ONLINE_CPUS.load(Ordering::Acquire)
+}
+
+/// The local APIC id of `cpu`, to send it interrupts.
+pub fn apic_id_of(cpu: CpuId) -> u8 {
+    CPU_APIC_IDS[cpu].load(Ordering::Relaxed) as u8
+}
+
+/// Register the executing CPU under `cpu`, and point its GS base to its [`CpuLocal`].
+pub fn register_cpu(cpu: CpuId) {
+    let apic_id = apic_id() as u32;
+
+    if let Some(other) = CPU_APIC_IDS
+        .iter()
+        .position(|id| id.load(Ordering::Relaxed) == apic_id)
+    {
+        panic!("APIC id {apic_id} is already registered for CPU {other}");
+    }
+
+    CPU_APIC_IDS[cpu].store(apic_id, Ordering::Relaxed);
+
+    unsafe {
+        write_msr(GS_BASE_MSR, &CPU_LOCALS[cpu] as *const CpuLocal as u64);
+    }
+
+    tlb::register_active_table();
+}
+
+/// Start all application processors, each of them continues in `entry` once it is initialized.
+///
+/// The APICs must be initialized, and `processors` is the number of processors the firmware reported, including this one.
+/// The processors are started with the active memory map, which must be the kernel's root map.
+/// Returns the number of application processors that came online.
+pub fn start_application_processors(
+    mapper: &mut MemoryMapper,
+    entry: fn(CpuId) -> !,
+    processors: usize,
+) -> Result<usize, StartProcessorsError> {
+    let trampoline_page = PhysicalPage::new(TRAMPOLINE_PAGE.into(), PageSize::Size4Kib);
+
+    if FRAME_ALLOCATOR.is_usable(trampoline_page) {
+        return Err(StartProcessorsError::TrampolineInUse);
+    }
+
+    // the trampoline keeps executing from the same address after it enables paging.
+    identity_map(mapper, trampoline_page)?;
+
+    AP_ENTRY.initialize_with(entry);
+
+    unsafe {
+        write_trampoline();
+    }
+
+    LOCAL_APIC.send_ipi(IpiDestination::AllExcludingSelf, IpiKind::Init);
+    delay_microseconds(10_000);
+
+    for _ in 0..2 {
+        LOCAL_APIC.send_ipi(
+            IpiDestination::AllExcludingSelf,
+            IpiKind::Startup((TRAMPOLINE_PAGE / 0x1000) as u8),
+        );
+        delay_microseconds(200);
+    }
+
+    // give the processors some time to come online, a processor that never does is left alone.
+    let expected = processors.min(MAX_CPUS);
+
+    for _ in 0..100 {
+        if online_cpus() >= expected {
+            break;
+        }
+
+        delay_microseconds(1_000);
+    }
+
+    Ok(online_cpus() - 1)
+}
+
+fn identity_map(mapper: &mut MemoryMapper, page: PhysicalPage) -> Result<(), NewMappingError> {
+    let virtual_page = VirtualPage::new(page.addr().as_u64().into(), page.size());
+
+    if mapper.translate_virtual_to_physical(virtual_page.addr()) == Some(page.addr()) {
+        return Ok(());
+    }
+
+    let mut flags = PageTableEntryFlags::default();
+    flags.set_present(true);
+    flags.set_writable(true);
+
+    unsafe { mapper.map_to(flags, flags, virtual_page, page.addr()) }?.flush();
+
+    Ok(())
+}
+
+/// Copy the trampoline to its page, and fill in the values it needs to reach [`application_processor_start`].
+///
+/// # Safety
+///
+/// The trampoline page must be identity mapped and unused.
+unsafe fn write_trampoline() {
+    let start = &ap_trampoline_start as *const u8;
+    let length = &ap_trampoline_end as *const u8 as usize - start as usize;
+    let target = TRAMPOLINE_PAGE as *mut u8;
+
+    copy_nonoverlapping(start, target, length);
+
+    let field = |symbol: &u8| target.add(symbol as *const u8 as usize - start as usize);
+
+    let cr3 = PhysicalPage::active().0.addr().as_u64();
+    assert!(
+        cr3 < u32::MAX as u64,
+        "The trampoline can only load a 32-bit cr3"
+    );
+
+    write_volatile(field(&ap_cr3) as *mut u64, cr3);
+    write_volatile(
+        field(&ap_entry) as *mut u64,
+        application_processor_start as *const () as u64,
+    );
+    write_volatile(
+        field(&ap_stacks_base) as *mut u64,
+        addr_of!(AP_STACKS) as u64,
+    );
+    write_volatile(field(&ap_stack_size) as *mut u64, AP_STACK_SIZE as u64);
+    write_volatile(field(&ap_next_cpu) as *mut u32, 1);
+    write_volatile(field(&ap_max_cpus) as *mut u32, MAX_CPUS as u32);
+}
+
+/// Wait for roughly `microseconds`, a write to port 0x80 takes about a microsecond.
+fn delay_microseconds(microseconds: usize) {
+    let mut port = unsafe { Port::<u8, WriteOnly>::write_only(0x80) };
+
+    for _ in 0..microseconds {
+        unsafe { port.write(0) }
+    }
+}
+
+/// The first Rust code executed by an application processor, on the stack set up by the trampoline.
+extern "C" fn application_processor_start(cpu: u32) -> ! {
+    let cpu = cpu as CpuId;
+
+    register_cpu(cpu);
+    init_x86_64_application_processor(cpu);
+    ONLINE_CPUS.fetch_add(1, Ordering::AcqRel);
+
+    (*AP_ENTRY)(cpu)
+}
+
+extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_end: u8;
+    static ap_cr3: u8;
+    static ap_entry: u8;
+    static ap_stacks_base: u8;
+    static ap_stack_size: u8;
+    static ap_next_cpu: u8;
+    static ap_max_cpus: u8;
+}
+
+// The application processors start in real mode at the trampoline page.
+// The trampoline switches to long mode with a temporary GDT and the kernel's page table,
+// takes the next cpu id, and calls `ap_entry` with it on its own stack.
+// Addresses are computed relative to the trampoline page, since this code is copied there before it runs.
+global_asm!(
+    r#"
+    .section .text
+    .global ap_trampoline_start
+    .global ap_trampoline_end
+    .global ap_cr3
+    .global ap_entry
+    .global ap_stacks_base
+    .global ap_stack_size
+    .global ap_next_cpu
+    .global ap_max_cpus
+
+    .set TRAMPOLINE, 0x8000
+    .code16
+ap_trampoline_start:
+    cli
+    cld
+    xor %ax, %ax
+    mov %ax, %ds
+    lgdtl (ap_gdt_pointer - ap_trampoline_start + TRAMPOLINE)
+
+    mov %cr0, %eax
+    or $1, %eax
+    mov %eax, %cr0
+    ljmpl $0x08, $(ap_protected_mode - ap_trampoline_start + TRAMPOLINE)
+
+    .code32
+ap_protected_mode:
+    mov $0x10, %ax
+    mov %ax, %ds
+    mov %ax, %es
+    mov %ax, %ss
+
+    // enable physical address extension
+    mov %cr4, %eax
+    or $(1 << 5), %eax
+    mov %eax, %cr4
+
+    mov (ap_cr3 - ap_trampoline_start + TRAMPOLINE), %eax
+    mov %eax, %cr3
+
+    // enable long mode and no-execute in the EFER
+    mov $0xC0000080, %ecx
+    rdmsr
+    or $((1 << 8) | (1 << 11)), %eax
+    wrmsr
+
+    // enable paging and write protection
+    mov %cr0, %eax
+    or $((1 << 31) | (1 << 16)), %eax
+    mov %eax, %cr0
+    ljmpl $0x18, $(ap_long_mode - ap_trampoline_start + TRAMPOLINE)
+
+    .code64
+ap_long_mode:
+    mov $1, %eax
+    lock xaddl %eax, (ap_next_cpu - ap_trampoline_start + TRAMPOLINE)
+    cmpl (ap_max_cpus - ap_trampoline_start + TRAMPOLINE), %eax
+    jae ap_halt
+
+    mov %rax, %rbx
+    inc %rbx
+    imul (ap_stack_size - ap_trampoline_start + TRAMPOLINE), %rbx
+    add (ap_stacks_base - ap_trampoline_start + TRAMPOLINE), %rbx
+    mov %rbx, %rsp
+    xor %rbp, %rbp
+
+    mov %eax, %edi
+    call *(ap_entry - ap_trampoline_start + TRAMPOLINE)
+
+ap_halt:
+    cli
+    hlt
+    jmp ap_halt
+
+    .align 8
+ap_gdt:
+    .quad 0
+    .quad 0x00cf9a000000ffff
+    .quad 0x00cf92000000ffff
+    .quad 0x00af9a000000ffff
+ap_gdt_pointer:
+    .word ap_gdt_pointer - ap_gdt - 1
+    .long ap_gdt - ap_trampoline_start + TRAMPOLINE
+
+    .align 8
+ap_cr3:
+    .quad 0
+ap_entry:
+    .quad 0
+ap_stacks_base:
+    .quad 0
+ap_stack_size:
+    .quad 0
+ap_next_cpu:
+    .long 0
+ap_max_cpus:
+    .long 0
+ap_trampoline_end:
+"#,
+    options(att_syntax)
+);
@@ -0,0 +1,182 @@
+//! Keeping the translation caches of all CPUs in sync with the memory maps.
+//!
+//! Every CPU only flushes its own TLB, so when a mapping is removed or restricted while other CPUs have the memory map active,
+//! they are sent a shootdown interrupt and reload their memory map.
+//! The frames that were reachable through the old mappings must not be reused before every targeted CPU did so, see [`Shootdown`].
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use essentials::address::PhysicalAddress;
+use x86_64::devices::local_apic::{IpiDestination, IpiKind};
+use x86_64::paging::{PageSize, PhysicalPage};
+
+use crate::arch::x86_64::devices::{LOCAL_APIC, TLB_SHOOTDOWN_INT_INDEX};
+use crate::arch::x86_64::smp::{apic_id_of, current_cpu, online_cpus, CpuId, MAX_CPUS};
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicU64 = AtomicU64::new(0);
+
+/// The physical address of the l4 table each CPU has active, `0` before it recorded one.
+static ACTIVE_TABLES: [AtomicU64; MAX_CPUS] = [ZERO; MAX_CPUS];
+
+/// The physical address of the kernel's root l4 table, which the CPUs start with.
+static KERNEL_TABLE: AtomicU64 = AtomicU64::new(0);
+
+/// The number of shootdowns requested from each CPU.
+static REQUESTED: [AtomicU64; MAX_CPUS] = [ZERO; MAX_CPUS];
+
+/// The number of requested shootdowns each CPU has handled, its TLB was reloaded after those were requested.
+static COMPLETED: [AtomicU64; MAX_CPUS] = [ZERO; MAX_CPUS];
+
+/// A set of CPUs.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CpuSet(u64);
+
+impl CpuSet {
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    pub fn insert(&mut self, cpu: CpuId) {
+        self.0 |= 1 << cpu;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = CpuId> {
+        let set = self.0;
+        (0..MAX_CPUS).filter(move |cpu| set & (1 << cpu) != 0)
+    }
+}
+
+/// Make `table` the memory map of the executing CPU.
+///
+/// Loading a memory map flushes the TLB, so it also completes the shootdowns requested from this CPU so far.
+///
+/// # Safety
+///
+/// The table must be a valid l4 table which maps the executing code and the kernel.
+pub unsafe fn activate(table: PhysicalPage) {
+    let cpu = current_cpu();
+
+    // the table is recorded before it is loaded, so a CPU changing a mapping after this either targets this CPU,
+    // or the change is visible once the table is loaded.
+    ACTIVE_TABLES[cpu].store(table.addr().as_u64(), Ordering::SeqCst);
+    let requested = REQUESTED[cpu].load(Ordering::SeqCst);
+
+    table.make_active();
+
+    COMPLETED[cpu].fetch_max(requested, Ordering::SeqCst);
+}
+
+/// Make the kernel's root map the memory map of the executing CPU, e.g. before it runs a thread without a service.
+///
+/// This keeps the CPU from holding on to the memory map of a service it no longer runs, which could not be freed otherwise.
+pub fn activate_kernel_table() {
+    let cpu = current_cpu();
+    let table = KERNEL_TABLE.load(Ordering::SeqCst);
+
+    if table == 0 || ACTIVE_TABLES[cpu].load(Ordering::SeqCst) == table {
+        return;
+    }
+
+    let table = PhysicalPage::new(PhysicalAddress::new(table as usize), PageSize::Size4Kib);
+
+    // Safety: the root map maps the kernel, and every CPU started with it.
+    unsafe { activate(table) };
+}
+
+/// The other CPUs which have `table` active, and may cache translations of it.
+pub fn cpus_using(table: PhysicalPage) -> CpuSet {
+    let current = current_cpu();
+    let mut cpus = CpuSet::empty();
+
+    for (cpu, active) in ACTIVE_TABLES.iter().enumerate().take(online_cpus()) {
+        if cpu != current && active.load(Ordering::SeqCst) == table.addr().as_u64() {
+            cpus.insert(cpu);
+        }
+    }
+
+    cpus
+}
+
+/// All online CPUs except the executing one, e.g. for mappings shared by every memory map.
+pub fn other_cpus() -> CpuSet {
+    let current = current_cpu();
+    let mut cpus = CpuSet::empty();
+
+    for cpu in (0..online_cpus()).filter(|cpu| *cpu != current) {
+        cpus.insert(cpu);
+    }
+
+    cpus
+}
+
+/// A shootdown sent to a set of CPUs, it completes once all of them reloaded their memory map.
+#[derive(Debug, Clone, Copy)]
+pub struct Shootdown {
+    /// The shootdown each CPU has to complete, `0` for CPUs that are not targeted.
+    tickets: [u64; MAX_CPUS],
+}
+
+impl Shootdown {
+    /// Whether every targeted CPU flushed its TLB since the shootdown was sent.
+    pub fn is_complete(&self) -> bool {
+        self.tickets
+            .iter()
+            .enumerate()
+            .all(|(cpu, ticket)| COMPLETED[cpu].load(Ordering::SeqCst) >= *ticket)
+    }
+
+    /// Spin until every targeted CPU flushed its TLB.
+    ///
+    /// The targeted CPUs only flush once they accept interrupts, so no lock they could be waiting on may be held.
+    pub fn wait(&self) {
+        while !self.is_complete() {
+            // another CPU could wait for this one at the same time.
+            handle_shootdown();
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// Ask the `cpus` to flush their TLB, without waiting for them.
+pub fn shoot_down(cpus: CpuSet) -> Shootdown {
+    let mut tickets = [0; MAX_CPUS];
+
+    for cpu in cpus.iter() {
+        tickets[cpu] = REQUESTED[cpu].fetch_add(1, Ordering::SeqCst) + 1;
+
+        LOCAL_APIC.send_ipi(
+            IpiDestination::Apic(apic_id_of(cpu)),
+            IpiKind::Fixed(TLB_SHOOTDOWN_INT_INDEX as u8),
+        );
+    }
+
+    Shootdown { tickets }
+}
+
+/// Complete the shootdowns requested from the executing CPU, by reloading its memory map.
+pub fn handle_shootdown() {
+    let cpu = current_cpu();
+    let requested = REQUESTED[cpu].load(Ordering::SeqCst);
+
+    if COMPLETED[cpu].load(Ordering::SeqCst) >= requested {
+        return;
+    }
+
+    let (active, _) = PhysicalPage::active();
+
+    // Safety: reloading the active table does not change any mapping.
+    unsafe { active.make_active() };
+
+    COMPLETED[cpu].fetch_max(requested, Ordering::SeqCst);
+}
+
+/// Record the memory map the executing CPU started with, which is the kernel's root map.
+pub fn register_active_table() {
+    let (active, _) = PhysicalPage::active();
+    ACTIVE_TABLES[current_cpu()].store(active.addr().as_u64(), Ordering::SeqCst);
+    KERNEL_TABLE.store(active.addr().as_u64(), Ordering::SeqCst);
+}
@@ -3,7 +3,6 @@ use core::panic::PanicInfo;
 use bootloader::BootInfo;
 use essentials::address::VirtualAddress;
 use essentials::display::ReadableSize;
-use essentials::sync::{PanicOnce, SpinMutex};
 use x86_64::constants::MIN_STACK_SIZE;
 use x86_64::instructions::halt_loop;
 use x86_64::interrupts::atomic_block;
@@ -11,14 +10,16 @@ use x86_64::paging::PhysicalPage;
 use x86_64::syscalls::init_syscalls;
 use x86_64::ARCH_NAME;
 
+use crate::arch::x86_64::apic::init_apic;
 use crate::arch::x86_64::init::GDT;
 use crate::arch::x86_64::init_x86_64;
+use crate::arch::x86_64::smp::{start_application_processors, CpuId};
 use crate::debug::DEBUG_CHANNEL;
 use crate::interface::abi::setup_abi_page;
 use crate::interface::interrupts::INTERRUPT_HANDLERS;
 use crate::interface::syscalls::{handle_kernel_syscall_raw, handle_user_syscall_raw};
-use crate::memory::heap::{map_heap, HEAP_SIZE};
-use crate::memory::{MemoryMapper, FRAME_ALLOCATOR};
+use crate::memory::heap::{map_heap, INITIAL_HEAP_SIZE};
+use crate::memory::{MemoryMapper, FRAME_ALLOCATOR, ROOT_MAPPER};
 use crate::multi_tasking::scheduler::{Thread, ThreadStack, SCHEDULER};
 use crate::service::SERVICE_TABLE;
 
@@ -28,14 +29,14 @@ pub fn handle_panic(info: &PanicInfo) -> ! {
     halt_loop()
 }
 
-static ROOT_MAPPER: SpinMutex<PanicOnce<MemoryMapper>> = SpinMutex::new(PanicOnce::new());
-
-/// The kernel entry point.
-pub fn kernel_main(boot_info: &'static BootInfo) -> ! {
+/// Set up the executing CPU, the frame allocator, the root memory map and the kernel heap.
+///
+/// This is what the kernel needs before its first thread is added, the unit tests start from here as well.
+pub fn init_kernel(boot_info: &'static BootInfo) {
     init_x86_64(INTERRUPT_HANDLERS);
 
-    let memory_mapper = unsafe {
-        FRAME_ALLOCATOR.init(&boot_info.memory_map);
+    let mut memory_mapper = unsafe {
+        FRAME_ALLOCATOR.init(&boot_info.memory_map, boot_info.physical_memory_offset);
         MemoryMapper::new(
             &FRAME_ALLOCATOR,
             PhysicalPage::active().0,
@@ -43,11 +44,23 @@ pub fn kernel_main(boot_info: &'static BootInfo) -> ! {
         )
     };
 
+    debug_println!(
+        "Initializing kernel heap with {} of memory",
+        ReadableSize::new(INITIAL_HEAP_SIZE)
+    );
+
+    map_heap(&mut memory_mapper).expect("Failed to map the kernel heap");
+
     ROOT_MAPPER.lock().initialize_with(memory_mapper);
+}
+
+/// The kernel entry point.
+pub fn kernel_main(boot_info: &'static BootInfo) -> ! {
+    init_kernel(boot_info);
 
     SCHEDULER.add_thread(unsafe {
         Thread::start_new(
-            Some("Kernel Main/Idle Thread"),
+            Some("Kernel Main Thread"),
             ThreadStack::from_slice(&mut KERNEL_MAIN_STACK),
             VirtualAddress::from(main_kernel_thread as *const fn()),
             None,
@@ -64,21 +77,14 @@ static mut KERNEL_MAIN_STACK: [u8; MIN_STACK_SIZE] = [0; MIN_STACK_SIZE];
 ///
 /// Execution is given to this code asap in the init process.
 /// This is preferred because `multi_tasking::sync` primitives are allowed only from a scheduled thread.
-/// This thread also acts as the idle task.
 fn main_kernel_thread() -> ! {
-    let mut mapper = ROOT_MAPPER.lock();
-
     atomic_block(|| {
         debug_println!("Starting the Serva Operating System...");
         debug_println!("Architecture: {}", ARCH_NAME);
         debug_println!("Debug channel: {}", DEBUG_CHANNEL);
 
-        debug_println!(
-            "Initializing kernel heap with {} of memory",
-            ReadableSize::new(HEAP_SIZE)
-        );
-
-        map_heap(&mut mapper).expect("Failed to map the kernel heap");
+        // the root mapper is only held while the kernel map is changed, the steps after it allocate and can sleep.
+        let mut mapper = ROOT_MAPPER.lock();
 
         unsafe {
             init_syscalls(handle_user_syscall_raw, GDT.syscall, GDT.sysret);
@@ -86,6 +92,22 @@ fn main_kernel_thread() -> ! {
                 .expect("Failed to initialize ABI page");
         }
 
+        match init_apic(&mut mapper) {
+            Ok(processors) => {
+                match start_application_processors(
+                    &mut mapper,
+                    application_processor_main,
+                    processors,
+                ) {
+                    Ok(count) => debug_println!("Started {count} application processors"),
+                    Err(err) => {
+                        debug_println!("Failed to start the application processors: {err:?}")
+                    }
+                }
+            }
+            Err(err) => debug_println!("Falling back to the 8259 PIC: {err:?}"),
+        }
+
         debug_println!("{:#?}", FRAME_ALLOCATOR.info());
 
         // from this point on the kernel map is shared and cannot be changed.
@@ -95,13 +117,22 @@ fn main_kernel_thread() -> ! {
                 .expect("Failed to inherit root memory map"),
         );
 
+        drop(mapper);
+
         test_service::setup_test_service();
     });
 
-    SCHEDULER.yield_current();
+    SCHEDULER.exit_current()
+}
 
-    // make sure there is always nothing to do.
-    halt_loop()
+/// The entry point of the application processors, once they are initialized.
+fn application_processor_main(_cpu: CpuId) -> ! {
+    unsafe {
+        init_syscalls(handle_user_syscall_raw, GDT.syscall, GDT.sysret);
+    }
+
+    SCHEDULER.yield_current();
+    unreachable!()
 }
 
 mod test_service {
@@ -204,7 +235,7 @@ mod test_service {
         loop {
             debug_println!("Requesting to {endpoint_name:?}");
 
-            unsafe { syscall::request(connection, &endpoint_name) }.unwrap();
+            unsafe { syscall::request(connection, &endpoint_name, false, None) }.unwrap();
 
             debug_println!("Request open");
 
@@ -212,13 +243,13 @@ mod test_service {
 
             for _ in 0..10 {
                 debug_println!("Writing {} bytes", buffer.len());
-                unsafe { syscall::write(connection, &buffer, false) }.unwrap();
+                unsafe { syscall::write(connection, &buffer, false, false, None) }.unwrap();
                 halt()
             }
 
             debug_println!("Finishing request");
 
-            unsafe { syscall::write(connection, &[], true) }.unwrap();
+            unsafe { syscall::write(connection, &[], true, false, None) }.unwrap();
 
             debug_println!("Request finished");
 
@@ -227,7 +258,7 @@ mod test_service {
     }
 
     fn test_dep_service_start() -> ! {
-        while let Some((connection, endpoint)) = unsafe { syscall::accept() } {
+        while let Ok(Some((connection, endpoint))) = unsafe { syscall::accept(false, None) } {
             debug_println!("Request accepted with connection {connection} for endpoint {endpoint}");
 
             let mut buffer = [0u8; 50];
@@ -235,7 +266,8 @@ mod test_service {
             loop {
                 debug_println!("Reading data");
 
-                let bytes_read = unsafe { syscall::read(connection, &mut buffer) }.unwrap();
+                let bytes_read =
+                    unsafe { syscall::read(connection, &mut buffer, false, None) }.unwrap();
 
                 debug_println!(
                     "Read {bytes_read} bytes: {:?}",
@@ -3,13 +3,20 @@ use essentials::address::VirtualAddress;
 use x86_64::paging::{PageSize, PageTableEntryFlags, VirtualPage};
 use x86_64::syscalls::SyscallArgs;
 
-use crate::memory::{MemoryMapper, NewMappingError, TableCacheFlush};
+use crate::memory::{MemoryMapper, NewMappingError, RegionKind, TableCacheFlush};
 
 // TODO: is there a better way of putting a function at a known location?
 
 static mut SYSCALL_HANDLER: MaybeUninit<fn(SyscallArgs) -> u64> = MaybeUninit::uninit();
 
-extern "C" fn syscall_handler(syscall: u64, arg0: u64, arg1: u64, arg2: u64, arg3: u64) -> u64 {
+extern "C" fn syscall_handler(
+    syscall: u64,
+    arg0: u64,
+    arg1: u64,
+    arg2: u64,
+    arg3: u64,
+    arg4: u64,
+) -> u64 {
     unsafe {
         (SYSCALL_HANDLER.assume_init_ref())(SyscallArgs {
             syscall,
@@ -17,6 +24,7 @@ extern "C" fn syscall_handler(syscall: u64, arg0: u64, arg1: u64, arg2: u64, arg
             arg1,
             arg2,
             arg3,
+            arg4,
         })
     }
 }
@@ -35,9 +43,9 @@ pub unsafe fn setup_abi_page(
     creation_flags.set_writable(true);
     creation_flags.set_present(true);
 
-    memory_mapper
-        .new_map(creation_flags, creation_flags, abi_page)?
-        .flush();
+    let (_, cache_flush) =
+        memory_mapper.map_region(Some(abi_page.addr()), 1, RegionKind::Abi, creation_flags)?;
+    cache_flush.flush();
 
     let fn_ptr = abi_page.addr().as_mut_ptr::<extern "C" fn(
         syscall: u64,
@@ -45,6 +53,7 @@ pub unsafe fn setup_abi_page(
         arg1: u64,
         arg2: u64,
         arg3: u64,
+        arg4: u64,
     ) -> u64>();
 
     *fn_ptr = syscall_handler;
@@ -1,15 +1,82 @@
-use crate::arch::x86_64::init::InterruptHandlers;
+use crate::arch::x86_64::init::{Fault, InterruptHandlers};
+use crate::arch::x86_64::tlb;
 use crate::multi_tasking::scheduler::SCHEDULER;
+use crate::service::{ServiceRef, SERVICE_TABLE};
+use essentials::address::VirtualAddress;
 use x86_64::interrupts::context::InterruptedContext;
+use x86_64::interrupts::PageFaultErrorCode;
+
+/// Activate the memory map of the service of the next thread, threads without a service run in the kernel's root map.
+fn switch_memory_map(service: Option<ServiceRef>) {
+    match service {
+        Some(service) => service.set_memory_map_active(),
+        None => tlb::activate_kernel_table(),
+    }
+}
 
 fn tick(ctx: InterruptedContext) -> *const InterruptedContext {
     let (ctx, service) = SCHEDULER.tick(ctx);
 
-    if let Some(service) = service {
-        service.set_memory_map_active();
-    }
+    switch_memory_map(service);
+    ctx
+}
 
+fn yield_current(ctx: InterruptedContext) -> *const InterruptedContext {
+    let (ctx, service) = SCHEDULER.reschedule(ctx);
+
+    switch_memory_map(service);
+    ctx
+}
+
+fn preempt(ctx: InterruptedContext) -> *const InterruptedContext {
+    let (ctx, service) = SCHEDULER.preempt(ctx);
+
+    switch_memory_map(service);
     ctx
 }
 
-pub const INTERRUPT_HANDLERS: InterruptHandlers = InterruptHandlers { tick };
+/// Back lazily reserved pages of the current service on their first access, and copy shared pages on their first write.
+fn page_fault(addr: VirtualAddress, error_code: PageFaultErrorCode) -> bool {
+    let Some(service) = SCHEDULER.current_service() else {
+        return false;
+    };
+
+    // kernel code faulting while it holds the services would deadlock on them, leave the fault to panic instead.
+    if SERVICE_TABLE.is_locked_by_current_cpu() {
+        return false;
+    }
+
+    match (
+        error_code.protection_violation(),
+        error_code.caused_by_write(),
+    ) {
+        (false, _) => service.populate(addr),
+        (true, true) => service.copy_on_write(addr),
+        (true, false) => false,
+    }
+}
+
+/// Terminate the current service when the fault happened in its own code, faults in kernel context return to panic.
+fn fault(fault: Fault) {
+    let Some(service) = SCHEDULER.current_service() else {
+        return;
+    };
+
+    // syscalls only touch service memory after checking it, so a fault in one is a kernel bug.
+    if SCHEDULER.in_syscall() {
+        return;
+    }
+
+    debug_println!("Terminating service {} after {fault:?}", service.id());
+
+    service.terminate(fault);
+    SCHEDULER.exit_current()
+}
+
+pub const INTERRUPT_HANDLERS: InterruptHandlers = InterruptHandlers {
+    tick,
+    yield_current,
+    preempt,
+    page_fault,
+    fault,
+};
@@ -1,22 +1,42 @@
-use syscall::{encode_syscall_result, SyscallError, SyscallResult};
+use alloc::string::String;
+use alloc::vec;
+use core::arch::asm;
+use essentials::address::VirtualAddress;
+use syscall::{decode_deadline, encode_syscall_result, SyscallError, SyscallResult};
 use x86_64::interrupts::atomic_block;
+use x86_64::paging::PageSize;
 use x86_64::syscalls::SyscallArgs;
 
-use crate::multi_tasking::scheduler::SCHEDULER;
-use crate::service::ServiceRef;
+use crate::multi_tasking::scheduler::{Tick, SCHEDULER};
+use crate::service::{ServiceRef, UserBuffer, SERVICE_TABLE};
 
 mod accept;
 mod connect;
+mod create_shared_memory;
 mod disconnect;
+mod grant_shared_memory;
 mod hello;
+mod introspect;
+mod map_anonymous;
+mod map_shared_memory;
+mod poll;
 mod read;
 mod request;
+mod service_stats;
+mod set_priority;
 mod stat_endpoint;
+mod unmap_anonymous;
+mod unmap_shared_memory;
+mod uptime;
 mod write;
+mod yield_now;
+
+/// The stack a syscall may use below the stack pointer of the calling thread, backed before the syscall runs.
+const SYSCALL_STACK_SIZE: usize = 32 * 1024;
 
 pub type SyscallHandler = fn(&SyscallArgs, ServiceRef) -> SyscallResult;
 
-static USER_SYSCALL_TABLE: [SyscallHandler; 7] = [
+static USER_SYSCALL_TABLE: [SyscallHandler; 19] = [
     hello::hello_syscall,
     connect::connect_syscall,
     request::request_syscall,
@@ -24,12 +44,73 @@ static USER_SYSCALL_TABLE: [SyscallHandler; 7] = [
     read::read_syscall,
     accept::accept_syscall,
     stat_endpoint::stat_endpoint_syscall,
+    uptime::uptime_syscall,
+    poll::poll_syscall,
+    set_priority::set_priority_syscall,
+    yield_now::yield_syscall,
+    service_stats::service_stats_syscall,
+    introspect::introspect_syscall,
+    map_anonymous::map_anonymous_syscall,
+    unmap_anonymous::unmap_anonymous_syscall,
+    create_shared_memory::create_shared_memory_syscall,
+    grant_shared_memory::grant_shared_memory_syscall,
+    map_shared_memory::map_shared_memory_syscall,
+    unmap_shared_memory::unmap_shared_memory_syscall,
 ];
 
 static KERNEL_SYSCALL_TABLE: [SyscallHandler; 0] = [];
 
 const KERNEL_CALLS_START: usize = 1024;
 
+/// The longest spec or endpoint name a syscall copies in.
+const MAX_NAME_LEN: usize = 256;
+
+/// Decode a deadline argument (see [`syscall::encode_deadline`]) into the tick at which it expires.
+fn deadline_arg(value: u64) -> Option<Tick> {
+    decode_deadline(value).map(|uptime| SCHEDULER.tick_at_uptime(uptime))
+}
+
+/// Copy a name of `len` bytes at `ptr` in from the calling service.
+fn name_arg(current_service: &ServiceRef, ptr: u64, len: usize) -> Result<String, SyscallError> {
+    if len > MAX_NAME_LEN {
+        return Err(SyscallError::InvalidStringArgument);
+    }
+
+    let buffer = UserBuffer::new(VirtualAddress::from(ptr), len)
+        .map_err(|_| SyscallError::InvalidPointerMappings)?;
+    let mut name = vec![0; len];
+
+    current_service
+        .copy_from_user(buffer, 0, &mut name)
+        .map_err(|_| SyscallError::InvalidPointerMappings)?;
+
+    String::from_utf8(name).map_err(|_| SyscallError::InvalidStringArgument)
+}
+
+/// Touch every page of the [`SYSCALL_STACK_SIZE`] bytes below the stack pointer, from the top down.
+///
+/// Syscalls run on the lazily backed stack of the calling thread. Backing a page on a fault takes the services lock,
+/// so faulting on the stack while a syscall holds a table lock would deadlock.
+/// The faults taken here happen before any lock is taken and are resolved by the page fault handler.
+#[inline(never)]
+fn probe_syscall_stack() {
+    let page_size = PageSize::Size4Kib.as_usize();
+    let stack_pointer: usize;
+
+    unsafe {
+        asm!("mov {}, rsp", out(reg) stack_pointer, options(nomem, nostack, preserves_flags));
+    }
+
+    let top = VirtualAddress::align_ptr_down(stack_pointer, page_size);
+
+    for offset in (page_size..=SYSCALL_STACK_SIZE).step_by(page_size) {
+        // Safety: only reads a byte of the stack, which belongs to the calling thread.
+        unsafe {
+            asm!("test byte ptr [{}], 0", in(reg) top - offset, options(readonly, nostack));
+        }
+    }
+}
+
 pub fn handle_kernel_syscall(args: &SyscallArgs) -> SyscallResult {
     let mut call_index = args.syscall as usize;
 
@@ -50,7 +131,14 @@ pub fn handle_kernel_syscall(args: &SyscallArgs) -> SyscallResult {
             .expect("syscalls should only be called from services")
     });
 
-    (table[call_index])(&args, current_service)
+    probe_syscall_stack();
+    atomic_block(|| SERVICE_TABLE.reap_terminated());
+
+    SCHEDULER.enter_syscall();
+    let result = (table[call_index])(&args, current_service);
+    SCHEDULER.leave_syscall();
+
+    result
 }
 pub fn handle_user_syscall(args: &SyscallArgs) -> SyscallResult {
     let call_index = args.syscall as usize;
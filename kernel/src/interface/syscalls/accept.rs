@@ -1,12 +1,18 @@
+use crate::interface::syscalls::deadline_arg;
+use crate::multi_tasking::scheduler::SCHEDULER;
 use crate::service::{Id, ServiceRef};
 use core::mem::size_of;
-use syscall::SyscallResult;
+use syscall::{SyscallError, SyscallResult};
 use x86_64::interrupts::atomic_block;
 use x86_64::syscalls::SyscallArgs;
 
 const NEW_CONNECTION_FLAG: u64 = 1 << (size_of::<Id>() * 2 * 8);
+const ACCEPT_NONBLOCKING_FLAG: u64 = 1;
+
+pub fn accept_syscall(args: &SyscallArgs, current_service: ServiceRef) -> SyscallResult {
+    let nonblocking = (args.arg3 & ACCEPT_NONBLOCKING_FLAG) != 0;
+    let deadline = deadline_arg(args.arg4);
 
-pub fn accept_syscall(_args: &SyscallArgs, current_service: ServiceRef) -> SyscallResult {
     atomic_block(|| loop {
         let next_connection = current_service.accept_next_connection_request();
 
@@ -17,6 +23,14 @@ pub fn accept_syscall(_args: &SyscallArgs, current_service: ServiceRef) -> Sysca
             return Ok(result | NEW_CONNECTION_FLAG);
         }
 
-        current_service.block_until_next_request();
+        if nonblocking {
+            return Err(SyscallError::WouldBlock);
+        }
+
+        if SCHEDULER.deadline_passed(deadline) {
+            return Err(SyscallError::TimedOut);
+        }
+
+        current_service.block_until_next_request(deadline);
     })
 }
@@ -0,0 +1,35 @@
+use syscall::{SyscallError, SyscallResult};
+use x86_64::syscalls::SyscallArgs;
+
+use crate::service::{ServiceRef, SharedMemoryError};
+
+/// Create a shared memory object owned by the calling service, returns its id.
+///
+/// The frames are zeroed with interrupts enabled, the service table only disables them while it is locked.
+pub fn create_shared_memory_syscall(
+    args: &SyscallArgs,
+    current_service: ServiceRef,
+) -> SyscallResult {
+    let pages = args.arg0;
+
+    if pages == 0 {
+        return Err(SyscallError::InvalidArgument);
+    }
+
+    let pages = usize::try_from(pages).map_err(|_| SyscallError::OutOfMemory)?;
+
+    match current_service.create_shared_memory(pages) {
+        Ok(id) => Ok(id as u64),
+        Err(SharedMemoryError::OutOfMemory | SharedMemoryError::QuotaExceeded) => {
+            Err(SyscallError::OutOfMemory)
+        }
+        Err(
+            SharedMemoryError::NotFound
+            | SharedMemoryError::NotOwner
+            | SharedMemoryError::InvalidConnection
+            | SharedMemoryError::InvalidRange
+            | SharedMemoryError::AlreadyMapped
+            | SharedMemoryError::NotMapped,
+        ) => Err(SyscallError::InvalidArgument),
+    }
+}
@@ -0,0 +1,32 @@
+use syscall::{SyscallError, SyscallResult};
+use x86_64::interrupts::atomic_block;
+use x86_64::syscalls::SyscallArgs;
+
+use crate::service::{Id, ServiceRef, SharedMemoryError};
+
+/// Allow the service on the other end of a connection to map shared memory owned by the calling service.
+pub fn grant_shared_memory_syscall(
+    args: &SyscallArgs,
+    current_service: ServiceRef,
+) -> SyscallResult {
+    let id = args.arg0 as Id;
+    let connection = args.arg1 as Id;
+
+    atomic_block(
+        || match current_service.grant_shared_memory(id, connection) {
+            Ok(()) => Ok(0),
+            Err(SharedMemoryError::NotFound | SharedMemoryError::InvalidConnection) => {
+                Err(SyscallError::ResourceNotFound)
+            }
+            Err(SharedMemoryError::NotOwner) => Err(SyscallError::OperationNotPermitted),
+            Err(SharedMemoryError::OutOfMemory | SharedMemoryError::QuotaExceeded) => {
+                Err(SyscallError::OutOfMemory)
+            }
+            Err(
+                SharedMemoryError::InvalidRange
+                | SharedMemoryError::AlreadyMapped
+                | SharedMemoryError::NotMapped,
+            ) => Err(SyscallError::InvalidArgument),
+        },
+    )
+}
@@ -0,0 +1,73 @@
+use crate::interface::syscalls::{SyscallError, SyscallResult};
+use crate::multi_tasking::scheduler::SCHEDULER;
+use crate::service::{Privilege, ServiceRef, UserBuffer, SERVICE_TABLE};
+use core::cmp::min;
+use core::mem::size_of;
+use essentials::address::VirtualAddress;
+use syscall::{ServiceInfo, ThreadInfo};
+use x86_64::interrupts::atomic_block;
+use x86_64::syscalls::SyscallArgs;
+
+const LIST_SERVICES_FLAG: u64 = 1;
+const MORE_ENTRIES_FLAG: u64 = 1 << 32;
+
+/// The number of entries filled before they are copied out to the service.
+const CHUNK_ENTRIES: usize = 16;
+
+pub fn introspect_syscall(args: &SyscallArgs, current_service: ServiceRef) -> SyscallResult {
+    let capacity = args.arg0 as usize;
+    let entries_ptr = args.arg1;
+    let start = args.arg2 as usize;
+
+    if atomic_block(|| current_service.spec().privilege()) < Privilege::System {
+        return Err(SyscallError::OperationNotPermitted);
+    }
+
+    let (written, more) = if args.arg3 & LIST_SERVICES_FLAG != 0 {
+        copy_entries::<ServiceInfo>(&current_service, entries_ptr, capacity, |first, chunk| {
+            SERVICE_TABLE.service_info(start + first, chunk)
+        })?
+    } else {
+        copy_entries::<ThreadInfo>(&current_service, entries_ptr, capacity, |first, chunk| {
+            SCHEDULER.thread_info(start + first, chunk)
+        })?
+    };
+
+    let mut result = written as u64;
+
+    if more {
+        result |= MORE_ENTRIES_FLAG;
+    }
+
+    Ok(result)
+}
+
+/// Fill up to `capacity` entries at `entries_ptr` in chunks, `fill` writes the entries from an index into a chunk.
+fn copy_entries<T: Copy + Default>(
+    current_service: &ServiceRef,
+    entries_ptr: u64,
+    capacity: usize,
+    fill: impl Fn(usize, &mut [T]) -> (usize, bool),
+) -> Result<(usize, bool), SyscallError> {
+    let entries = UserBuffer::new_array::<T>(VirtualAddress::from(entries_ptr), capacity)
+        .map_err(|_| SyscallError::InvalidPointerMappings)?;
+
+    let mut chunk = [T::default(); CHUNK_ENTRIES];
+    let mut written = 0;
+
+    loop {
+        let chunk = &mut chunk[0..min(CHUNK_ENTRIES, capacity - written)];
+        let (filled, more) = atomic_block(|| fill(written, chunk));
+
+        atomic_block(|| {
+            current_service.copy_to_user(entries, written * size_of::<T>(), &chunk[0..filled])
+        })
+        .map_err(|_| SyscallError::InvalidPointerMappings)?;
+
+        written += filled;
+
+        if !more || filled < chunk.len() || written == capacity {
+            return Ok((written, more));
+        }
+    }
+}
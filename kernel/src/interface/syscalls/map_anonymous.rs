@@ -0,0 +1,40 @@
+use essentials::address::VirtualAddress;
+use syscall::{SyscallError, SyscallResult};
+use x86_64::interrupts::atomic_block;
+use x86_64::paging::PageSize;
+use x86_64::syscalls::SyscallArgs;
+
+use crate::service::{MapError, ServiceRef};
+
+const FIXED_ADDRESS_FLAG: u64 = 1;
+
+/// The largest number of pages mapped by a single call, so the size of the region cannot overflow.
+const MAX_PAGES: u64 = u32::MAX as u64;
+
+/// Map zeroed pages in the address space of the calling service, returns the address of the first page.
+pub fn map_anonymous_syscall(args: &SyscallArgs, current_service: ServiceRef) -> SyscallResult {
+    let pages = args.arg0;
+    let fixed = args.arg3 & FIXED_ADDRESS_FLAG != 0;
+
+    if pages == 0 || pages > MAX_PAGES {
+        return Err(SyscallError::InvalidArgument);
+    }
+
+    let addr = if fixed {
+        if args.arg1 % PageSize::Size4Kib.as_usize() as u64 != 0 {
+            return Err(SyscallError::InvalidArgument);
+        }
+
+        Some(VirtualAddress::from(args.arg1))
+    } else {
+        None
+    };
+
+    atomic_block(
+        || match current_service.map_anonymous(addr, pages as usize) {
+            Ok(addr) => Ok(addr.as_u64()),
+            Err(MapError::OutOfMemory) => Err(SyscallError::OutOfMemory),
+            Err(MapError::InvalidRange) => Err(SyscallError::InvalidArgument),
+        },
+    )
+}
@@ -0,0 +1,45 @@
+use essentials::address::VirtualAddress;
+use syscall::{SyscallError, SyscallResult};
+use x86_64::interrupts::atomic_block;
+use x86_64::paging::PageSize;
+use x86_64::syscalls::SyscallArgs;
+
+use crate::service::{Id, ServiceRef, SharedMemoryError};
+
+const FIXED_ADDRESS_FLAG: u64 = 1;
+const READ_ONLY_FLAG: u64 = 1 << 1;
+
+/// Map shared memory owned by or granted to the calling service, returns the address of the first page.
+pub fn map_shared_memory_syscall(args: &SyscallArgs, current_service: ServiceRef) -> SyscallResult {
+    let id = args.arg0 as Id;
+    let pages = args.arg1 as usize;
+    let fixed = args.arg3 & FIXED_ADDRESS_FLAG != 0;
+    let writable = args.arg3 & READ_ONLY_FLAG == 0;
+
+    let addr = if fixed {
+        if args.arg2 % PageSize::Size4Kib.as_usize() as u64 != 0 {
+            return Err(SyscallError::InvalidArgument);
+        }
+
+        Some(VirtualAddress::from(args.arg2))
+    } else {
+        None
+    };
+
+    atomic_block(
+        || match current_service.map_shared_memory(id, pages, addr, writable) {
+            Ok(addr) => Ok(addr.as_u64()),
+            Err(SharedMemoryError::NotFound) => Err(SyscallError::ResourceNotFound),
+            Err(SharedMemoryError::OutOfMemory | SharedMemoryError::QuotaExceeded) => {
+                Err(SyscallError::OutOfMemory)
+            }
+            Err(
+                SharedMemoryError::InvalidRange
+                | SharedMemoryError::AlreadyMapped
+                | SharedMemoryError::NotOwner
+                | SharedMemoryError::InvalidConnection
+                | SharedMemoryError::NotMapped,
+            ) => Err(SyscallError::InvalidArgument),
+        },
+    )
+}
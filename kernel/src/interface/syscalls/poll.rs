@@ -0,0 +1,201 @@
+use crate::interface::syscalls::{deadline_arg, SyscallError, SyscallResult};
+use crate::multi_tasking::scheduler::SCHEDULER;
+use crate::service::{as_bytes_mut, PollError, ServiceRef, UserBuffer};
+use core::cmp::min;
+use core::mem::size_of;
+use essentials::address::VirtualAddress;
+use syscall::{PollEntry, PollEvents};
+use x86_64::interrupts::atomic_block;
+use x86_64::syscalls::SyscallArgs;
+
+const POLL_ACCEPT_FLAG: u64 = 1;
+const REQUEST_PENDING_FLAG: u64 = 1 << 32;
+
+/// The number of entries copied in and out of the service at once.
+const POLL_CHUNK_ENTRIES: usize = 32;
+
+pub fn poll_syscall(args: &SyscallArgs, current_service: ServiceRef) -> SyscallResult {
+    let entry_count = args.arg0 as usize;
+    let entries_ptr = args.arg1;
+    let accept = args.arg3 & POLL_ACCEPT_FLAG != 0;
+    let deadline = deadline_arg(args.arg4);
+
+    let entries =
+        UserBuffer::new_array::<PollEntry>(VirtualAddress::from(entries_ptr), entry_count)
+            .map_err(|_| SyscallError::InvalidPointerMappings)?;
+
+    atomic_block(|| loop {
+        let (ready_entries, request_pending) =
+            poll_entries(&current_service, entries, entry_count, accept)?;
+
+        if ready_entries > 0 || request_pending {
+            let mut result = ready_entries as u64;
+
+            if request_pending {
+                result |= REQUEST_PENDING_FLAG;
+            }
+
+            return Ok(result);
+        }
+
+        if SCHEDULER.deadline_passed(deadline) {
+            return Err(SyscallError::TimedOut);
+        }
+
+        current_service.block_until_poll_event(deadline);
+    })
+}
+
+/// Poll the entries in chunks, each chunk is copied in, updated and copied back out to the service.
+fn poll_entries(
+    current_service: &ServiceRef,
+    entries: UserBuffer,
+    entry_count: usize,
+    accept: bool,
+) -> Result<(usize, bool), SyscallError> {
+    let mut chunk = [PollEntry::new(0, PollEvents::NONE); POLL_CHUNK_ENTRIES];
+    let mut ready_entries = 0;
+    let mut request_pending = false;
+    let mut first = 0;
+
+    loop {
+        let chunk = &mut chunk[0..min(POLL_CHUNK_ENTRIES, entry_count - first)];
+        let offset = first * size_of::<PollEntry>();
+
+        // SAFETY: poll entries only hold integers, so any bytes are valid entries.
+        current_service
+            .copy_from_user(entries, offset, unsafe { as_bytes_mut(chunk) })
+            .map_err(|_| SyscallError::InvalidPointerMappings)?;
+
+        let (ready, pending) = match current_service.poll(chunk, accept) {
+            Ok(result) => result,
+            Err(PollError::InvalidConnection) => return Err(SyscallError::ResourceNotFound),
+        };
+
+        current_service
+            .copy_to_user(entries, offset, chunk)
+            .map_err(|_| SyscallError::InvalidPointerMappings)?;
+
+        ready_entries += ready;
+        request_pending |= pending;
+        first += chunk.len();
+
+        if first >= entry_count {
+            return Ok((ready_entries, request_pending));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+    use core::time::Duration;
+    use syscall::encode_deadline;
+
+    use crate::arch::x86_64::init::{Fault, FaultKind};
+    use crate::multi_tasking::scheduler::tests::{advance_clock, exit_threads, is_blocked};
+    use crate::service::tests::{
+        add_poller, new_table, register_spec, register_spec_with_privilege, stop_services,
+    };
+    use crate::service::{Privilege, ServiceTable};
+
+    /// Poll the `entries` as `service`, which passes them in kernel memory.
+    fn poll(
+        table: &ServiceTable,
+        service: &ServiceRef,
+        entries: &mut [PollEntry],
+        deadline: Option<Duration>,
+    ) -> SyscallResult {
+        let args = SyscallArgs {
+            syscall: 0,
+            arg0: entries.len() as u64,
+            arg1: entries.as_mut_ptr() as u64,
+            arg2: 0,
+            arg3: 0,
+            arg4: encode_deadline(deadline),
+        };
+
+        poll_syscall(&args, ServiceRef::new(table, service.id()))
+    }
+
+    /// A kernel service connected to a new instance of another spec, returns both ends and the connection.
+    fn connected_pair(table: &ServiceTable) -> (ServiceRef<'_>, ServiceRef<'_>, u16) {
+        let client_spec = register_spec_with_privilege(table, "client", Privilege::Kernel);
+        let client = table.start_service(client_spec).unwrap();
+        let connection = client.connect_to(register_spec(table, "target")).unwrap();
+        let target = client.get_service_from_connection(connection).unwrap().id();
+
+        (client, ServiceRef::new(table, target), connection)
+    }
+
+    #[test_case]
+    fn test_ready_connections_are_reported() {
+        let table = new_table();
+        let (client, target, connection) = connected_pair(&table);
+
+        // without a request, the client can always start writing one but has nothing to read.
+        let mut entries = vec![PollEntry::new(
+            connection,
+            PollEvents::READABLE | PollEvents::WRITABLE,
+        )];
+
+        assert!(matches!(poll(&table, &client, &mut entries, None), Ok(1)));
+        assert_eq!(PollEvents::WRITABLE, entries[0].ready);
+
+        stop_services(&[client.id(), target.id()]);
+    }
+
+    #[test_case]
+    fn test_poll_times_out_at_the_deadline() {
+        let table = new_table();
+        let (client, target, connection) = connected_pair(&table);
+        let mut entries = vec![PollEntry::new(connection, PollEvents::READABLE)];
+
+        let deadline = SCHEDULER.uptime() + Duration::from_millis(10);
+        let deadline_tick = SCHEDULER.tick_at_uptime(deadline);
+        let poller = add_poller(&table, client.id(), Some(deadline_tick));
+
+        advance_clock(deadline_tick - SCHEDULER.current_tick());
+
+        // the blocked poller wakes up at the deadline, and finds it passed without any event.
+        assert!(!is_blocked(poller));
+        assert!(matches!(
+            poll(&table, &client, &mut entries, Some(deadline)),
+            Err(SyscallError::TimedOut)
+        ));
+        assert_eq!(PollEvents::NONE, entries[0].ready);
+
+        exit_threads(&[poller]);
+        stop_services(&[client.id(), target.id()]);
+    }
+
+    #[test_case]
+    fn test_severed_connections_wake_the_poller() {
+        let table = new_table();
+        let (client, target, connection) = connected_pair(&table);
+        let mut entries = vec![PollEntry::new(connection, PollEvents::READABLE)];
+
+        // a deadline of the current tick has passed, so the poll returns right away.
+        advance_clock(1);
+        assert!(matches!(
+            poll(&table, &client, &mut entries, Some(SCHEDULER.uptime())),
+            Err(SyscallError::TimedOut)
+        ));
+
+        let poller = add_poller(&table, client.id(), None);
+
+        target.terminate(Fault {
+            kind: FaultKind::InvalidOpcode,
+            instruction_pointer: VirtualAddress::new(0x1000),
+        });
+
+        // the closed pipe reads as the end of the response.
+        assert!(!is_blocked(poller));
+        assert!(matches!(poll(&table, &client, &mut entries, None), Ok(1)));
+        assert_eq!(PollEvents::READABLE, entries[0].ready);
+
+        exit_threads(&[poller]);
+        stop_services(&[client.id(), target.id()]);
+    }
+}
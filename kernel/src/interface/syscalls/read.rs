@@ -1,25 +1,21 @@
-use crate::interface::syscalls::{SyscallError, SyscallResult};
-use crate::service::{Id, ReadError, ServiceRef};
+use crate::interface::syscalls::{deadline_arg, SyscallError, SyscallResult};
+use crate::multi_tasking::scheduler::SCHEDULER;
+use crate::service::{Id, ReadError, ServiceRef, UserBuffer};
 use essentials::address::VirtualAddress;
 use x86_64::interrupts::atomic_block;
 use x86_64::syscalls::SyscallArgs;
 
+const READ_NONBLOCKING_FLAG: u64 = 1;
+
 pub fn read_syscall(args: &SyscallArgs, current_service: ServiceRef) -> SyscallResult {
     let connection_id = args.arg0 as Id;
     let buffer_len = args.arg1 as usize;
     let buffer_ptr = args.arg2;
+    let nonblocking = (args.arg3 & READ_NONBLOCKING_FLAG) != 0;
+    let deadline = deadline_arg(args.arg4);
 
-    let Some(read_buffer) =
-        atomic_block(|| current_service.deref_incoming_pointer(VirtualAddress::from(buffer_ptr)))
-    else {
-        return Err(SyscallError::InvalidPointerMappings);
-    };
-
-    if buffer_len > read_buffer.len() {
-        return Err(SyscallError::InvalidPointerMappings);
-    }
-
-    let target_buffer = &mut read_buffer[0..buffer_len];
+    let target_buffer = UserBuffer::new(VirtualAddress::from(buffer_ptr), buffer_len)
+        .map_err(|_| SyscallError::InvalidPointerMappings)?;
     let mut start = 0;
 
     atomic_block(|| loop {
@@ -30,6 +26,7 @@ pub fn read_syscall(args: &SyscallArgs, current_service: ServiceRef) -> SyscallR
                 return match err {
                     ReadError::InvalidConnection => Err(SyscallError::ResourceNotFound),
                     ReadError::RequestClosed => Ok(0),
+                    ReadError::InvalidBuffer(_) => Err(SyscallError::InvalidPointerMappings),
                 }
             }
             Ok(read) => {
@@ -39,9 +36,17 @@ pub fn read_syscall(args: &SyscallArgs, current_service: ServiceRef) -> SyscallR
                     return Ok(start as u64);
                 }
 
-                // because the buffer could not be written in its entirety, it must be full.
-                // Therefore wait until the other side reads from the buffer.
-                current_service.block_until_read_available(connection_id)
+                if nonblocking {
+                    return Err(SyscallError::WouldBlock);
+                }
+
+                if SCHEDULER.deadline_passed(deadline) {
+                    return Err(SyscallError::TimedOut);
+                }
+
+                // because nothing could be read, the buffer must be empty.
+                // Therefore wait until the other side writes to the buffer.
+                current_service.block_until_read_available(connection_id, deadline)
             }
         }
     })
@@ -1,35 +1,27 @@
-use crate::interface::syscalls::{SyscallError, SyscallResult};
+use crate::interface::syscalls::{deadline_arg, name_arg, SyscallError, SyscallResult};
+use crate::multi_tasking::scheduler::SCHEDULER;
 use crate::service::{CreateRequestError, Id, ServiceRef};
-use essentials::address::VirtualAddress;
 use x86_64::interrupts::atomic_block;
 use x86_64::syscalls::SyscallArgs;
 
+const REQUEST_NONBLOCKING_FLAG: u64 = 1;
+
 pub fn request_syscall(args: &SyscallArgs, current_service: ServiceRef) -> SyscallResult {
     let connection_id = args.arg0 as Id;
     let name_len = args.arg1 as usize;
     let name_ptr = args.arg2;
+    let nonblocking = (args.arg3 & REQUEST_NONBLOCKING_FLAG) != 0;
+    let deadline = deadline_arg(args.arg4);
 
-    let Some(target_endpoint_name) =
-        atomic_block(|| current_service.deref_incoming_pointer(VirtualAddress::from(name_ptr)))
-    else {
-        return Err(SyscallError::InvalidPointerMappings);
-    };
-
-    if name_len > target_endpoint_name.len() {
-        return Err(SyscallError::InvalidStringArgument);
-    }
-
-    let target_endpoint_name = core::str::from_utf8(&target_endpoint_name[0..name_len])
-        .map_err(|_| SyscallError::InvalidStringArgument)?;
+    let target_endpoint_name = atomic_block(|| name_arg(&current_service, name_ptr, name_len))?;
 
     atomic_block(|| {
-        let target_service = current_service
-            .get_service_from_connection(connection_id)
+        let target_service_spec = current_service
+            .get_spec_from_connection(connection_id)
             .ok_or(SyscallError::ResourceNotFound)?;
 
-        let target_service_spec = target_service.spec();
         let target_endpoint = target_service_spec
-            .get_endpoint_by_name(target_endpoint_name)
+            .get_endpoint_by_name(&target_endpoint_name)
             .ok_or(SyscallError::ResourceNotFound)?;
 
         loop {
@@ -41,6 +33,9 @@ pub fn request_syscall(args: &SyscallArgs, current_service: ServiceRef) -> Sysca
                     CreateRequestError::NotPermitted => {
                         return Err(SyscallError::OperationNotPermitted)
                     }
+                    CreateRequestError::TargetTerminated => {
+                        return Err(SyscallError::RequestClosed)
+                    }
                     CreateRequestError::ConnectionBusy => {}
                     CreateRequestError::InvalidEndpointId => {
                         panic!("Expected the endpoint to be valid before creating the request")
@@ -48,7 +43,15 @@ pub fn request_syscall(args: &SyscallArgs, current_service: ServiceRef) -> Sysca
                 },
             }
 
-            current_service.block_until_request_close(connection_id);
+            if nonblocking {
+                return Err(SyscallError::WouldBlock);
+            }
+
+            if SCHEDULER.deadline_passed(deadline) {
+                return Err(SyscallError::TimedOut);
+            }
+
+            current_service.block_until_request_close(connection_id, deadline);
         }
     })
 }
@@ -0,0 +1,39 @@
+use crate::interface::syscalls::{SyscallError, SyscallResult};
+use crate::multi_tasking::scheduler::SCHEDULER;
+use crate::service::{Id, Privilege, ServiceRef, UserBuffer};
+use essentials::address::VirtualAddress;
+use syscall::ServiceStats;
+use x86_64::interrupts::atomic_block;
+use x86_64::syscalls::SyscallArgs;
+
+const CURRENT_SERVICE_FLAG: u64 = 1;
+
+pub fn service_stats_syscall(args: &SyscallArgs, current_service: ServiceRef) -> SyscallResult {
+    let stats_ptr = args.arg1;
+
+    let buffer = UserBuffer::new_array::<ServiceStats>(VirtualAddress::from(stats_ptr), 1)
+        .map_err(|_| SyscallError::InvalidPointerMappings)?;
+
+    atomic_block(|| {
+        let service = if args.arg3 & CURRENT_SERVICE_FLAG != 0 {
+            current_service.id()
+        } else {
+            args.arg0 as Id
+        };
+
+        if service != current_service.id() && current_service.spec().privilege() < Privilege::System
+        {
+            return Err(SyscallError::OperationNotPermitted);
+        }
+
+        let stats = SCHEDULER
+            .service_stats(service)
+            .ok_or(SyscallError::ResourceNotFound)?;
+
+        current_service
+            .copy_to_user(buffer, 0, &[stats])
+            .map_err(|_| SyscallError::InvalidPointerMappings)?;
+
+        Ok(0)
+    })
+}
@@ -0,0 +1,25 @@
+use crate::interface::syscalls::{SyscallError, SyscallResult};
+use crate::multi_tasking::scheduler::SCHEDULER;
+use crate::service::{Privilege, ServiceRef};
+use syscall::Priority;
+use x86_64::interrupts::atomic_block;
+use x86_64::syscalls::SyscallArgs;
+
+pub fn set_priority_syscall(args: &SyscallArgs, current_service: ServiceRef) -> SyscallResult {
+    let priority = Priority::try_from(args.arg0).map_err(|_| SyscallError::InvalidArgument)?;
+
+    atomic_block(|| {
+        let spec = current_service.spec();
+
+        if spec.privilege() == Privilege::User && priority > spec.priority() {
+            return Err(SyscallError::OperationNotPermitted);
+        }
+
+        let current = SCHEDULER
+            .current_thread()
+            .expect("syscalls should only be called from threads");
+
+        SCHEDULER.set_priority(current, priority);
+        Ok(0)
+    })
+}
@@ -0,0 +1,16 @@
+use essentials::address::VirtualAddress;
+use syscall::{SyscallError, SyscallResult};
+use x86_64::interrupts::atomic_block;
+use x86_64::syscalls::SyscallArgs;
+
+use crate::service::{ServiceRef, UnmapError};
+
+/// Unmap a region mapped with the map anonymous syscall, freeing its frames.
+pub fn unmap_anonymous_syscall(args: &SyscallArgs, current_service: ServiceRef) -> SyscallResult {
+    let addr = VirtualAddress::from(args.arg0);
+
+    atomic_block(|| match current_service.unmap_anonymous(addr) {
+        Ok(()) => Ok(0),
+        Err(UnmapError::RegionNotFound) => Err(SyscallError::ResourceNotFound),
+    })
+}
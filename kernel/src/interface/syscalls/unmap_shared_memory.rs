@@ -0,0 +1,39 @@
+use syscall::{SyscallError, SyscallResult};
+use x86_64::interrupts::atomic_block;
+use x86_64::syscalls::SyscallArgs;
+
+use crate::service::{Id, ServiceRef, SharedMemoryError};
+
+const REVOKE_FLAG: u64 = 1;
+
+/// Unmap shared memory from the calling service, or revoke it from every service when the revoke flag is set.
+pub fn unmap_shared_memory_syscall(
+    args: &SyscallArgs,
+    current_service: ServiceRef,
+) -> SyscallResult {
+    let id = args.arg0 as Id;
+    let revoke = args.arg3 & REVOKE_FLAG != 0;
+
+    atomic_block(|| {
+        let result = match revoke {
+            true => current_service.revoke_shared_memory(id),
+            false => current_service.unmap_shared_memory(id),
+        };
+
+        match result {
+            Ok(()) => Ok(0),
+            Err(SharedMemoryError::NotFound | SharedMemoryError::NotMapped) => {
+                Err(SyscallError::ResourceNotFound)
+            }
+            Err(SharedMemoryError::NotOwner) => Err(SyscallError::OperationNotPermitted),
+            Err(SharedMemoryError::OutOfMemory | SharedMemoryError::QuotaExceeded) => {
+                Err(SyscallError::OutOfMemory)
+            }
+            Err(
+                SharedMemoryError::InvalidConnection
+                | SharedMemoryError::InvalidRange
+                | SharedMemoryError::AlreadyMapped,
+            ) => Err(SyscallError::InvalidArgument),
+        }
+    })
+}
@@ -0,0 +1,9 @@
+use crate::interface::syscalls::SyscallResult;
+use crate::multi_tasking::scheduler::SCHEDULER;
+use crate::service::ServiceRef;
+use x86_64::syscalls::SyscallArgs;
+
+/// The time since boot in milliseconds, used by services to calculate deadlines.
+pub fn uptime_syscall(_args: &SyscallArgs, _current_service: ServiceRef) -> SyscallResult {
+    Ok(SCHEDULER.uptime().as_millis() as u64)
+}
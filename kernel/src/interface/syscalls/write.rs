@@ -1,16 +1,19 @@
-use crate::interface::syscalls::{SyscallError, SyscallResult};
-use crate::service::{Id, ServiceRef, WriteError};
+use crate::interface::syscalls::{deadline_arg, SyscallError, SyscallResult};
+use crate::multi_tasking::scheduler::SCHEDULER;
+use crate::service::{Id, ServiceRef, UserBuffer, WriteError};
 use essentials::address::VirtualAddress;
 use x86_64::interrupts::atomic_block;
 use x86_64::syscalls::SyscallArgs;
 
 const WRITE_END_FLAG: u64 = 1;
+const WRITE_NONBLOCKING_FLAG: u64 = 1 << 1;
 
 fn map_write_error_to_syscall_error(err: WriteError) -> SyscallError {
     match err {
         WriteError::InvalidConnection => SyscallError::ResourceNotFound,
         WriteError::NoOpenRequest | WriteError::RequestClosed => SyscallError::RequestClosed,
         WriteError::ParameterOverflow => SyscallError::ParameterOverflow,
+        WriteError::InvalidBuffer(_) => SyscallError::InvalidPointerMappings,
     }
 }
 
@@ -19,18 +22,10 @@ pub fn write_syscall(args: &SyscallArgs, current_service: ServiceRef) -> Syscall
     let buffer_size = args.arg1 as usize;
     let buffer_ptr = args.arg2;
     let flags = args.arg3;
+    let deadline = deadline_arg(args.arg4);
 
-    let Some(write_buffer) =
-        atomic_block(|| current_service.deref_incoming_pointer(VirtualAddress::from(buffer_ptr)))
-    else {
-        return Err(SyscallError::InvalidPointerMappings);
-    };
-
-    if buffer_size > write_buffer.len() {
-        return Err(SyscallError::InvalidStringArgument);
-    }
-
-    let source_buffer = &write_buffer[0..buffer_size];
+    let source_buffer = UserBuffer::new(VirtualAddress::from(buffer_ptr), buffer_size)
+        .map_err(|_| SyscallError::InvalidPointerMappings)?;
     let mut start = 0;
 
     atomic_block(|| loop {
@@ -51,9 +46,17 @@ pub fn write_syscall(args: &SyscallArgs, current_service: ServiceRef) -> Syscall
                     return Ok(start as u64);
                 }
 
+                if (flags & WRITE_NONBLOCKING_FLAG) != 0 {
+                    return Err(SyscallError::WouldBlock);
+                }
+
+                if SCHEDULER.deadline_passed(deadline) {
+                    return Err(SyscallError::TimedOut);
+                }
+
                 // because the buffer could not be written in its entirety, it must be full.
                 // Therefore wait until the other side reads from the buffer.
-                current_service.block_until_write_available(connection_id)
+                current_service.block_until_write_available(connection_id, deadline)
             }
         }
     })
@@ -0,0 +1,10 @@
+use crate::interface::syscalls::SyscallResult;
+use crate::multi_tasking::scheduler::SCHEDULER;
+use crate::service::ServiceRef;
+use x86_64::syscalls::SyscallArgs;
+
+/// Give the rest of the time slice to the next thread, the calling thread stays runnable.
+pub fn yield_syscall(_args: &SyscallArgs, _current_service: ServiceRef) -> SyscallResult {
+    SCHEDULER.yield_current();
+    Ok(0)
+}
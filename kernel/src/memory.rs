@@ -4,9 +4,12 @@ pub use flush::*;
 pub use frame_allocator::*;
 pub use info::*;
 pub use mapper::*;
+pub use regions::*;
 
 mod flush;
 mod frame_allocator;
 pub mod heap;
 mod info;
 mod mapper;
+mod regions;
+mod slab;
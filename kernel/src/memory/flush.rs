@@ -1,5 +1,9 @@
+use alloc::vec::Vec;
 use essentials::collections::FixedVec;
-use x86_64::paging::PhysicalPage;
+use x86_64::paging::{PhysicalPage, VirtualPage};
+
+use crate::arch::x86_64::tlb::{self, CpuSet, Shootdown};
+use crate::memory::frame_allocator::FrameAllocator;
 
 #[must_use]
 pub trait TableCacheFlush {
@@ -34,3 +38,94 @@ impl TableCacheFlush for TableListCacheFlush {
         }
     }
 }
+
+/// Flushes the translations of a range of virtual pages, after their mappings were changed or removed.
+///
+/// Other CPUs which may cache the old translations are shot down as well,
+/// and the frames that were reachable through them are only freed once those CPUs flushed.
+pub struct PageRangeCacheFlush {
+    start: VirtualPage,
+    pages: usize,
+    /// The other CPUs which have the memory map active.
+    remote: CpuSet,
+    /// The frames and tables that were unmapped, with the allocator they are returned to.
+    released: Option<(&'static FrameAllocator, Vec<PhysicalPage>)>,
+}
+
+impl PageRangeCacheFlush {
+    /// Above this amount of pages, reloading the whole address space is cheaper than flushing every page.
+    const MAX_SINGLE_FLUSHES: usize = 64;
+
+    /// Flush the range on the executing CPU only, for mappings no other CPU can have cached.
+    pub const fn new(start: VirtualPage, pages: usize) -> Self {
+        Self {
+            start,
+            pages,
+            remote: CpuSet::empty(),
+            released: None,
+        }
+    }
+
+    /// Flush the range on the executing CPU and the `remote` CPUs, and free the `released` pages afterwards.
+    pub fn with_shootdown(
+        start: VirtualPage,
+        pages: usize,
+        remote: CpuSet,
+        frame_allocator: &'static FrameAllocator,
+        released: Vec<PhysicalPage>,
+    ) -> Self {
+        Self {
+            start,
+            pages,
+            remote,
+            released: Some((frame_allocator, released)),
+        }
+    }
+
+    fn flush_local(&self) {
+        if self.pages > Self::MAX_SINGLE_FLUSHES {
+            let (active, _) = PhysicalPage::active();
+
+            // Safety: reloading the active table does not change any mapping.
+            unsafe { active.make_active() };
+            return;
+        }
+
+        let mut page = self.start;
+
+        for _ in 0..self.pages {
+            page.flush();
+            page = page.next();
+        }
+    }
+
+    /// Flush the range like [`TableCacheFlush::flush`], and return the shootdown sent to the other CPUs, if any.
+    ///
+    /// The other CPUs keep using the old translations until it completes, see [`Shootdown::wait`].
+    pub fn flush_remote(self) -> Option<Shootdown> {
+        self.flush_local();
+
+        let (frame_allocator, released) = self.released?;
+
+        if self.remote.is_empty() {
+            released
+                .into_iter()
+                .for_each(|page| frame_allocator.free_page(page));
+            return None;
+        }
+
+        let shootdown = tlb::shoot_down(self.remote);
+
+        if !released.is_empty() {
+            frame_allocator.free_after(shootdown, released);
+        }
+
+        Some(shootdown)
+    }
+}
+
+impl TableCacheFlush for PageRangeCacheFlush {
+    fn flush(self) {
+        self.flush_remote();
+    }
+}
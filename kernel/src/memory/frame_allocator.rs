@@ -1,38 +1,83 @@
-use core::sync::atomic::{AtomicUsize, Ordering};
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::slice;
 
 use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
-use essentials::sync::{Expected, SpinRwLock};
+use essentials::sync::{Expected, SpinMutex, SpinRwLock};
 use x86_64::paging::{PageSize, PhysicalPage};
 
+use crate::arch::x86_64::tlb::Shootdown;
+use crate::memory::frame_allocator::bitmap::FrameBitmap;
+use crate::memory::heap::ALLOCATOR;
 use crate::memory::MemoryInfo;
 
+mod bitmap;
+
 const SIZE: PageSize = PageSize::Size4Kib;
 
+/// Hands out physical frames from the usable regions of the memory map, and takes them back.
+///
+/// Free frames are tracked in a bitmap, which is stored in the first usable region that fits it.
+/// Frames mapped more than once, e.g. for copy-on-write, are reference counted.
 pub struct FrameAllocator {
     memory_map: SpinRwLock<Expected<&'static MemoryMap>>,
-    next: AtomicUsize,
+    bitmap: SpinMutex<Option<FrameBitmap<'static>>>,
+    /// The number of references to each frame with more than one.
+    shared: SpinMutex<BTreeMap<usize, usize>>,
+    /// Pages that are freed once other CPUs flushed their stale translations to them, see [`FrameAllocator::free_after`].
+    deferred: SpinMutex<Vec<(Shootdown, Vec<PhysicalPage>)>>,
 }
 
 impl FrameAllocator {
     pub const fn new() -> Self {
         Self {
             memory_map: SpinRwLock::new(Expected::new()),
-            next: AtomicUsize::new(0),
+            bitmap: SpinMutex::new(None),
+            shared: SpinMutex::new(BTreeMap::new()),
+            deferred: SpinMutex::new(Vec::new()),
         }
     }
 
-    pub unsafe fn init(&self, memory_map: &'static MemoryMap) {
+    /// # Safety
+    ///
+    /// All physical memory must be mapped at `physical_memory_offset`, and the usable regions of the `memory_map` must be unused.
+    pub unsafe fn init(&self, memory_map: &'static MemoryMap, physical_memory_offset: u64) {
         let mut lock = self.memory_map.write();
         lock.set(memory_map);
+
+        let usable_regions = || {
+            memory_map
+                .iter()
+                .filter(|r| r.region_type == MemoryRegionType::Usable)
+                .map(|r| r.range.start_frame_number as usize..r.range.end_frame_number as usize)
+        };
+
+        let frames = usable_regions().map(|r| r.end).max().unwrap_or(0);
+        let words = FrameBitmap::words_for(frames);
+        let bitmap_frames = (words * size_of::<u64>()).div_ceil(SIZE.as_usize());
+
+        let bitmap_start = usable_regions()
+            .find(|r| r.len() >= bitmap_frames)
+            .expect("no usable region can hold the frame bitmap")
+            .start;
+
+        let bitmap_ptr =
+            (physical_memory_offset as usize + bitmap_start * SIZE.as_usize()) as *mut u64;
+        let mut bitmap = FrameBitmap::new(slice::from_raw_parts_mut(bitmap_ptr, words));
+
+        for region in usable_regions() {
+            bitmap.mark_free(region);
+        }
+
+        bitmap.mark_used(bitmap_start..bitmap_start + bitmap_frames);
+
+        *self.bitmap.lock() = Some(bitmap);
     }
 
     pub fn info(&self) -> MemoryInfo {
         let memory_map = self.memory_map.read().clone();
 
-        let current = self.next.load(Ordering::Relaxed);
-
-        let bytes_allocated = current * SIZE.as_usize() as usize;
-
         let mut total_allocatable_bytes = 0;
         let mut total_bytes = 0;
         let mut kernel = 0;
@@ -57,27 +102,147 @@ impl FrameAllocator {
             }
         }
 
+        let free_bytes = self
+            .bitmap
+            .lock()
+            .as_ref()
+            .map_or(0, |bitmap| bitmap.free_frames() * SIZE.as_usize());
+
+        let heap = ALLOCATOR.usage();
+
         MemoryInfo {
-            allocated: bytes_allocated,
+            allocated: total_allocatable_bytes - free_bytes,
             usable: total_allocatable_bytes,
             total_size: total_bytes,
             kernel,
+            heap_size: heap.size,
+            heap_used: heap.used,
         }
     }
 
-    pub fn allocate_new_page_table(&self) -> Option<PhysicalPage> {
+    /// Whether `page` lies in usable memory, which means it is handed out by the allocator at some point.
+    pub fn is_usable(&self, page: PhysicalPage) -> bool {
         let memory_map = self.memory_map.read().clone();
+        let addr = page.addr().as_u64();
+
+        memory_map.iter().any(|r| {
+            r.region_type == MemoryRegionType::Usable
+                && (r.range.start_addr()..r.range.end_addr()).contains(&addr)
+        })
+    }
+
+    /// Take a single 4KiB frame.
+    pub fn allocate_frame(&self) -> Option<PhysicalPage> {
+        let frame = self.bitmap.lock().as_mut()?.allocate()?;
+        Some(Self::frame_page(frame, SIZE))
+    }
+
+    /// Take a physically contiguous page of `size`, aligned to its size, e.g. to back a huge page.
+    pub fn allocate_page(&self, size: PageSize) -> Option<PhysicalPage> {
+        let frames = Self::frames_per_page(size);
+        let frame = self
+            .bitmap
+            .lock()
+            .as_mut()?
+            .allocate_contiguous(frames, frames)?;
+
+        Some(Self::frame_page(frame, size))
+    }
+
+    /// Take `count` physically contiguous 4KiB frames, e.g. for DMA buffers, returns the first one.
+    pub fn allocate_contiguous(&self, count: usize) -> Option<PhysicalPage> {
+        let frame = self.bitmap.lock().as_mut()?.allocate_contiguous(count, 1)?;
+
+        Some(Self::frame_page(frame, SIZE))
+    }
+
+    /// Add a reference to a 4KiB frame, it is only freed once every reference is returned with [`FrameAllocator::free_page`].
+    pub fn share_frame(&self, page: PhysicalPage) {
+        let frame = Self::frame_index(page);
+        *self.shared.lock().entry(frame).or_insert(1) += 1;
+    }
 
-        let mut usable_pages = memory_map
-            .iter()
-            .filter(|r| r.region_type == MemoryRegionType::Usable)
-            .flat_map(|r| {
-                (r.range.start_addr()..r.range.end_addr()).step_by(SIZE.as_usize() as usize)
-            })
-            .map(|addr| PhysicalPage::new(addr.into(), SIZE));
+    /// The number of references to a frame, see [`FrameAllocator::share_frame`].
+    pub fn frame_references(&self, page: PhysicalPage) -> usize {
+        let frame = Self::frame_index(page);
+        self.shared.lock().get(&frame).copied().unwrap_or(1)
+    }
+
+    /// Return a page taken with [`FrameAllocator::allocate_frame`] or [`FrameAllocator::allocate_page`].
+    ///
+    /// A shared frame only loses a reference, until the last one is returned.
+    pub fn free_page(&self, page: PhysicalPage) {
+        if matches!(page.size(), PageSize::Size4Kib) && self.release_shared(page) {
+            return;
+        }
+
+        self.free_contiguous(
+            PhysicalPage::new(page.addr(), SIZE),
+            Self::frames_per_page(page.size()),
+        )
+    }
+
+    /// Return `pages` with [`FrameAllocator::free_page`] once the `shootdown` completed.
+    ///
+    /// Until then, the CPUs it targets can still reach the pages through stale translations, so they cannot be reused.
+    /// The pages are freed by a later call, once the shootdown is seen to be complete.
+    pub fn free_after(&self, shootdown: Shootdown, pages: Vec<PhysicalPage>) {
+        let mut deferred = self.deferred.lock();
+
+        deferred.retain(|(shootdown, pages)| {
+            let complete = shootdown.is_complete();
+            if complete {
+                pages.iter().for_each(|page| self.free_page(*page));
+            }
+            !complete
+        });
+
+        if shootdown.is_complete() {
+            pages.into_iter().for_each(|page| self.free_page(page));
+        } else {
+            deferred.push((shootdown, pages));
+        }
+    }
+
+    /// Return `count` frames starting at `first`, taken with [`FrameAllocator::allocate_contiguous`].
+    pub fn free_contiguous(&self, first: PhysicalPage, count: usize) {
+        let start = Self::frame_index(first);
+
+        self.bitmap
+            .lock()
+            .as_mut()
+            .expect("frames cannot be freed before the allocator is initialized")
+            .free(start..start + count);
+    }
+
+    /// Drop a reference to a shared frame, returns whether other references remain.
+    fn release_shared(&self, page: PhysicalPage) -> bool {
+        let frame = Self::frame_index(page);
+        let mut shared = self.shared.lock();
+
+        let Some(references) = shared.get_mut(&frame) else {
+            return false;
+        };
+
+        *references -= 1;
+
+        if *references == 1 {
+            shared.remove(&frame);
+        }
+
+        true
+    }
+
+    fn frame_index(page: PhysicalPage) -> usize {
+        page.addr().as_u64() as usize / SIZE.as_usize()
+    }
+
+    fn frames_per_page(size: PageSize) -> usize {
+        size.as_usize() / SIZE.as_usize()
+    }
 
-        let index = self.next.fetch_add(1, Ordering::AcqRel);
-        usable_pages.nth(index)
+    fn frame_page(frame: usize, size: PageSize) -> PhysicalPage {
+        PhysicalPage::new(((frame * SIZE.as_usize()) as u64).into(), size)
     }
 }
 
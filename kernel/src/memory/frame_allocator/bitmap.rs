@@ -0,0 +1,166 @@
+use core::ops::Range;
+
+const BITS: usize = u64::BITS as usize;
+
+/// A bitmap with a bit per physical frame, a set bit marks a free frame.
+///
+/// Frames start out as used, so only frames explicitly marked free are ever handed out.
+pub struct FrameBitmap<'a> {
+    words: &'a mut [u64],
+    free: usize,
+    /// The word to start searching at for single frames, everything before it was full at some point.
+    next_word: usize,
+}
+
+impl<'a> FrameBitmap<'a> {
+    /// Create a bitmap over `words.len() * 64` frames, all of them used.
+    pub fn new(words: &'a mut [u64]) -> Self {
+        words.fill(0);
+
+        Self {
+            words,
+            free: 0,
+            next_word: 0,
+        }
+    }
+
+    /// The number of bitmap words needed to track `frames` frames.
+    pub const fn words_for(frames: usize) -> usize {
+        frames.div_ceil(BITS)
+    }
+
+    pub fn frames(&self) -> usize {
+        self.words.len() * BITS
+    }
+
+    pub fn free_frames(&self) -> usize {
+        self.free
+    }
+
+    pub fn is_free(&self, frame: usize) -> bool {
+        frame < self.frames() && self.words[frame / BITS] & (1 << (frame % BITS)) != 0
+    }
+
+    /// Mark the frames free, frames outside the bitmap are ignored.
+    pub fn mark_free(&mut self, frames: Range<usize>) {
+        for frame in frames.start..frames.end.min(self.frames()) {
+            if !self.is_free(frame) {
+                self.set(frame, true);
+            }
+        }
+
+        self.next_word = self.next_word.min(frames.start / BITS);
+    }
+
+    /// Mark the frames used, frames outside the bitmap are ignored.
+    pub fn mark_used(&mut self, frames: Range<usize>) {
+        for frame in frames.start..frames.end.min(self.frames()) {
+            if self.is_free(frame) {
+                self.set(frame, false);
+            }
+        }
+    }
+
+    /// Take a single free frame.
+    pub fn allocate(&mut self) -> Option<usize> {
+        let word_index = (self.next_word..self.words.len()).find(|i| self.words[*i] != 0)?;
+        self.next_word = word_index;
+
+        let frame = word_index * BITS + self.words[word_index].trailing_zeros() as usize;
+        self.set(frame, false);
+
+        Some(frame)
+    }
+
+    /// Take `count` contiguous free frames, the first one being a multiple of `alignment`.
+    pub fn allocate_contiguous(&mut self, count: usize, alignment: usize) -> Option<usize> {
+        if count == 0 {
+            return None;
+        }
+
+        if count == 1 && alignment <= 1 {
+            return self.allocate();
+        }
+
+        let alignment = alignment.max(1);
+        let mut start = 0;
+
+        while start + count <= self.frames() {
+            match (start..start + count).find(|frame| !self.is_free(*frame)) {
+                Some(used) => start = (used + 1).next_multiple_of(alignment),
+                None => {
+                    self.mark_used(start..start + count);
+                    return Some(start);
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Return frames to the bitmap.
+    ///
+    /// # Panics
+    ///
+    /// When one of the frames is already free, which means it was freed twice or never allocated.
+    pub fn free(&mut self, frames: Range<usize>) {
+        for frame in frames.clone() {
+            assert!(
+                frame < self.frames() && !self.is_free(frame),
+                "Frame {frame} is freed but was not allocated"
+            );
+            self.set(frame, true);
+        }
+
+        self.next_word = self.next_word.min(frames.start / BITS);
+    }
+
+    fn set(&mut self, frame: usize, free: bool) {
+        let word = &mut self.words[frame / BITS];
+        let bit = 1 << (frame % BITS);
+
+        if free {
+            *word |= bit;
+            self.free += 1;
+        } else {
+            *word &= !bit;
+            self.free -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_allocate_and_free() {
+        let mut words = [0; 2];
+        let mut bitmap = FrameBitmap::new(&mut words);
+
+        assert_eq!(None, bitmap.allocate());
+
+        bitmap.mark_free(3..70);
+        assert_eq!(67, bitmap.free_frames());
+        assert_eq!(Some(3), bitmap.allocate());
+        assert_eq!(Some(4), bitmap.allocate());
+
+        bitmap.free(3..4);
+        assert_eq!(Some(3), bitmap.allocate());
+        assert_eq!(65, bitmap.free_frames());
+    }
+
+    #[test_case]
+    fn test_allocate_contiguous_aligned() {
+        let mut words = [0; 2];
+        let mut bitmap = FrameBitmap::new(&mut words);
+
+        bitmap.mark_free(1..128);
+        bitmap.mark_used(40..41);
+
+        assert_eq!(Some(64), bitmap.allocate_contiguous(32, 32));
+        assert_eq!(Some(8), bitmap.allocate_contiguous(32, 8));
+        assert_eq!(None, bitmap.allocate_contiguous(64, 64));
+        assert_eq!(Some(96), bitmap.allocate_contiguous(32, 32));
+    }
+}
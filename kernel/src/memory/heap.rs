@@ -1,21 +1,78 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{null_mut, NonNull};
 use essentials::address::VirtualAddress;
-use linked_list_allocator::LockedHeap;
+use essentials::sync::{PanicOnce, SpinMutex};
+use linked_list_allocator::{Heap, LockedHeap};
 use x86_64::paging::*;
 
 use crate::memory::flush::TableCacheFlush;
-use crate::memory::{MemoryMapper, NewMappingError};
+use crate::memory::slab::SlabCache;
+use crate::memory::{MemoryMapper, NewMappingError, Region, RegionKind};
 
 const HEAP_START: VirtualAddress = VirtualAddress::new(0x_4444_4444_0000);
-pub const HEAP_SIZE: usize = 100 * 1024;
+pub const INITIAL_HEAP_SIZE: usize = 100 * 1024;
+/// The heap grows up to this size, the whole range is reserved up front so growing never allocates.
+pub const MAX_HEAP_SIZE: usize = 256 * 1024 * 1024;
 
-pub fn map_heap(mapper: &mut MemoryMapper) -> Result<(), NewMappingError> {
-    const PAGE_SIZE: PageSize = PageSize::Size4Kib;
+const PAGE_SIZE: PageSize = PageSize::Size4Kib;
 
+/// The minimal number of bytes the heap grows by, so it does not map pages for every allocation.
+const MIN_GROW_SIZE: usize = 16 * 4096;
+
+/// The object sizes with their own slab cache, allocations up to the largest size are served by the smallest that fits.
+///
+/// Threads, connections and pipe buffers come and go all the time, their types assert they fit
+/// [`MAX_SLAB_OBJECT_SIZE`].
+const SLAB_SIZES: [usize; 8] = [16, 32, 64, 128, 256, 512, 1024, 2048];
+
+/// The largest allocation served by a slab cache, larger ones come from the heap itself.
+pub const MAX_SLAB_OBJECT_SIZE: usize = SLAB_SIZES[SLAB_SIZES.len() - 1];
+
+fn heap_flags() -> PageTableEntryFlags {
     let mut flags = PageTableEntryFlags::default();
     flags.set_writable(true);
     flags.set_present(true);
+    flags
+}
+
+/// The mapper of the heap's entry in the level 4 table, it has its own level 4 table which is never active.
+///
+/// The root map borrows the entry from it, so growing the heap does not depend on the root mapper being unlocked.
+static HEAP_MAPPER: SpinMutex<PanicOnce<MemoryMapper>> = SpinMutex::new(PanicOnce::new());
+
+/// Map the initial heap and lend it to the `root` map, before anything is allocated.
+pub fn map_heap(root: &mut MemoryMapper) -> Result<(), NewMappingError> {
+    let flags = heap_flags();
+
+    let mut mapper = root.new_mapper(false)?;
+    map_heap_pages(&mut mapper, HEAP_START, INITIAL_HEAP_SIZE)?;
+    mapper.lend_l4_entry(root, HEAP_START.indices()[0] as usize);
+
+    unsafe {
+        ALLOCATOR
+            .heap
+            .lock()
+            .init(HEAP_START.as_mut_ptr(), INITIAL_HEAP_SIZE);
+    }
+
+    // the region tree allocates, so the heap can only be recorded once it is initialized.
+    let pages = MAX_HEAP_SIZE.div_ceil(PAGE_SIZE.as_usize());
+    root.reserve_region(Region::new(HEAP_START, pages, RegionKind::Heap, flags))
+        .map_err(NewMappingError::Region)?;
+
+    HEAP_MAPPER.lock().initialize_with(mapper);
+
+    Ok(())
+}
+
+fn map_heap_pages(
+    mapper: &mut MemoryMapper,
+    start: VirtualAddress,
+    size: usize,
+) -> Result<(), NewMappingError> {
+    let flags = heap_flags();
 
-    for page_addr in (HEAP_START.as_usize()..HEAP_START.as_usize() + HEAP_SIZE)
+    for page_addr in (start.as_usize()..start.as_usize() + size)
         .step_by(PAGE_SIZE.as_usize())
         .map(VirtualAddress::new)
     {
@@ -24,12 +81,144 @@ pub fn map_heap(mapper: &mut MemoryMapper) -> Result<(), NewMappingError> {
         mapper.new_map(flags, flags, new_page)?.flush();
     }
 
-    unsafe {
-        ALLOCATOR.lock().init(HEAP_START.as_mut_ptr(), HEAP_SIZE);
+    Ok(())
+}
+
+/// How much of the kernel heap is in use.
+#[derive(Debug, Clone, Copy)]
+pub struct HeapUsage {
+    /// The number of bytes mapped for the heap.
+    pub size: usize,
+    /// The number of bytes allocated, objects free in the slab caches are not counted.
+    pub used: usize,
+    /// The number of bytes held by the slab caches, in use or not.
+    pub slabs: usize,
+}
+
+/// The kernel allocator, slab caches for small objects in front of a linked list heap that grows on demand.
+pub struct KernelHeap {
+    heap: LockedHeap,
+    slabs: [SlabCache; SLAB_SIZES.len()],
+}
+
+impl KernelHeap {
+    const fn new() -> Self {
+        Self {
+            heap: LockedHeap::empty(),
+            slabs: [
+                SlabCache::new(SLAB_SIZES[0]),
+                SlabCache::new(SLAB_SIZES[1]),
+                SlabCache::new(SLAB_SIZES[2]),
+                SlabCache::new(SLAB_SIZES[3]),
+                SlabCache::new(SLAB_SIZES[4]),
+                SlabCache::new(SLAB_SIZES[5]),
+                SlabCache::new(SLAB_SIZES[6]),
+                SlabCache::new(SLAB_SIZES[7]),
+            ],
+        }
     }
 
-    Ok(())
+    pub fn usage(&self) -> HeapUsage {
+        let (slabs, slabs_free) = self
+            .slabs
+            .iter()
+            .map(SlabCache::usage)
+            .fold((0, 0), |(total, free), (size, slab_free)| {
+                (total + size, free + slab_free)
+            });
+
+        let heap = self.heap.lock();
+
+        HeapUsage {
+            size: heap.size(),
+            used: heap.used() - slabs_free,
+            slabs,
+        }
+    }
+
+    /// The slab cache serving `layout`, `None` when it is too large for all of them.
+    fn slab_for(&self, layout: Layout) -> Option<&SlabCache> {
+        let size = layout.size().max(layout.align());
+        self.slabs.iter().find(|slab| slab.object_size() >= size)
+    }
+
+    fn allocate_from_heap(&self, layout: Layout) -> Option<NonNull<u8>> {
+        let mut heap = self.heap.lock();
+
+        if let Ok(ptr) = heap.allocate_first_fit(layout) {
+            return Some(ptr);
+        }
+
+        Self::grow(&mut heap, layout).ok()?;
+        heap.allocate_first_fit(layout).ok()
+    }
+
+    /// Map enough pages at the end of the heap to fit `layout`.
+    ///
+    /// The pages are mapped below the heap's entry in the level 4 table, which every address space borrows, so they
+    /// are visible everywhere at once.
+    /// Only the heap mapper is locked, which never allocates from the heap.
+    fn grow(heap: &mut Heap, layout: Layout) -> Result<(), NewMappingError> {
+        // the new memory may not be merged with the last free block, so it has to fit the layout on its own.
+        let bytes = (layout.size() + layout.align())
+            .max(MIN_GROW_SIZE)
+            .next_multiple_of(PAGE_SIZE.as_usize());
+
+        let top = VirtualAddress::from(heap.top());
+
+        if top.as_usize() + bytes > HEAP_START.as_usize() + MAX_HEAP_SIZE {
+            return Err(NewMappingError::OutOfFrames);
+        }
+
+        map_heap_pages(&mut HEAP_MAPPER.lock(), top, bytes)?;
+
+        unsafe { heap.extend(bytes) };
+
+        Ok(())
+    }
+}
+
+unsafe impl GlobalAlloc for KernelHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = match self.slab_for(layout) {
+            Some(slab) => slab.allocate(|| self.allocate_from_heap(SlabCache::slab_layout())),
+            None => self.allocate_from_heap(layout),
+        };
+
+        ptr.map_or(null_mut(), |ptr| ptr.as_ptr())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let ptr = NonNull::new_unchecked(ptr);
+
+        match self.slab_for(layout) {
+            Some(slab) => slab.free(ptr),
+            None => self.heap.lock().deallocate(ptr, layout),
+        }
+    }
 }
 
 #[global_allocator]
-static ALLOCATOR: LockedHeap = LockedHeap::empty();
+pub static ALLOCATOR: KernelHeap = KernelHeap::new();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+    use core::mem::size_of;
+
+    use crate::memory::ROOT_MAPPER;
+
+    #[test_case]
+    fn test_grows_past_initial_size() {
+        let size = ALLOCATOR.usage().size;
+
+        // the root mapper being locked by the allocating code does not stop the heap from growing.
+        let root = ROOT_MAPPER.lock();
+        let values: Vec<usize> = (0..INITIAL_HEAP_SIZE).collect();
+        drop(root);
+
+        assert!(ALLOCATOR.usage().size >= size + INITIAL_HEAP_SIZE * size_of::<usize>());
+        assert!(values.iter().enumerate().all(|(i, value)| i == *value));
+    }
+}
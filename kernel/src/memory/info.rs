@@ -6,6 +6,9 @@ pub struct MemoryInfo {
     pub allocated: usize,
     pub total_size: usize,
     pub kernel: usize,
+    /// The bytes mapped for the kernel heap, and the bytes of it that are allocated.
+    pub heap_size: usize,
+    pub heap_used: usize,
 }
 
 impl Debug for MemoryInfo {
@@ -16,7 +19,8 @@ impl Debug for MemoryInfo {
         writeln!(f, "\tkernel:    {}", ReadableSize::new(self.kernel))?;
         writeln!(f, "\tusable:    {}", ReadableSize::new(self.usable))?;
         writeln!(f, "\tallocated: {}", ReadableSize::new(self.allocated))?;
-        write!(f,   "\tfree:      {}", ReadableSize::new(self.usable - self.allocated))?;
+        writeln!(f, "\tfree:      {}", ReadableSize::new(self.usable - self.allocated))?;
+        write!(f,   "\theap:      {} of {}", ReadableSize::new(self.heap_used), ReadableSize::new(self.heap_size))?;
         Ok(())
     }
 }
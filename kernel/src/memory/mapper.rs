@@ -1,12 +1,19 @@
+use alloc::vec::Vec;
+use core::cmp::min;
 use core::fmt::Display;
+use core::ops::Range;
 use essentials::address::*;
+use essentials::collections::FixedVec;
+use essentials::sync::{PanicOnce, SpinMutex};
 
 use page_walker::*;
 use x86_64::paging::*;
 
-use crate::memory::flush::{TableCacheFlush, TableListCacheFlush};
+use crate::arch::x86_64::tlb::{self, CpuSet};
+use crate::memory::flush::{PageRangeCacheFlush, TableCacheFlush, TableListCacheFlush};
 use crate::memory::frame_allocator::FrameAllocator;
 use crate::memory::mapper::tree_display::MemoryMapTreeDisplay;
+use crate::memory::regions::{Region, RegionError, RegionKind, RegionTree};
 
 mod page_walker;
 mod tree_display;
@@ -16,12 +23,25 @@ pub enum NewMappingError {
     AlreadyMapped,
     OutOfFrames,
     NotOwned,
+    /// The physical address is not aligned to the size of the page.
+    Unaligned,
+    /// The pages could not be reserved in the region tree.
+    Region(RegionError),
+    /// The address is not part of a lazily backed region.
+    NotReserved,
 }
 
 #[derive(Debug, Clone, Copy)]
 pub enum ModifyMappingError {
     NotOwned,
     NotMapped,
+    OutOfFrames,
+    /// The address is not part of a huge page, so it cannot be split.
+    NotHuge,
+    /// The address is part of a huge page, which has to be split first, see [`MemoryMapper::split_huge_page`].
+    Huge,
+    /// The page is not shared copy-on-write.
+    NotCopyOnWrite,
 }
 
 impl From<WalkError> for ModifyMappingError {
@@ -50,6 +70,11 @@ pub struct MemoryMapper {
     frame_allocator: &'static FrameAllocator,
     l4_page: PhysicalPage,
     global_offset: u64,
+    /// Whether the tables were allocated by this mapper, which is not the case for the tables set up by the bootloader.
+    owns_tables: bool,
+    regions: RegionTree,
+    /// The frames and tables unmapped since the last flush, they are freed by the flush, see [`PageRangeCacheFlush`].
+    released: Vec<PhysicalPage>,
 }
 
 impl MemoryMapper {
@@ -70,6 +95,9 @@ impl MemoryMapper {
             frame_allocator,
             l4_page,
             global_offset,
+            owns_tables: false,
+            regions: RegionTree::new(Self::region_window()),
+            released: Vec::new(),
         }
     }
 
@@ -78,6 +106,11 @@ impl MemoryMapper {
         a.into()
     }
 
+    /// The virtual address at which the complete physical memory is mapped.
+    pub fn physical_memory_offset(&self) -> u64 {
+        self.global_offset
+    }
+
     /// Get the physical address from a virtual address.
     pub fn translate_virtual_to_physical(&self, addr: VirtualAddress) -> Option<PhysicalAddress> {
         let last_entry = self.walk_entries(addr).last()?.ok()?;
@@ -129,7 +162,7 @@ impl MemoryMapper {
     pub fn new_mapper(&self, inherit: bool) -> Result<Self, NewMappingError> {
         let new_frame = self
             .frame_allocator
-            .allocate_new_page_table()
+            .allocate_frame()
             .ok_or(NewMappingError::OutOfFrames)?;
 
         let table = unsafe { self.deref_page_table_mut(new_frame.addr()) };
@@ -148,11 +181,17 @@ impl MemoryMapper {
             table.zero();
         }
 
-        Ok(Self {
+        let mut mapper = Self {
             l4_page: new_frame,
             frame_allocator: self.frame_allocator,
             global_offset: self.global_offset,
-        })
+            owns_tables: true,
+            regions: RegionTree::new(Self::region_window()),
+            released: Vec::new(),
+        };
+
+        mapper.reserve_inherited_regions();
+        Ok(mapper)
     }
 
     /// Create a new `MemoryMapper`.
@@ -175,7 +214,7 @@ impl MemoryMapper {
 
         let new_frame = self
             .frame_allocator
-            .allocate_new_page_table()
+            .allocate_frame()
             .ok_or(NewMappingError::OutOfFrames)?;
 
         let table = unsafe { self.deref_page_table_mut(new_frame.addr()) };
@@ -187,20 +226,427 @@ impl MemoryMapper {
             table.zero();
         }
 
-        Ok(Self {
+        let mut mapper = Self {
             l4_page: new_frame,
             frame_allocator: self.frame_allocator,
             global_offset: self.global_offset,
-        })
+            owns_tables: true,
+            regions: RegionTree::new(Self::region_window()),
+            released: Vec::new(),
+        };
+
+        mapper.reserve_inherited_regions();
+        Ok(mapper)
+    }
+
+    /// Let `target` use the mappings below the l4 entry at `index`, which stay owned by this mapper.
+    ///
+    /// The entry is borrowed in `target`, so only this mapper changes the mappings below it, which `target` sees at once.
+    pub fn lend_l4_entry(&self, target: &mut MemoryMapper, index: usize) {
+        let mut entry = self.deref_l4_page_table()[index];
+        let target_entry = &mut target.deref_l4_page_table_mut()[index];
+
+        assert!(
+            !target_entry.flags().present(),
+            "The l4 entry is already in use"
+        );
+
+        let mut borrow_flag = PageTableEntryFlags::default();
+        borrow_flag.set_borrowed(true);
+        entry.set_flags(entry.flags() | borrow_flag);
+
+        *target_entry = entry;
+    }
+
+    /// The range in which [`MemoryMapper::map_region`] places regions without a fixed address, the lower half without the first l4 entry.
+    fn region_window() -> Range<VirtualAddress> {
+        VirtualAddress::from_l4_index(1)..VirtualAddress::from_l4_index(256)
+    }
+
+    /// Record the borrowed l4 entries in the lower half as regions, so no other region is placed in them.
+    fn reserve_inherited_regions(&mut self) {
+        const L4_ENTRY_PAGES: usize = 512 * 512 * 512;
+
+        for index in 0..256 {
+            let entry = self.deref_l4_page_table()[index];
+
+            if !entry.flags().present() || !entry.flags().borrowed() {
+                continue;
+            }
+
+            let start = VirtualAddress::from_l4_index(index as u16);
+            let region = Region::new(start, L4_ENTRY_PAGES, RegionKind::Inherited, entry.flags());
+
+            self.regions
+                .insert(region)
+                .expect("l4 entries cannot overlap");
+        }
+    }
+
+    /// The regions in use in this address space.
+    pub fn regions(&self) -> &RegionTree {
+        &self.regions
+    }
+
+    /// Record a region that is mapped by other means, e.g. before the kernel heap is available.
+    pub fn reserve_region(&mut self, region: Region) -> Result<(), RegionError> {
+        self.regions.insert(region)
+    }
+
+    /// Reserve a region of `pages` pages without mapping them, each page is mapped to a zeroed frame when it is first touched, see [`MemoryMapper::populate`].
+    ///
+    /// The region starts at `start` when given, otherwise the lowest free range in the lower half is used.
+    pub fn reserve_lazy_region(
+        &mut self,
+        start: Option<VirtualAddress>,
+        pages: usize,
+        kind: RegionKind,
+        flags: PageTableEntryFlags,
+    ) -> Result<Region, NewMappingError> {
+        let start = self.region_start(start, pages)?;
+        let region = Region::new_lazy(start, pages, kind, flags);
+        self.regions
+            .insert(region)
+            .map_err(NewMappingError::Region)?;
+
+        Ok(region)
+    }
+
+    /// Back the page containing `addr` with a zeroed frame, when it is part of a lazy region.
+    ///
+    /// This is called from the page fault handler, and by the kernel before it accesses memory of a service.
+    pub fn populate(
+        &mut self,
+        addr: VirtualAddress,
+    ) -> Result<impl TableCacheFlush, NewMappingError> {
+        let region = self
+            .regions
+            .find(addr)
+            .filter(|region| region.is_lazy())
+            .copied()
+            .ok_or(NewMappingError::NotReserved)?;
+
+        let page = VirtualPage::new(addr, PageSize::Size4Kib);
+        let cache_flush = self.new_map(region.flags(), region.flags(), page)?;
+        self.zero_page(page);
+
+        Ok(cache_flush)
+    }
+
+    fn region_start(
+        &self,
+        start: Option<VirtualAddress>,
+        pages: usize,
+    ) -> Result<VirtualAddress, NewMappingError> {
+        match start {
+            Some(start) => Ok(start),
+            None => self
+                .regions
+                .find_free(pages, 0)
+                .map_err(NewMappingError::Region),
+        }
+    }
+
+    /// Reserve a region of `pages` pages and map each page to a new zeroed frame.
+    ///
+    /// The region starts at `start` when given, otherwise the lowest free range in the lower half is used.
+    pub fn map_region(
+        &mut self,
+        start: Option<VirtualAddress>,
+        pages: usize,
+        kind: RegionKind,
+        flags: PageTableEntryFlags,
+    ) -> Result<(Region, impl TableCacheFlush), NewMappingError> {
+        let start = self.region_start(start, pages)?;
+        let region = Region::new(start, pages, kind, flags);
+        self.regions
+            .insert(region)
+            .map_err(NewMappingError::Region)?;
+
+        let mut page = region.first_page();
+
+        for i in 0..pages {
+            if let Err(err) = self.new_map(flags, flags, page) {
+                self.regions.remove(region.start());
+
+                // the pages before the failing page were mapped by this call, so they are owned.
+                self.unmap_range(region.first_page(), i)
+                    .expect("the newly mapped pages should be owned")
+                    .flush();
+
+                return Err(err);
+            }
+
+            self.zero_page(page);
+            page = page.next();
+        }
+
+        Ok((region, PageRangeCacheFlush::new(region.first_page(), pages)))
+    }
+
+    /// Fill the frame `page` is mapped to with zeroes, through the physical memory mapping so the map does not need to be active.
+    fn zero_page(&self, page: VirtualPage) {
+        let frame = self
+            .translate_virtual_to_physical(page.addr())
+            .expect("the page should be mapped");
+
+        self.zero_frame(PhysicalPage::new(frame, page.size()));
+    }
+
+    /// Fill a frame with zeroes through the physical memory mapping.
+    ///
+    /// The frame should not be referenced by anything else yet, e.g. right after it is allocated.
+    pub fn zero_frame(&self, frame: PhysicalPage) {
+        let ptr: *mut u8 = self.translate_table_frame(frame.addr()).as_mut_ptr();
+
+        // Safety: all physical memory is mapped at the global offset.
+        unsafe { ptr.write_bytes(0, frame.size().as_usize()) };
+    }
+
+    /// Copy `data` to the pages mapped from `addr` on, through the physical memory mapping so the map does not need to be active.
+    pub fn write_bytes(&self, addr: VirtualAddress, data: &[u8]) {
+        let page_size = PageSize::Size4Kib.as_usize();
+        let mut written = 0;
+
+        while written < data.len() {
+            let current = addr + written;
+            let frame = self
+                .translate_virtual_to_physical(current)
+                .expect("the pages should be mapped");
+
+            let length = min(
+                data.len() - written,
+                page_size - current.as_usize() % page_size,
+            );
+            let destination: *mut u8 = self.translate_table_frame(frame).as_mut_ptr();
+
+            // Safety: all physical memory is mapped at the global offset, and the length stays within the frame.
+            unsafe { destination.copy_from_nonoverlapping(data[written..].as_ptr(), length) };
+
+            written += length;
+        }
+    }
+
+    /// Reserve a region and map its pages to `frames`, which are 4KiB frames shared with other address spaces.
+    ///
+    /// Each mapping holds a reference to its frame, see [`FrameAllocator::share_frame`], so unmapping the region does not free frames still referenced elsewhere.
+    pub fn map_shared_frames(
+        &mut self,
+        start: Option<VirtualAddress>,
+        frames: &[PhysicalPage],
+        flags: PageTableEntryFlags,
+    ) -> Result<(Region, impl TableCacheFlush), NewMappingError> {
+        let start = self.region_start(start, frames.len())?;
+        let region = Region::new(start, frames.len(), RegionKind::Shared, flags);
+        self.regions
+            .insert(region)
+            .map_err(NewMappingError::Region)?;
+
+        let mut page_flags = flags;
+        page_flags.set_owns_frame(true);
+
+        let mut page = region.first_page();
+
+        for (i, frame) in frames.iter().enumerate() {
+            self.frame_allocator.share_frame(*frame);
+
+            // Safety: the frame stays valid for as long as a reference to it remains.
+            let result = unsafe { self.map_to(page_flags, flags, page, frame.addr()) };
+
+            if let Err(err) = result {
+                self.frame_allocator.free_page(*frame);
+                self.regions.remove(region.start());
+
+                self.unmap_range(region.first_page(), i)
+                    .expect("the newly mapped pages should be owned")
+                    .flush();
+
+                return Err(err);
+            }
+
+            page = page.next();
+        }
+
+        Ok((
+            region,
+            PageRangeCacheFlush::new(region.first_page(), frames.len()),
+        ))
+    }
+
+    /// Map the pages of the region starting at `start` into `target` at the same addresses, sharing their frames copy-on-write.
+    ///
+    /// Writable pages become read-only in both address spaces, until a write gives the writer its own copy, see [`MemoryMapper::copy_on_write`].
+    /// Other CPUs with this map active may keep writing to the shared frames until the shootdown of the returned flush completes,
+    /// see [`PageRangeCacheFlush::flush_remote`].
+    /// Pages of a lazy region that are not backed yet stay lazy in both address spaces.
+    /// When an error occurs, the pages shared before it stay shared.
+    pub fn share_copy_on_write(
+        &mut self,
+        target: &mut MemoryMapper,
+        start: VirtualAddress,
+    ) -> Result<(Region, PageRangeCacheFlush), NewMappingError> {
+        let region = self
+            .regions
+            .find(start)
+            .filter(|region| region.start() == start)
+            .copied()
+            .ok_or(NewMappingError::NotReserved)?;
+
+        target
+            .regions
+            .insert(region)
+            .map_err(NewMappingError::Region)?;
+
+        let mut page = region.first_page();
+
+        for i in 0..region.pages() {
+            let entry = match self.leaf_entry_mut(page.addr()) {
+                Ok(entry) => entry,
+                Err(ModifyMappingError::NotMapped) => {
+                    page = page.next();
+                    continue;
+                }
+                // borrowed and huge pages cannot be shared.
+                Err(_) => {
+                    self.range_flush(region.first_page(), i).flush();
+                    return Err(NewMappingError::NotOwned);
+                }
+            };
+
+            let mut flags = entry.flags();
+            if flags.writable() {
+                flags.set_writable(false);
+                flags.set_copy_on_write(true);
+                entry.set_flags(flags);
+            }
+
+            let frame = entry.as_frame(1);
+            self.frame_allocator.share_frame(frame);
+
+            // Safety: the frame stays valid for as long as one of the address spaces references it.
+            let result = unsafe { target.map_to(flags, region.flags(), page, frame.addr()) };
+
+            if let Err(err) = result {
+                self.frame_allocator.free_page(frame);
+                self.range_flush(region.first_page(), i + 1).flush();
+                return Err(err);
+            }
+
+            page = page.next();
+        }
+
+        Ok((
+            region,
+            self.range_flush(region.first_page(), region.pages()),
+        ))
+    }
+
+    /// Give the page containing `addr` its own copy of a frame shared copy-on-write, and make it writable again.
+    ///
+    /// When no other address space references the frame anymore, it is made writable without a copy.
+    pub fn copy_on_write(
+        &mut self,
+        addr: VirtualAddress,
+    ) -> Result<PageRangeCacheFlush, ModifyMappingError> {
+        let frame_allocator = self.frame_allocator;
+        let mut entry = *self.leaf_entry_mut(addr)?;
+        let mut flags = entry.flags();
+
+        if !flags.copy_on_write() {
+            return Err(ModifyMappingError::NotCopyOnWrite);
+        }
+
+        let frame = entry.as_frame(1);
+
+        if frame_allocator.frame_references(frame) > 1 {
+            let copy = frame_allocator
+                .allocate_frame()
+                .ok_or(ModifyMappingError::OutOfFrames)?;
+
+            let source: *const u8 = self.translate_table_frame(frame.addr()).as_ptr();
+            let destination: *mut u8 = self.translate_table_frame(copy.addr()).as_mut_ptr();
+
+            // Safety: both frames are mapped through the physical memory mapping, and the copy is not referenced yet.
+            unsafe { destination.copy_from_nonoverlapping(source, frame.size().as_usize()) };
+
+            // other CPUs may still read the shared frame through the old mapping.
+            self.released.push(frame);
+            entry.set_addr(copy.addr());
+        }
+
+        flags.set_copy_on_write(false);
+        flags.set_writable(true);
+        entry.set_flags(flags);
+
+        *self.leaf_entry_mut(addr)? = entry;
+
+        Ok(self.range_flush(VirtualPage::new(addr, PageSize::Size4Kib), 1))
+    }
+
+    /// The level 1 entry mapping `addr`, in a table owned by this mapper.
+    fn leaf_entry_mut(
+        &mut self,
+        addr: VirtualAddress,
+    ) -> Result<&mut PageTableEntry, ModifyMappingError> {
+        let mut table = self.l4_page.addr();
+
+        for (level, index) in Self::iter_address(addr) {
+            // Safety: the tables are walked from the l4 table, so each one is a valid table.
+            let entry = &mut unsafe { self.deref_page_table_mut(table) }[index];
+
+            if !entry.flags().present() {
+                return Err(ModifyMappingError::NotMapped);
+            }
+
+            if level == 4 && entry.flags().borrowed() {
+                return Err(ModifyMappingError::NotOwned);
+            }
+
+            if level == 1 {
+                return Ok(entry);
+            }
+
+            if entry.flags().huge() {
+                return Err(ModifyMappingError::Huge);
+            }
+
+            table = entry.addr();
+        }
+
+        unreachable!("the walk ends at the level 1 table")
+    }
+
+    /// Unmap the pages of the region starting at `start` and forget the region.
+    pub fn unmap_region(
+        &mut self,
+        start: VirtualAddress,
+    ) -> Result<(Region, impl TableCacheFlush), ModifyMappingError> {
+        let region = self
+            .regions
+            .remove(start)
+            .ok_or(ModifyMappingError::NotMapped)?;
+
+        let cache_flush = self.unmap_range(region.first_page(), region.pages())?;
+        Ok((region, cache_flush))
     }
 
     fn map_to_inner(
         &mut self,
         flags: PageTableEntryFlags,
-        parent_flags: PageTableEntryFlags,
+        mut parent_flags: PageTableEntryFlags,
         new_page: VirtualPage,
         physical_address: PhysicalAddress,
     ) -> Result<impl TableCacheFlush, NewMappingError> {
+        let leaf_level = new_page.size().level();
+
+        if physical_address.as_u64() % new_page.size().as_usize() as u64 != 0 {
+            return Err(NewMappingError::Unaligned);
+        }
+
+        // tables are never huge, and do not own a frame that is part of the mapping.
+        parent_flags.set_huge(false);
+        parent_flags.set_owns_frame(false);
+
         let mut cache_flush = TableListCacheFlush::new();
         let mut table_frame = self.l4_page;
 
@@ -213,20 +659,24 @@ impl MemoryMapper {
                 return Err(NewMappingError::NotOwned);
             }
 
-            match (page_level, entry.flags().present()) {
-                (1, true) => {
+            match (page_level == leaf_level, entry.flags().present()) {
+                (true, true) => {
                     return Err(NewMappingError::AlreadyMapped);
                 }
-                (1, false) => {
+                (true, false) => {
+                    let mut flags = flags;
+                    flags.set_huge(leaf_level > 1);
+
                     entry.set_flags(flags);
                     entry.set_addr(physical_address);
                     table[index] = entry;
                     cache_flush.add_table(table_frame);
+                    break;
                 }
-                (_, false) => {
+                (false, false) => {
                     let allocated_page = self
                         .frame_allocator
-                        .allocate_new_page_table()
+                        .allocate_frame()
                         .ok_or(NewMappingError::OutOfFrames)?;
 
                     let new_table = unsafe { self.deref_page_table_mut(allocated_page.addr()) };
@@ -238,6 +688,10 @@ impl MemoryMapper {
                     table[index] = entry;
                     cache_flush.add_table(table_frame);
                 }
+                (false, true) if entry.flags().huge() => {
+                    // a larger page already covers the new page.
+                    return Err(NewMappingError::AlreadyMapped);
+                }
                 _ => {}
             }
 
@@ -262,19 +716,245 @@ impl MemoryMapper {
         self.map_to_inner(flags, parent_flags, new_page, physical_address)
     }
 
-    /// Creates a new mapping in the page table.
+    /// Creates a new mapping in the page table, to a new frame that is freed when the page is unmapped.
+    ///
+    /// Huge pages are backed by physically contiguous frames.
     pub fn new_map(
         &mut self,
-        flags: PageTableEntryFlags,
+        mut flags: PageTableEntryFlags,
         parent_flags: PageTableEntryFlags,
         new_page: VirtualPage,
     ) -> Result<impl TableCacheFlush, NewMappingError> {
         let frame = self
             .frame_allocator
-            .allocate_new_page_table()
+            .allocate_page(new_page.size())
             .ok_or(NewMappingError::OutOfFrames)?;
 
-        unsafe { self.map_to(flags, parent_flags, new_page, frame.addr()) }
+        flags.set_owns_frame(true);
+
+        let result = unsafe { self.map_to(flags, parent_flags, new_page, frame.addr()) };
+
+        if result.is_err() {
+            self.frame_allocator.free_page(frame);
+        }
+
+        result
+    }
+
+    /// Remove the mapping of `page`, the frame is freed when the mapping owns it, see [`MemoryMapper::new_map`].
+    ///
+    /// The frame is only freed by the returned flush, once no CPU can reach it anymore.
+    ///
+    /// Page tables that become empty are freed as well.
+    pub fn unmap(&mut self, page: VirtualPage) -> Result<impl TableCacheFlush, ModifyMappingError> {
+        self.unmap_inner(page)?;
+        Ok(self.range_flush(page, 1))
+    }
+
+    /// Remove the mappings of `count` pages starting at `start`, pages in the range that are not mapped are skipped.
+    ///
+    /// When a page is not owned, the pages before it are still unmapped and flushed.
+    pub fn unmap_range(
+        &mut self,
+        start: VirtualPage,
+        count: usize,
+    ) -> Result<impl TableCacheFlush, ModifyMappingError> {
+        let mut page = start;
+
+        for i in 0..count {
+            match self.unmap_inner(page) {
+                Ok(()) | Err(ModifyMappingError::NotMapped) => {}
+                Err(err) => {
+                    self.range_flush(start, i).flush();
+                    return Err(err);
+                }
+            }
+
+            page = page.next();
+        }
+
+        Ok(self.range_flush(start, count))
+    }
+
+    fn unmap_inner(&mut self, page: VirtualPage) -> Result<(), ModifyMappingError> {
+        let leaf_level = page.size().level();
+
+        // the tables from the l4 table down to the table holding the leaf entry, with the index used in each.
+        let mut path = FixedVec::<4, (PhysicalAddress, usize)>::new();
+        let mut table = self.l4_page.addr();
+
+        for (level, index) in Self::iter_address(page.addr()) {
+            let entry = unsafe { self.deref_page_table(table) }[index];
+
+            if !entry.flags().present() {
+                return Err(ModifyMappingError::NotMapped);
+            }
+
+            if level == 4 && entry.flags().borrowed() {
+                return Err(ModifyMappingError::NotOwned);
+            }
+
+            path.push((table, index));
+
+            if level == leaf_level {
+                break;
+            }
+
+            if entry.flags().huge() {
+                // the page is part of a larger page, which has to be unmapped as a whole.
+                return Err(ModifyMappingError::NotMapped);
+            }
+
+            table = entry.addr();
+        }
+
+        let (leaf_table, leaf_index) = path[path.len() - 1];
+        let leaf_table = unsafe { self.deref_page_table_mut(leaf_table) };
+        let entry = leaf_table[leaf_index];
+
+        if leaf_level > 1 && !entry.flags().huge() {
+            // the entry points to a table of smaller pages instead.
+            return Err(ModifyMappingError::NotMapped);
+        }
+
+        leaf_table[leaf_index] = PageTableEntry::default();
+
+        if entry.flags().owns_frame() {
+            self.released.push(entry.as_frame(leaf_level));
+        }
+
+        self.free_empty_tables(&path);
+
+        Ok(())
+    }
+
+    /// Replace the huge page containing `addr` by a table of pages one size smaller, which map the same frames with the same flags.
+    ///
+    /// This allows unmapping or changing parts of a huge page.
+    pub fn split_huge_page(
+        &mut self,
+        addr: VirtualAddress,
+    ) -> Result<impl TableCacheFlush, ModifyMappingError> {
+        let mut table = self.l4_page.addr();
+
+        for (level, index) in Self::iter_address(addr) {
+            let entry = unsafe { self.deref_page_table(table) }[index];
+
+            if !entry.flags().present() {
+                return Err(ModifyMappingError::NotMapped);
+            }
+
+            if level == 4 && entry.flags().borrowed() {
+                return Err(ModifyMappingError::NotOwned);
+            }
+
+            if level == 4 || !entry.flags().huge() {
+                table = entry.addr();
+                continue;
+            }
+
+            let new_table_frame = self
+                .frame_allocator
+                .allocate_frame()
+                .ok_or(ModifyMappingError::OutOfFrames)?;
+
+            let new_table = unsafe { self.deref_page_table_mut(new_table_frame.addr()) };
+            let small_size = PageSize::from_level(level - 1);
+
+            let mut small_flags = entry.flags();
+            small_flags.set_huge(level - 1 > 1);
+
+            for (i, small_entry) in new_table.iter_mut().enumerate() {
+                *small_entry =
+                    PageTableEntry::new(small_flags, entry.addr() + i * small_size.as_usize());
+            }
+
+            let mut table_flags = entry.flags();
+            table_flags.set_huge(false);
+            table_flags.set_owns_frame(false);
+
+            let parent = unsafe { self.deref_page_table_mut(table) };
+            parent[index] = PageTableEntry::new(table_flags, new_table_frame.addr());
+
+            let huge_page = VirtualPage::new(addr, PageSize::from_level(level));
+            return Ok(PageRangeCacheFlush::new(huge_page, 1));
+        }
+
+        Err(ModifyMappingError::NotHuge)
+    }
+
+    /// Free the tables on the `path` that no longer have any entries, from the bottom up.
+    ///
+    /// The l4 table is never freed, since it is the root of the memory map.
+    fn free_empty_tables(&mut self, path: &[(PhysicalAddress, usize)]) {
+        // the bootloader's tables cannot be told apart from the ones allocated since, so they are all kept.
+        if !self.owns_tables {
+            return;
+        }
+
+        for depth in (1..path.len()).rev() {
+            let (table, _) = path[depth];
+
+            let is_empty = unsafe { self.deref_page_table(table) }
+                .iter()
+                .all(|entry| !entry.flags().present());
+
+            if !is_empty {
+                break;
+            }
+
+            let (parent, parent_index) = path[depth - 1];
+            let parent = unsafe { self.deref_page_table_mut(parent) };
+            parent[parent_index] = PageTableEntry::default();
+
+            self.released
+                .push(PhysicalPage::new(table, PageSize::Size4Kib));
+        }
+    }
+
+    /// Flush `pages` pages starting at `start` on every CPU that may cache them, and free the pages released so far afterwards.
+    fn range_flush(&mut self, start: VirtualPage, pages: usize) -> PageRangeCacheFlush {
+        PageRangeCacheFlush::with_shootdown(
+            start,
+            pages,
+            self.remote_cpus(),
+            self.frame_allocator,
+            core::mem::take(&mut self.released),
+        )
+    }
+
+    /// The other CPUs which may cache translations of this memory map.
+    ///
+    /// The tables of the root map are borrowed by every address space, so they can be cached by any CPU.
+    fn remote_cpus(&self) -> CpuSet {
+        if self.owns_tables {
+            tlb::cpus_using(self.l4_page)
+        } else {
+            tlb::other_cpus()
+        }
+    }
+
+    /// Free all frames owned by the table at `table`, including the table itself.
+    ///
+    /// Safety:
+    /// The caller must ensure that the `table` parameter points to a valid page table of the given `level`, which is owned by this mapper.
+    unsafe fn free_table(&self, table: PhysicalAddress, level: u8) {
+        for entry in self.deref_page_table(table).iter() {
+            if !entry.flags().present() {
+                continue;
+            }
+
+            if level == 1 || entry.flags().huge() {
+                if entry.flags().owns_frame() {
+                    self.frame_allocator.free_page(entry.as_frame(level));
+                }
+            } else {
+                self.free_table(entry.addr(), level - 1);
+            }
+        }
+
+        self.frame_allocator
+            .free_page(PhysicalPage::new(table, PageSize::Size4Kib));
     }
 
     pub fn effective_flags(&self, addr: VirtualAddress) -> Option<(PageTableEntryFlags, PageSize)> {
@@ -302,6 +982,32 @@ impl MemoryMapper {
         Some((flags, size))
     }
 
+    /// The number of bytes mapped through the tables owned by this mapper, borrowed tables are not counted.
+    pub fn owned_memory(&self) -> usize {
+        self.deref_l4_page_table()
+            .iter()
+            .filter(|entry| entry.flags().present() && !entry.flags().borrowed())
+            // Safety: present entries of a valid table point to valid tables.
+            .map(|entry| unsafe { self.mapped_memory(entry.addr(), 3) })
+            .sum()
+    }
+
+    /// Safety:
+    /// The caller must ensure that the `table` parameter points to a valid page table of the given `level`.
+    unsafe fn mapped_memory(&self, table: PhysicalAddress, level: u8) -> usize {
+        self.deref_page_table(table)
+            .iter()
+            .filter(|entry| entry.flags().present())
+            .map(|entry| {
+                if level == 1 || entry.flags().huge() {
+                    PageSize::from_level(level).as_usize()
+                } else {
+                    self.mapped_memory(entry.addr(), level - 1)
+                }
+            })
+            .sum()
+    }
+
     fn deref_l4_page_table_mut(&mut self) -> &mut PageTable {
         // Safety: As stated in the constructor, the l4_page is guaranteed to point to valid data
         unsafe { self.deref_page_table_mut(self.l4_page.addr()) }
@@ -326,7 +1032,13 @@ impl MemoryMapper {
 
     /// Set the memory map to the address space. In x86_64 terms, this means setting the CR3 register.
     pub fn set_active(&self) {
-        unsafe { self.l4_page.make_active() }
+        unsafe { tlb::activate(self.l4_page) }
+    }
+
+    /// Whether the memory map is active on any CPU.
+    pub fn is_active_anywhere(&self) -> bool {
+        PhysicalPage::active().0.addr() == self.l4_page.addr()
+            || !tlb::cpus_using(self.l4_page).is_empty()
     }
 
     /// Display the memory map as a tree view for debugging purposes.
@@ -358,8 +1070,168 @@ impl MemoryMapper {
     }
 }
 
+/// The mapper of the kernel's own address space, services borrow its lower half through [`MemoryMapper::new_mapper`].
+pub static ROOT_MAPPER: SpinMutex<PanicOnce<MemoryMapper>> = SpinMutex::new(PanicOnce::new());
+
 impl Drop for MemoryMapper {
+    /// Free the frames of all owned entries, borrowed entries are left to their owner.
+    ///
+    /// The memory map must not be active on any CPU anymore.
     fn drop(&mut self) {
-        todo!("Clean up all owned frames")
+        if !self.owns_tables {
+            return;
+        }
+
+        assert!(
+            !self.is_active_anywhere(),
+            "The active memory map cannot be dropped"
+        );
+
+        for page in core::mem::take(&mut self.released) {
+            self.frame_allocator.free_page(page);
+        }
+
+        for entry in self.deref_l4_page_table().iter() {
+            if entry.flags().present() && !entry.flags().borrowed() {
+                // Safety: present entries of an owned table point to valid tables.
+                unsafe { self.free_table(entry.addr(), 3) };
+            }
+        }
+
+        self.frame_allocator.free_page(self.l4_page);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::{FRAME_ALLOCATOR, ROOT_MAPPER};
+
+    fn data_flags() -> PageTableEntryFlags {
+        let mut flags = PageTableEntryFlags::default();
+        flags.set_present(true);
+        flags.set_writable(true);
+        flags
+    }
+
+    fn allocated_frames() -> usize {
+        FRAME_ALLOCATOR.info().allocated / PageSize::Size4Kib.as_usize()
+    }
+
+    /// Map and unmap a page in a new mapper, returns the number of frames allocated while the page was mapped.
+    fn map_and_unmap(mapper: &mut MemoryMapper, page: VirtualPage) -> usize {
+        let before = allocated_frames();
+
+        mapper
+            .new_map(data_flags(), data_flags(), page)
+            .unwrap()
+            .flush();
+        let mapped = allocated_frames() - before;
+
+        mapper.unmap(page).unwrap().flush();
+        mapped
+    }
+
+    #[test_case]
+    fn test_unmap_frees_the_frame_and_empty_tables() {
+        let mut mapper = ROOT_MAPPER.lock().new_mapper(false).unwrap();
+        let page = VirtualPage::new(VirtualAddress::new(0x4000_0000), PageSize::Size4Kib);
+        let l4_index = page.addr().indices()[0] as usize;
+
+        // the first round fills the slab caches the mapper's bookkeeping uses, so the heap does not grow in the second.
+        map_and_unmap(&mut mapper, page);
+
+        let before = allocated_frames();
+
+        // the frame and the level 3, 2 and 1 tables leading to it.
+        assert_eq!(4, map_and_unmap(&mut mapper, page));
+        assert_eq!(before, allocated_frames());
+        assert!(!mapper.deref_l4_page_table()[l4_index].flags().present());
+        assert_eq!(None, mapper.translate_virtual_to_physical(page.addr()));
+    }
+
+    #[test_case]
+    fn test_drop_frees_all_owned_frames() {
+        let pages = [0x4000_0000, 0x4000_1000, 0x80_0000_0000]
+            .map(|addr| VirtualPage::new(VirtualAddress::new(addr), PageSize::Size4Kib));
+
+        let map_all = || {
+            let mut mapper = ROOT_MAPPER.lock().new_mapper(false).unwrap();
+
+            for page in pages {
+                mapper
+                    .new_map(data_flags(), data_flags(), page)
+                    .unwrap()
+                    .flush();
+            }
+
+            mapper
+        };
+
+        drop(map_all());
+        let before = allocated_frames();
+
+        let mapper = map_all();
+        // the l4 table, three frames, and the tables below two level 4 entries.
+        assert_eq!(before + 1 + 3 + 2 * 3, allocated_frames());
+
+        drop(mapper);
+        assert_eq!(before, allocated_frames());
+    }
+
+    #[test_case]
+    fn test_map_split_and_unmap_huge_page() {
+        let mut mapper = ROOT_MAPPER.lock().new_mapper(false).unwrap();
+        let addr = VirtualAddress::new(0x4000_0000);
+        let huge_page = VirtualPage::new(addr, PageSize::Size2Mib);
+
+        let mut flags = PageTableEntryFlags::default();
+        flags.set_present(true);
+        flags.set_writable(true);
+
+        mapper.new_map(flags, flags, huge_page).unwrap().flush();
+
+        let frame = mapper.translate_virtual_to_physical(addr).unwrap();
+        let last = addr + 511 * PageSize::Size4Kib.as_usize();
+        let last_frame = mapper.translate_virtual_to_physical(last);
+
+        assert_eq!(
+            Some(frame + 511 * PageSize::Size4Kib.as_usize()),
+            last_frame
+        );
+        assert!(matches!(
+            mapper.effective_flags(last),
+            Some((_, PageSize::Size2Mib))
+        ));
+
+        // a part of a huge page cannot be unmapped before it is split.
+        let first_page = VirtualPage::new(addr, PageSize::Size4Kib);
+        assert!(matches!(
+            mapper.unmap(first_page),
+            Err(ModifyMappingError::NotMapped)
+        ));
+
+        mapper.split_huge_page(last).unwrap().flush();
+
+        assert!(matches!(
+            mapper.split_huge_page(last),
+            Err(ModifyMappingError::NotHuge)
+        ));
+        assert!(matches!(
+            mapper.effective_flags(last),
+            Some((_, PageSize::Size4Kib))
+        ));
+        assert_eq!(last_frame, mapper.translate_virtual_to_physical(last));
+
+        // the pages of the split page are unmapped one by one.
+        mapper.unmap(first_page).unwrap().flush();
+
+        assert_eq!(None, mapper.translate_virtual_to_physical(addr));
+        assert_eq!(last_frame, mapper.translate_virtual_to_physical(last));
+
+        mapper.unmap_range(first_page, 512).unwrap().flush();
+
+        assert_eq!(None, mapper.translate_virtual_to_physical(last));
+        assert!(mapper.effective_flags(last).is_none());
     }
 }
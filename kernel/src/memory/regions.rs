@@ -0,0 +1,272 @@
+use alloc::collections::BTreeMap;
+use core::ops::Range;
+use essentials::address::VirtualAddress;
+use x86_64::paging::{PageSize, PageTableEntryFlags, VirtualPage};
+
+const PAGE_SIZE: PageSize = PageSize::Size4Kib;
+
+/// What a region of an address space is used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+    /// Shared with the mapper this address space was created from, e.g. the kernel.
+    Inherited,
+    Abi,
+    Heap,
+    Stack,
+    Code,
+    Anonymous,
+    Shared,
+    /// Never backed, so running into it faults instead of silently continuing in the region next to it.
+    Guard,
+}
+
+/// A range of pages in an address space, mapped with the same flags.
+#[derive(Debug, Clone, Copy)]
+pub struct Region {
+    start: VirtualAddress,
+    pages: usize,
+    kind: RegionKind,
+    flags: PageTableEntryFlags,
+    /// Whether the pages are only backed by a frame once they are touched.
+    lazy: bool,
+}
+
+impl Region {
+    /// A region of `pages` 4KiB pages, `start` is aligned down to a page.
+    pub fn new(
+        start: VirtualAddress,
+        pages: usize,
+        kind: RegionKind,
+        flags: PageTableEntryFlags,
+    ) -> Self {
+        Self {
+            start: VirtualPage::new(start, PAGE_SIZE).addr(),
+            pages,
+            kind,
+            flags,
+            lazy: false,
+        }
+    }
+
+    /// A region whose pages are mapped on first access, see [`super::MemoryMapper::populate`].
+    pub fn new_lazy(
+        start: VirtualAddress,
+        pages: usize,
+        kind: RegionKind,
+        flags: PageTableEntryFlags,
+    ) -> Self {
+        Self {
+            lazy: true,
+            ..Self::new(start, pages, kind, flags)
+        }
+    }
+
+    pub fn start(&self) -> VirtualAddress {
+        self.start
+    }
+
+    pub fn end(&self) -> VirtualAddress {
+        self.start + self.size()
+    }
+
+    pub fn first_page(&self) -> VirtualPage {
+        VirtualPage::new(self.start, PAGE_SIZE)
+    }
+
+    pub fn pages(&self) -> usize {
+        self.pages
+    }
+
+    pub fn size(&self) -> usize {
+        self.pages * PAGE_SIZE.as_usize()
+    }
+
+    pub fn kind(&self) -> RegionKind {
+        self.kind
+    }
+
+    pub fn flags(&self) -> PageTableEntryFlags {
+        self.flags
+    }
+
+    pub fn is_lazy(&self) -> bool {
+        self.lazy
+    }
+
+    pub fn contains(&self, addr: VirtualAddress) -> bool {
+        self.range().contains(&addr.as_usize())
+    }
+
+    fn range(&self) -> Range<usize> {
+        self.start.as_usize()..self.end().as_usize()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum RegionError {
+    /// The region overlaps a region that is already in use.
+    Overlaps,
+    /// There is no free range large enough for the region.
+    OutOfSpace,
+    /// The region has no pages.
+    Empty,
+}
+
+/// Keeps track of the regions in use in an address space, ordered by their start address.
+///
+/// The tree only records regions, mapping the pages is up to the [`super::MemoryMapper`] owning it.
+pub struct RegionTree {
+    regions: BTreeMap<usize, Region>,
+    /// The range in which free regions are allocated.
+    window: Range<usize>,
+}
+
+impl RegionTree {
+    pub fn new(window: Range<VirtualAddress>) -> Self {
+        Self {
+            regions: BTreeMap::new(),
+            window: window.start.as_usize()..window.end.as_usize(),
+        }
+    }
+
+    /// Record `region`, as long as it does not overlap any region already in use.
+    pub fn insert(&mut self, region: Region) -> Result<(), RegionError> {
+        if region.pages == 0 {
+            return Err(RegionError::Empty);
+        }
+
+        let range = region.range();
+
+        let overlaps = self
+            .regions
+            .range(..range.end)
+            .next_back()
+            .is_some_and(|(_, before)| before.range().end > range.start);
+
+        if overlaps {
+            return Err(RegionError::Overlaps);
+        }
+
+        self.regions.insert(range.start, region);
+        Ok(())
+    }
+
+    /// Find the lowest free range of `pages` pages in the window, aligned to `alignment` bytes.
+    pub fn find_free(&self, pages: usize, alignment: usize) -> Result<VirtualAddress, RegionError> {
+        if pages == 0 {
+            return Err(RegionError::Empty);
+        }
+
+        let size = pages * PAGE_SIZE.as_usize();
+        let alignment = alignment.max(PAGE_SIZE.as_usize());
+        let mut start = self.window.start.next_multiple_of(alignment);
+
+        for region in self.regions.values() {
+            let range = region.range();
+
+            if range.end <= start {
+                continue;
+            }
+
+            if start + size <= range.start {
+                break;
+            }
+
+            start = range.end.next_multiple_of(alignment);
+        }
+
+        if start + size > self.window.end {
+            return Err(RegionError::OutOfSpace);
+        }
+
+        Ok(start.into())
+    }
+
+    /// Whether `region` lies in the window, i.e. in the part of the address space regions are handed out from.
+    pub fn in_window(&self, region: &Region) -> bool {
+        let range = region.range();
+        self.window.start <= range.start && range.end <= self.window.end
+    }
+
+    /// Remove the region starting at `start`.
+    pub fn remove(&mut self, start: VirtualAddress) -> Option<Region> {
+        self.regions.remove(&start.as_usize())
+    }
+
+    /// The region containing `addr`.
+    pub fn find(&self, addr: VirtualAddress) -> Option<&Region> {
+        self.regions
+            .range(..=addr.as_usize())
+            .next_back()
+            .map(|(_, region)| region)
+            .filter(|region| region.contains(addr))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Region> {
+        self.regions.values()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE: usize = 4096;
+
+    fn tree() -> RegionTree {
+        RegionTree::new(VirtualAddress::new(0x10 * PAGE)..VirtualAddress::new(0x20 * PAGE))
+    }
+
+    fn region(page: usize, pages: usize) -> Region {
+        Region::new(
+            VirtualAddress::new(page * PAGE),
+            pages,
+            RegionKind::Anonymous,
+            PageTableEntryFlags::default(),
+        )
+    }
+
+    #[test_case]
+    fn test_insert_rejects_overlap() {
+        let mut tree = tree();
+
+        assert!(tree.insert(region(0x12, 2)).is_ok());
+        assert!(tree.insert(region(0x14, 1)).is_ok());
+        assert!(matches!(
+            tree.insert(region(0x13, 1)),
+            Err(RegionError::Overlaps)
+        ));
+        assert!(matches!(
+            tree.insert(region(0x10, 3)),
+            Err(RegionError::Overlaps)
+        ));
+        assert!(tree.insert(region(0x10, 2)).is_ok());
+
+        assert!(tree.find(VirtualAddress::new(0x13 * PAGE + 8)).is_some());
+        assert!(tree.find(VirtualAddress::new(0x15 * PAGE)).is_none());
+    }
+
+    #[test_case]
+    fn test_find_free_first_fit() {
+        let mut tree = tree();
+
+        tree.insert(region(0x11, 1)).unwrap();
+        assert_eq!(0x10 * PAGE, tree.find_free(1, PAGE).unwrap().as_usize());
+        tree.insert(region(0x10, 1)).unwrap();
+
+        let aligned = tree.find_free(2, 4 * PAGE).unwrap();
+        assert_eq!(0x14 * PAGE, aligned.as_usize());
+        tree.insert(region(0x14, 2)).unwrap();
+
+        assert_eq!(0x12 * PAGE, tree.find_free(2, PAGE).unwrap().as_usize());
+        tree.insert(region(0x12, 2)).unwrap();
+
+        assert!(matches!(
+            tree.find_free(16, PAGE),
+            Err(RegionError::OutOfSpace)
+        ));
+
+        tree.remove(aligned);
+        assert!(tree.find_free(10, PAGE).is_ok());
+    }
+}
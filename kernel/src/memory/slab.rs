@@ -0,0 +1,128 @@
+use core::alloc::Layout;
+use core::mem::size_of;
+use core::ptr::NonNull;
+use essentials::sync::SpinMutex;
+
+/// The size of the slabs objects are carved from.
+pub const SLAB_SIZE: usize = 4096;
+
+/// A freed object, which holds the link to the next free object in its own memory.
+struct FreeObject {
+    next: Option<NonNull<FreeObject>>,
+}
+
+struct FreeList {
+    head: Option<NonNull<FreeObject>>,
+    /// The number of objects carved from slabs, whether they are in use or not.
+    objects: usize,
+    free: usize,
+}
+
+// Safety: the free objects are only reachable through the list, which is behind a lock.
+unsafe impl Send for FreeList {}
+
+/// Hands out objects of a single size, carved from slabs taken from the heap.
+///
+/// Freed objects go on a free list and are handed out again first, so objects that are created and dropped all the
+/// time do not fragment the heap. Slabs are never given back.
+pub struct SlabCache {
+    object_size: usize,
+    list: SpinMutex<FreeList>,
+}
+
+impl SlabCache {
+    /// A cache of objects of `object_size` bytes, which must be a power of two of at least a pointer and at most a slab.
+    ///
+    /// Objects are aligned to their size, since the slabs are aligned to theirs.
+    pub const fn new(object_size: usize) -> Self {
+        assert!(object_size.is_power_of_two());
+        assert!(object_size >= size_of::<FreeObject>() && object_size <= SLAB_SIZE);
+
+        Self {
+            object_size,
+            list: SpinMutex::new(FreeList {
+                head: None,
+                objects: 0,
+                free: 0,
+            }),
+        }
+    }
+
+    pub fn object_size(&self) -> usize {
+        self.object_size
+    }
+
+    /// The layout of the slabs `allocate` asks for.
+    pub fn slab_layout() -> Layout {
+        Layout::from_size_align(SLAB_SIZE, SLAB_SIZE).unwrap()
+    }
+
+    /// Take an object, `new_slab` is asked for a slab of [`SlabCache::slab_layout`] when no object is free.
+    pub fn allocate(&self, new_slab: impl FnOnce() -> Option<NonNull<u8>>) -> Option<NonNull<u8>> {
+        let mut list = self.list.lock();
+
+        if list.head.is_none() {
+            let slab = new_slab()?;
+
+            for index in (0..SLAB_SIZE / self.object_size).rev() {
+                // Safety: the object lies in the slab, which is unused and aligned to the object size.
+                unsafe { Self::push(&mut list, slab.byte_add(index * self.object_size).cast()) };
+                list.objects += 1;
+            }
+        }
+
+        let object = list.head?;
+        // Safety: objects on the free list hold a valid link.
+        list.head = unsafe { object.as_ref().next };
+        list.free -= 1;
+
+        Some(object.cast())
+    }
+
+    /// Return an object to the cache.
+    ///
+    /// # Safety
+    ///
+    /// `object` must have been handed out by this cache, and must not be used anymore.
+    pub unsafe fn free(&self, object: NonNull<u8>) {
+        let mut list = self.list.lock();
+        unsafe { Self::push(&mut list, object.cast()) };
+    }
+
+    /// The number of bytes carved from slabs, and the number of those bytes that are free.
+    pub fn usage(&self) -> (usize, usize) {
+        let list = self.list.lock();
+        (
+            list.objects * self.object_size,
+            list.free * self.object_size,
+        )
+    }
+
+    unsafe fn push(list: &mut FreeList, object: NonNull<FreeObject>) {
+        unsafe { object.write(FreeObject { next: list.head }) };
+        list.head = Some(object);
+        list.free += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::alloc::alloc;
+
+    #[test_case]
+    fn test_reuses_freed_objects() {
+        let cache = SlabCache::new(256);
+        let new_slab = || NonNull::new(unsafe { alloc(SlabCache::slab_layout()) });
+
+        let first = cache.allocate(new_slab).unwrap();
+        let second = cache.allocate(|| None).unwrap();
+
+        assert_ne!(first, second);
+        assert_eq!(0, first.as_ptr() as usize % 256);
+        assert_eq!((SLAB_SIZE, SLAB_SIZE - 2 * 256), cache.usage());
+
+        unsafe { cache.free(first) };
+        assert_eq!(Some(first), cache.allocate(|| None));
+    }
+}
@@ -1 +1,3 @@
 pub mod scheduler;
+pub mod sync;
+pub mod wait_queue;
@@ -1,66 +1,192 @@
+pub use accounting::*;
+use alloc::boxed::Box;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::time::Duration;
 use essentials::collections::FixedVec;
 use essentials::sync::SpinMutex;
+pub use extended_state::*;
+pub use idle::*;
+pub use run_queue::*;
 pub use stack::*;
+use syscall::{Priority, ServiceStats, ThreadInfo};
 pub use thread::*;
+use x86_64::instructions::read_timestamp_counter;
 use x86_64::interrupts::context::InterruptedContext;
-use x86_64::interrupts::int3;
 
-use crate::service::{ServiceRef, SERVICE_TABLE};
+use crate::arch::x86_64::devices::TICK_FREQUENCY;
+use crate::arch::x86_64::init::raise_yield_interrupt;
+use crate::arch::x86_64::smp::{current_cpu, online_cpus, CpuId, MAX_CPUS};
+use crate::multi_tasking::wait_queue::Waiter;
+use crate::service::{Id, ServiceRef, SERVICE_TABLE};
 
+mod accounting;
+mod extended_state;
+mod idle;
+mod run_queue;
 mod stack;
 mod thread;
 
-pub struct ThreadBlocker {
-    thread_id: ThreadId,
-    last_thread_id: ThreadId,
-    scheduler: &'static Scheduler,
-}
+/// The number of timer interrupts since the scheduler started.
+pub type Tick = u64;
 
-impl ThreadBlocker {
-    pub fn unblock_one(mut self) -> Option<ThreadBlocker> {
-        let mut tasks = self.scheduler.tasks.lock();
-        if let Some(next) = tasks[self.thread_id].unblock() {
-            self.thread_id = next;
-            Some(self)
-        } else {
-            None
-        }
-    }
+/// The maximum number of threads, including the exited ones whose slots are not reused yet.
+pub const MAX_THREADS: usize = 10;
 
-    pub fn block_current(&mut self) {
-        let next_block = self.scheduler.block_current();
-        let mut tasks = self.scheduler.tasks.lock();
-        tasks[self.last_thread_id].set_next_block(next_block.thread_id);
-        self.last_thread_id = next_block.thread_id;
-    }
+/// The scheduling state of a single CPU.
+struct CpuScheduler {
+    /// The running thread, `None` while idling or before the first thread started.
+    current: SpinMutex<Option<ThreadId>>,
+    /// The runnable threads assigned to the CPU, it is only locked while the threads are locked.
+    run_queue: SpinMutex<RunQueue>,
+    /// The thread last woken up by the running thread.
+    ///
+    /// When the running thread blocks before its time slice ends, the rest of the slice is handed to this thread.
+    /// This keeps IPC round trips from waiting on the timer for every switch between client and server.
+    handoff: SpinMutex<Option<ThreadId>>,
+    idle: SpinMutex<Option<IdleContext>>,
+    /// Whether the running thread is executing a syscall, saved with the thread when it is switched out.
+    in_syscall: AtomicBool,
 }
 
-impl Drop for ThreadBlocker {
-    fn drop(&mut self) {
-        let mut tasks = self.scheduler.tasks.lock();
-        let mut current = self.thread_id;
-
-        while let Some(next) = tasks[current].unblock() {
-            current = next;
+impl CpuScheduler {
+    const fn new() -> Self {
+        Self {
+            current: SpinMutex::new(None),
+            run_queue: SpinMutex::new(RunQueue::new()),
+            handoff: SpinMutex::new(None),
+            idle: SpinMutex::new(None),
+            in_syscall: AtomicBool::new(false),
         }
     }
 }
 
+/// Schedules the threads over all CPUs.
+///
+/// Every CPU has a run queue of its own, with the runnable threads assigned to it, and steals from the queues of the
+/// other CPUs when its own runs dry. The threads themselves live in a single table, which is locked before any
+/// run queue.
 pub struct Scheduler {
-    current: SpinMutex<Option<ThreadId>>,
-    tasks: SpinMutex<FixedVec<10, Thread>>,
+    cpus: [CpuScheduler; MAX_CPUS],
+    /// The threads are boxed so they do not take up the table, they fit the largest slab cache of the heap.
+    tasks: SpinMutex<FixedVec<MAX_THREADS, Box<Thread>>>,
+    ticks: AtomicU64,
+    /// The time stamp counter at the first and at the latest tick, used to measure its frequency.
+    first_tick_cycles: AtomicU64,
+    last_tick_cycles: AtomicU64,
+    deadlock_reported: AtomicBool,
 }
 
 impl Scheduler {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NEW_CPU: CpuScheduler = CpuScheduler::new();
+
     const fn new() -> Self {
         Self {
-            current: SpinMutex::new(None),
+            cpus: [Self::NEW_CPU; MAX_CPUS],
             tasks: SpinMutex::new(FixedVec::new()),
+            ticks: AtomicU64::new(0),
+            first_tick_cycles: AtomicU64::new(0),
+            last_tick_cycles: AtomicU64::new(0),
+            deadlock_reported: AtomicBool::new(false),
         }
     }
 
+    /// The scheduling state of the executing CPU.
+    fn cpu(&self) -> &CpuScheduler {
+        &self.cpus[current_cpu()]
+    }
+
+    pub fn current_tick(&self) -> Tick {
+        self.ticks.load(Ordering::Relaxed)
+    }
+
+    /// The time elapsed since the scheduler started, with the precision of a single tick.
+    pub fn uptime(&self) -> Duration {
+        Duration::from_millis(self.current_tick() * 1000 / TICK_FREQUENCY as u64)
+    }
+
+    /// Convert a point in time, measured since the scheduler started, to the first tick at or after it.
+    pub fn tick_at_uptime(&self, uptime: Duration) -> Tick {
+        let frequency = TICK_FREQUENCY as u128;
+        ((uptime.as_millis() * frequency).div_ceil(1000)) as Tick
+    }
+
+    fn record_tick_cycles(&self, tick: Tick) {
+        let cycles = read_timestamp_counter();
+
+        if tick == 1 {
+            self.first_tick_cycles.store(cycles, Ordering::Relaxed);
+        }
+
+        self.last_tick_cycles.store(cycles, Ordering::Relaxed);
+    }
+
+    /// The frequency of the time stamp counter measured against the timer, `0` until two ticks have passed.
+    pub fn cycles_per_second(&self) -> u64 {
+        let ticks = self.current_tick();
+
+        if ticks < 2 {
+            return 0;
+        }
+
+        let cycles = self.last_tick_cycles.load(Ordering::Relaxed)
+            - self.first_tick_cycles.load(Ordering::Relaxed);
+
+        cycles * TICK_FREQUENCY as u64 / (ticks - 1)
+    }
+
+    /// The CPU time accounting of all threads of `service`, or `None` when it has no threads.
+    pub fn service_stats(&self, service: Id) -> Option<ServiceStats> {
+        let tasks_lock = self.tasks.lock();
+
+        let mut threads = 0;
+        let mut total = ThreadStats::default();
+
+        for task in tasks_lock
+            .iter()
+            .filter(|task| task.service_id() == Some(service))
+        {
+            threads += 1;
+            total += task.stats();
+        }
+
+        if threads == 0 {
+            return None;
+        }
+
+        Some(ServiceStats {
+            threads,
+            running_cycles: total.running_cycles,
+            blocked_cycles: total.blocked_cycles,
+            context_switches: total.context_switches,
+            cycles_per_second: self.cycles_per_second(),
+        })
+    }
+
+    /// Fill `entries` with the threads starting at index `start`, returns the number of entries written and whether more threads follow.
+    pub fn thread_info(&self, start: usize, entries: &mut [ThreadInfo]) -> (usize, bool) {
+        let tasks_lock = self.tasks.lock();
+        let threads = tasks_lock.iter().enumerate().skip(start);
+
+        let mut written = 0;
+        for ((id, thread), entry) in threads.zip(entries.iter_mut()) {
+            *entry = thread.info(id);
+            written += 1;
+        }
+
+        (written, start + written < tasks_lock.len())
+    }
+
+    pub fn deadline_passed(&self, deadline: Option<Tick>) -> bool {
+        deadline.is_some_and(|deadline| self.current_tick() >= deadline)
+    }
+
+    pub fn current_thread(&self) -> Option<ThreadId> {
+        *self.cpu().current.lock()
+    }
+
     pub fn current_service(&self) -> Option<ServiceRef> {
-        let current_lock = self.current.lock();
+        let current_lock = self.cpu().current.lock();
         let tasks_lock = self.tasks.lock();
 
         current_lock
@@ -68,85 +194,447 @@ impl Scheduler {
             .map(|service_id| ServiceRef::new(&SERVICE_TABLE, service_id))
     }
 
-    pub fn add_thread(&self, thread: Thread) {
+    /// Add a new thread, the threads are spread over the online CPUs.
+    ///
+    /// The slot of an exited thread is reused when there is one, see [`Thread::is_reusable`].
+    pub fn add_thread(&self, mut thread: Thread) -> ThreadId {
         let mut lock = self.tasks.lock();
-        lock.push(thread);
+
+        let id = match lock.iter().position(|task| task.is_reusable()) {
+            Some(id) => {
+                thread.set_cpu(id % online_cpus());
+                thread.continue_waits_of(&lock[id]);
+                *lock[id] = thread;
+                id
+            }
+            None => {
+                let id = lock.len();
+                thread.set_cpu(id % online_cpus());
+                lock.push(Box::new(thread));
+                id
+            }
+        };
+
+        self.requeue(&lock, id);
+        id
+    }
+
+    /// Put `thread` in the run queue of its CPU when it can run, at its current priority, and take it out otherwise.
+    ///
+    /// This is called after every change to the state or the priority of a thread, while the threads are locked.
+    fn requeue(&self, tasks: &[Box<Thread>], thread: ThreadId) {
+        let task = &tasks[thread];
+        let mut run_queue = self.cpus[task.cpu()].run_queue.lock();
+
+        run_queue.remove(thread);
+
+        if task.can_run() {
+            run_queue.push(thread, task.priority());
+        }
     }
 
-    pub fn block_current(&'static self) -> ThreadBlocker {
+    /// Block the current thread until it is woken up through a [`crate::multi_tasking::wait_queue::WaitQueue`] the returned waiter is pushed on, or until the `deadline` passes.
+    ///
+    /// The thread keeps running until it yields, see [`crate::multi_tasking::wait_queue::WaitQueue`] for the whole sequence.
+    pub fn block_current(&self, deadline: Option<Tick>) -> Waiter {
         let current = self
+            .cpu()
             .current
             .lock()
             .expect("cannot block threads when the scheduler is not yet started");
+
+        let wait = self.tasks.lock()[current].block(deadline);
+
+        Waiter {
+            thread: current,
+            wait,
+        }
+    }
+
+    /// Wake the thread of `waiter`, unless that wait already ended.
+    ///
+    /// When `hand_off` is set, the woken thread gets the rest of the current time slice.
+    pub fn wake(&self, waiter: Waiter, hand_off: bool) -> bool {
         let mut tasks_lock = self.tasks.lock();
 
-        tasks_lock[current].block();
-        ThreadBlocker {
-            scheduler: self,
-            thread_id: current,
-            last_thread_id: current,
+        if !tasks_lock[waiter.thread].unblock(waiter.wait) {
+            return false;
         }
+
+        self.requeue(&tasks_lock, waiter.thread);
+        drop(tasks_lock);
+
+        if hand_off {
+            self.hand_off_to(waiter.thread);
+        }
+
+        true
     }
 
+    pub fn set_priority(&self, thread: ThreadId, priority: Priority) {
+        let mut tasks_lock = self.tasks.lock();
+
+        tasks_lock[thread].set_priority(priority);
+        self.requeue(&tasks_lock, thread);
+    }
+
+    /// Lend the current thread's priority to `thread`, e.g. to the server handling its request.
+    ///
+    /// The donation lasts until it is handed to [`Scheduler::revoke_priority`].
+    pub fn donate_priority(&self, thread: ThreadId) -> Option<Priority> {
+        let current = (*self.cpu().current.lock())?;
+        let mut tasks_lock = self.tasks.lock();
+
+        let priority = tasks_lock[current].priority();
+        tasks_lock[thread].donate(priority);
+        self.requeue(&tasks_lock, thread);
+        Some(priority)
+    }
+
+    pub fn revoke_priority(&self, thread: ThreadId, priority: Priority) {
+        let mut tasks_lock = self.tasks.lock();
+
+        tasks_lock[thread].revoke(priority);
+        self.requeue(&tasks_lock, thread);
+    }
+
+    /// Mark the current thread as executing a syscall until [`Scheduler::leave_syscall`], faults in between are the kernel's.
+    pub fn enter_syscall(&self) {
+        self.cpu().in_syscall.store(true, Ordering::Relaxed);
+    }
+
+    pub fn leave_syscall(&self) {
+        self.cpu().in_syscall.store(false, Ordering::Relaxed);
+    }
+
+    /// Whether the current thread is executing a syscall, rather than the code of its service.
+    pub fn in_syscall(&self) -> bool {
+        self.cpu().in_syscall.load(Ordering::Relaxed)
+    }
+
+    /// Stop all threads of `service` for good, threads running on other CPUs stop at their next switch.
+    pub fn exit_service_threads(&self, service: Id) {
+        let mut tasks_lock = self.tasks.lock();
+
+        for id in 0..tasks_lock.len() {
+            if tasks_lock[id].service_id() == Some(service) {
+                tasks_lock[id].exit();
+                self.requeue(&tasks_lock, id);
+            }
+        }
+    }
+
+    /// Whether a thread of `service` can still run, or is still being executed by a CPU.
+    pub fn runs_service(&self, service: Id) -> bool {
+        self.tasks
+            .lock()
+            .iter()
+            .filter(|task| task.service_id() == Some(service))
+            .any(|task| !task.has_exited() || task.is_on_cpu())
+    }
+
+    /// Stop the current thread for good, and switch to the next one.
+    pub fn exit_current(&self) -> ! {
+        let current = self
+            .cpu()
+            .current
+            .lock()
+            .expect("cannot exit threads when the scheduler is not yet started");
+
+        self.tasks.lock()[current].exit();
+        self.yield_current();
+
+        unreachable!("exited threads should never be scheduled again")
+    }
+
+    /// Advance the clock by a single tick and switch to the next thread.
     pub fn tick(
         &self,
         ctx: InterruptedContext,
+    ) -> (*const InterruptedContext, Option<ServiceRef<'static>>) {
+        let now = self.ticks.fetch_add(1, Ordering::Relaxed) + 1;
+        self.record_tick_cycles(now);
+        self.wake_expired(now);
+
+        self.preempt(ctx)
+    }
+
+    /// End the time slice of the current thread on this CPU, and switch to the next thread.
+    pub fn preempt(
+        &self,
+        ctx: InterruptedContext,
+    ) -> (*const InterruptedContext, Option<ServiceRef<'static>>) {
+        // the time slice is over, so there is nothing left to hand off.
+        *self.cpu().handoff.lock() = None;
+
+        self.reschedule(ctx)
+    }
+
+    /// Switch to the next thread without advancing the clock.
+    pub fn reschedule(
+        &self,
+        ctx: InterruptedContext,
     ) -> (*const InterruptedContext, Option<ServiceRef<'static>>) {
         self.save_and_set_waiting(ctx);
         self.get_next()
     }
 
+    fn hand_off_to(&self, thread: ThreadId) {
+        *self.cpu().handoff.lock() = Some(thread);
+    }
+
+    fn wake_expired(&self, now: Tick) {
+        let mut tasks_lock = self.tasks.lock();
+
+        for id in 0..tasks_lock.len() {
+            if tasks_lock[id].expire(now) {
+                self.requeue(&tasks_lock, id);
+            }
+        }
+    }
+
+    /// Give up the rest of the current thread's time slice.
     pub fn yield_current(&self) {
-        int3();
+        raise_yield_interrupt();
     }
 
     fn save_and_set_waiting(&self, ctx: InterruptedContext) {
-        let current_lock = self.current.lock();
+        let current_lock = self.cpu().current.lock();
 
         if let Some(current) = *current_lock {
             let mut tasks_lock = self.tasks.lock();
 
             let current_task = &mut tasks_lock[current];
             current_task.save(ctx);
+            current_task.set_in_syscall(self.cpu().in_syscall.load(Ordering::Relaxed));
             current_task.finish_tick();
+
+            self.requeue(&tasks_lock, current);
         }
     }
 
     fn get_next(&self) -> (*const InterruptedContext, Option<ServiceRef<'static>>) {
-        let mut current_lock = self.current.lock();
-        let mut tasks_lock = self.tasks.lock();
-
-        let task_count = tasks_lock.len();
+        let cpu_id = current_cpu();
+        let cpu = &self.cpus[cpu_id];
 
-        let start = current_lock.map(|current| current + 1).unwrap_or(0);
-
-        let next_thread_id = (0..task_count)
-            .map(|i| (i + start) % task_count)
-            .find_map(|id| {
-                let task = &tasks_lock[id];
+        let mut current_lock = cpu.current.lock();
+        let mut tasks_lock = self.tasks.lock();
 
-                if !task.can_run() {
-                    return None;
-                }
+        // only hand off the time slice when the previous thread gave up the CPU because it blocked.
+        let handoff = cpu.handoff.lock().take();
+        let previous_blocked = current_lock.is_none_or(|previous| !tasks_lock[previous].can_run());
+        let preferred = handoff.filter(|_| previous_blocked);
 
-                Some(id)
-            });
+        let next_thread_id = self.pick_next(cpu_id, &mut tasks_lock, preferred);
 
         let Some(next_thread_id) = next_thread_id else {
-            todo!("Handle exit condition")
+            *current_lock = None;
+            cpu.in_syscall.store(false, Ordering::Relaxed);
+            self.report_deadlock(&tasks_lock);
+            return (Self::enter_idle(cpu_id, cpu), None);
         };
 
         *current_lock = Some(next_thread_id);
+        self.deadlock_reported.store(false, Ordering::Relaxed);
 
         let next_thread = &mut tasks_lock[next_thread_id];
         next_thread.start_tick();
+        cpu.in_syscall
+            .store(next_thread.in_syscall(), Ordering::Relaxed);
         (
-            next_thread.context_ptr(),
+            next_thread.restore(),
             next_thread
                 .service_id()
                 .map(|id| ServiceRef::new(&SERVICE_TABLE, id)),
         )
     }
+
+    /// Take the next thread to run on `cpu` out of the run queues.
+    ///
+    /// The `preferred` thread is picked when it can run and no thread queued on `cpu` has a higher priority.
+    /// When the queue of `cpu` is empty, the thread with the highest priority is stolen from the queue of another CPU,
+    /// and moves over to `cpu`.
+    fn pick_next(
+        &self,
+        cpu: CpuId,
+        tasks: &mut [Box<Thread>],
+        preferred: Option<ThreadId>,
+    ) -> Option<ThreadId> {
+        let run_queue = &self.cpus[cpu].run_queue;
+
+        let preferred = preferred.filter(|thread| {
+            let task = &tasks[*thread];
+            let highest = run_queue.lock().highest_priority();
+
+            task.can_run() && highest.is_none_or(|highest| task.priority() >= highest)
+        });
+
+        let next = match preferred {
+            Some(thread) => {
+                self.cpus[tasks[thread].cpu()]
+                    .run_queue
+                    .lock()
+                    .remove(thread);
+                thread
+            }
+            None => {
+                let local = run_queue.lock().pop();
+                local.or_else(|| self.steal(cpu))?
+            }
+        };
+
+        tasks[next].set_cpu(cpu);
+        Some(next)
+    }
+
+    /// Take the thread with the highest priority out of the queue of another CPU.
+    ///
+    /// Only a single run queue is locked at a time, and the threads stay locked, so the queues cannot change in between.
+    fn steal(&self, cpu: CpuId) -> Option<ThreadId> {
+        let (_, victim) = (0..online_cpus())
+            .filter(|other| *other != cpu)
+            .filter_map(|other| {
+                let highest = self.cpus[other].run_queue.lock().highest_priority()?;
+                Some((highest, other))
+            })
+            .max_by_key(|(highest, _)| *highest)?;
+
+        self.cpus[victim].run_queue.lock().pop()
+    }
+
+    fn enter_idle(cpu_id: CpuId, cpu: &CpuScheduler) -> *const InterruptedContext {
+        cpu.idle
+            .lock()
+            .get_or_insert_with(|| IdleContext::new(cpu_id))
+            .enter()
+    }
+
+    /// Report when no thread will ever be able to run again, because all of them are blocked without a deadline.
+    ///
+    /// Interrupts could in theory still unblock a thread, so the scheduler keeps idling afterwards.
+    fn report_deadlock(&self, tasks: &[Box<Thread>]) {
+        let mut remaining = tasks.iter().filter(|task| !task.has_exited()).peekable();
+
+        if remaining.peek().is_none() || !remaining.all(|task| task.is_blocked_forever()) {
+            return;
+        }
+
+        if self.deadlock_reported.swap(true, Ordering::Relaxed) {
+            return;
+        }
+
+        debug_println!("Deadlock: all threads are blocked without a deadline");
+
+        for (id, task) in tasks.iter().enumerate() {
+            if task.has_exited() {
+                continue;
+            }
+
+            debug_println!(
+                "  thread {id} ({}), service: {:?}",
+                task.name().unwrap_or("unnamed"),
+                task.service_id()
+            );
+        }
+    }
 }
 
 pub static SCHEDULER: Scheduler = Scheduler::new();
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use essentials::address::VirtualAddress;
+    use x86_64::paging::{PageSize, VirtualPage};
+
+    use super::*;
+
+    /// Add a thread that is never run and block it, returns its waiter like [`Scheduler::block_current`] would.
+    ///
+    /// The tests run without threads, so this stands in for a thread contending for a lock.
+    pub fn add_blocked_thread() -> Waiter {
+        add_blocked_thread_until(None)
+    }
+
+    /// Like [`add_blocked_thread`], but the thread is woken once the clock reaches `deadline`.
+    pub fn add_blocked_thread_until(deadline: Option<Tick>) -> Waiter {
+        let stack = ThreadStack::from_page(VirtualPage::new(
+            VirtualAddress::new(0x1000),
+            PageSize::Size4Kib,
+        ));
+        // Safety: the thread is never switched to.
+        let thread =
+            unsafe { Thread::start_new(Some("Blocked"), stack, VirtualAddress::new(0x1000), None) };
+
+        let thread = SCHEDULER.add_thread(thread);
+        let mut tasks_lock = SCHEDULER.tasks.lock();
+        let wait = tasks_lock[thread].block(deadline);
+        SCHEDULER.requeue(&tasks_lock, thread);
+
+        Waiter { thread, wait }
+    }
+
+    /// Advance the clock by `ticks` without switching threads, waking the threads whose deadline passed.
+    pub fn advance_clock(ticks: Tick) {
+        let now = SCHEDULER.ticks.fetch_add(ticks, Ordering::Relaxed) + ticks;
+        SCHEDULER.wake_expired(now);
+    }
+
+    pub fn is_blocked(waiter: Waiter) -> bool {
+        SCHEDULER.tasks.lock()[waiter.thread].is_blocked()
+    }
+
+    /// Exit the threads of [`add_blocked_thread`], so their slots are reused.
+    pub fn exit_threads(waiters: &[Waiter]) {
+        let mut tasks_lock = SCHEDULER.tasks.lock();
+
+        for waiter in waiters {
+            tasks_lock[waiter.thread].exit();
+            SCHEDULER.requeue(&tasks_lock, waiter.thread);
+        }
+    }
+
+    #[test_case]
+    fn test_woken_threads_are_queued_on_their_cpu() {
+        let waiter = add_blocked_thread();
+        let cpu = SCHEDULER.tasks.lock()[waiter.thread].cpu();
+
+        assert!(!SCHEDULER.cpus[cpu].run_queue.lock().remove(waiter.thread));
+
+        assert!(SCHEDULER.wake(waiter, false));
+        let tasks_lock = SCHEDULER.tasks.lock();
+        assert!(SCHEDULER.cpus[cpu].run_queue.lock().remove(waiter.thread));
+        SCHEDULER.requeue(&tasks_lock, waiter.thread);
+        drop(tasks_lock);
+
+        exit_threads(&[waiter]);
+    }
+
+    #[test_case]
+    fn test_idle_cpus_steal_queued_threads() {
+        let waiter = add_blocked_thread();
+        SCHEDULER.set_priority(waiter.thread, Priority::High);
+        assert!(SCHEDULER.wake(waiter, false));
+
+        let mut tasks_lock = SCHEDULER.tasks.lock();
+        let cpu = tasks_lock[waiter.thread].cpu();
+        let idle_cpu = (cpu + 1) % MAX_CPUS;
+
+        assert_eq!(
+            Some(waiter.thread),
+            SCHEDULER.pick_next(idle_cpu, &mut tasks_lock, None)
+        );
+        assert_eq!(idle_cpu, tasks_lock[waiter.thread].cpu());
+        assert!(!SCHEDULER.cpus[cpu].run_queue.lock().remove(waiter.thread));
+
+        // the stolen thread stays on the CPU that took it.
+        SCHEDULER.requeue(&tasks_lock, waiter.thread);
+        assert!(SCHEDULER.cpus[idle_cpu]
+            .run_queue
+            .lock()
+            .remove(waiter.thread));
+        SCHEDULER.requeue(&tasks_lock, waiter.thread);
+        drop(tasks_lock);
+
+        exit_threads(&[waiter]);
+    }
+}
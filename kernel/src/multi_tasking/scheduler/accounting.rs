@@ -0,0 +1,66 @@
+use core::ops::AddAssign;
+use x86_64::instructions::read_timestamp_counter;
+
+/// CPU time accounting of a thread, measured in time stamp counter cycles.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ThreadStats {
+    pub running_cycles: u64,
+    pub blocked_cycles: u64,
+    /// The number of times the thread was switched to.
+    pub context_switches: u64,
+}
+
+impl AddAssign for ThreadStats {
+    fn add_assign(&mut self, rhs: Self) {
+        self.running_cycles += rhs.running_cycles;
+        self.blocked_cycles += rhs.blocked_cycles;
+        self.context_switches += rhs.context_switches;
+    }
+}
+
+/// Keeps the [`ThreadStats`] up to date with the state changes of a thread.
+#[derive(Debug, Default)]
+pub struct Accounting {
+    stats: ThreadStats,
+    running_since: Option<u64>,
+    blocked_since: Option<u64>,
+}
+
+impl Accounting {
+    pub fn start_running(&mut self) {
+        self.running_since = Some(read_timestamp_counter());
+        self.stats.context_switches += 1;
+    }
+
+    pub fn stop_running(&mut self) {
+        if let Some(since) = self.running_since.take() {
+            self.stats.running_cycles += read_timestamp_counter().saturating_sub(since);
+        }
+    }
+
+    pub fn start_blocking(&mut self) {
+        self.blocked_since = Some(read_timestamp_counter());
+    }
+
+    pub fn stop_blocking(&mut self) {
+        if let Some(since) = self.blocked_since.take() {
+            self.stats.blocked_cycles += read_timestamp_counter().saturating_sub(since);
+        }
+    }
+
+    /// The stats up to now, including the time the thread is currently running or blocked.
+    pub fn stats(&self) -> ThreadStats {
+        let now = read_timestamp_counter();
+        let mut stats = self.stats;
+
+        if let Some(since) = self.running_since {
+            stats.running_cycles += now.saturating_sub(since);
+        }
+
+        if let Some(since) = self.blocked_since {
+            stats.blocked_cycles += now.saturating_sub(since);
+        }
+
+        stats
+    }
+}
@@ -0,0 +1,59 @@
+use alloc::boxed::Box;
+use alloc::vec;
+use core::fmt::{Debug, Formatter};
+use x86_64::extended_state::ExtendedStateFormat;
+
+use crate::arch::x86_64::init::EXTENDED_STATE_FORMAT;
+
+#[repr(C, align(64))]
+#[derive(Clone, Copy)]
+struct AlignedChunk([u8; ExtendedStateFormat::ALIGNMENT]);
+
+/// The saved FPU, SSE and AVX registers of a thread, sized for the format of the system.
+pub struct ExtendedState {
+    area: Box<[AlignedChunk]>,
+}
+
+impl ExtendedState {
+    pub fn new() -> Self {
+        let format = &EXTENDED_STATE_FORMAT;
+        let chunks = format.size().div_ceil(ExtendedStateFormat::ALIGNMENT);
+
+        let mut state = Self {
+            area: vec![AlignedChunk([0; ExtendedStateFormat::ALIGNMENT]); chunks]
+                .into_boxed_slice(),
+        };
+
+        format.init_area(state.as_mut_bytes());
+        state
+    }
+
+    fn as_mut_bytes(&mut self) -> &mut [u8] {
+        let len = self.area.len() * ExtendedStateFormat::ALIGNMENT;
+        unsafe { core::slice::from_raw_parts_mut(self.area.as_mut_ptr() as *mut u8, len) }
+    }
+
+    /// Save the registers of the executing CPU.
+    pub fn save(&mut self) {
+        unsafe { EXTENDED_STATE_FORMAT.save(self.area.as_mut_ptr() as *mut u8) }
+    }
+
+    /// Load the saved registers into the executing CPU.
+    pub fn restore(&self) {
+        unsafe { EXTENDED_STATE_FORMAT.restore(self.area.as_ptr() as *const u8) }
+    }
+}
+
+impl Default for ExtendedState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for ExtendedState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ExtendedState")
+            .field("size", &EXTENDED_STATE_FORMAT.size())
+            .finish()
+    }
+}
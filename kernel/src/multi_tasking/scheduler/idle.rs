@@ -0,0 +1,48 @@
+use crate::arch::x86_64::init::GDT;
+use crate::arch::x86_64::smp::{CpuId, MAX_CPUS};
+use crate::multi_tasking::scheduler::ThreadStack;
+use essentials::address::VirtualAddress;
+use x86_64::constants::MIN_STACK_SIZE;
+use x86_64::instructions::halt_loop;
+use x86_64::interrupts::context::{InterruptStackFrame, InterruptedContext};
+use x86_64::RFlags;
+
+static mut IDLE_STACKS: [[u8; MIN_STACK_SIZE]; MAX_CPUS] = [[0; MIN_STACK_SIZE]; MAX_CPUS];
+
+/// The context a CPU switches to when none of the threads can run, every CPU has its own.
+///
+/// The idle loop only halts until the next interrupt, so it has no state worth saving.
+/// Instead, it is restarted from the top of its stack every time it is entered.
+pub struct IdleContext {
+    stack: ThreadStack,
+    context: InterruptedContext,
+}
+
+impl IdleContext {
+    pub fn new(cpu: CpuId) -> Self {
+        let stack = unsafe { ThreadStack::from_slice(&mut IDLE_STACKS[cpu]) };
+        let context = Self::start_context(&stack);
+
+        Self { stack, context }
+    }
+
+    /// Reset the idle loop to its start, and return the context to switch to.
+    pub fn enter(&mut self) -> *const InterruptedContext {
+        self.context = Self::start_context(&self.stack);
+        &self.context
+    }
+
+    fn start_context(stack: &ThreadStack) -> InterruptedContext {
+        InterruptedContext::start_new(InterruptStackFrame::new(
+            VirtualAddress::from(idle_loop as *const fn()),
+            stack.top(),
+            RFlags::INTERRUPTS_ENABLED,
+            GDT.kernel_code,
+            GDT.kernel_data,
+        ))
+    }
+}
+
+fn idle_loop() -> ! {
+    halt_loop()
+}
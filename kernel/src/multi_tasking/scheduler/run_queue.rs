@@ -0,0 +1,117 @@
+use crate::multi_tasking::scheduler::{ThreadId, MAX_THREADS};
+use syscall::Priority;
+
+const _: () = assert!(MAX_THREADS <= u64::BITS as usize);
+
+/// The runnable threads assigned to a single CPU, as a set of threads per priority level.
+///
+/// A thread is in the queue of the CPU it is assigned to exactly while it can run, see [`super::Thread::can_run`].
+/// The highest priority level with a queued thread always wins,
+/// and the threads within a level take turns in a round-robin fashion.
+/// When its own queue is empty, a CPU steals a thread from the queue of another CPU, which moves the thread over.
+pub struct RunQueue {
+    /// The queued threads of each priority level, a bit per thread id.
+    levels: [u64; Priority::LEVELS],
+    /// The thread that was last picked from each priority level.
+    last_picked: [Option<ThreadId>; Priority::LEVELS],
+}
+
+impl RunQueue {
+    pub const fn new() -> Self {
+        Self {
+            levels: [0; Priority::LEVELS],
+            last_picked: [None; Priority::LEVELS],
+        }
+    }
+
+    pub fn push(&mut self, thread: ThreadId, priority: Priority) {
+        self.levels[priority.level()] |= 1 << thread;
+    }
+
+    /// Take `thread` out of the queue, returns whether it was queued.
+    pub fn remove(&mut self, thread: ThreadId) -> bool {
+        let mut removed = false;
+
+        for level in &mut self.levels {
+            removed |= *level & (1 << thread) != 0;
+            *level &= !(1 << thread);
+        }
+
+        removed
+    }
+
+    /// The priority of the most important queued thread, `None` when the queue is empty.
+    pub fn highest_priority(&self) -> Option<Priority> {
+        Priority::DESCENDING
+            .into_iter()
+            .find(|priority| self.levels[priority.level()] != 0)
+    }
+
+    /// Take the next thread of the highest priority level out of the queue.
+    pub fn pop(&mut self) -> Option<ThreadId> {
+        let level = self.highest_priority()?.level();
+        let threads = self.levels[level];
+        let start = self.last_picked[level].map(|last| last + 1).unwrap_or(0);
+
+        let next = (0..MAX_THREADS)
+            .map(|i| (i + start) % MAX_THREADS)
+            .find(|id| threads & (1 << id) != 0)?;
+
+        self.levels[level] &= !(1 << next);
+        self.last_picked[level] = Some(next);
+        Some(next)
+    }
+}
+
+impl Default for RunQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_higher_priorities_are_popped_first() {
+        let mut queue = RunQueue::new();
+
+        queue.push(0, Priority::Low);
+        queue.push(1, Priority::High);
+        queue.push(2, Priority::Normal);
+
+        assert_eq!(Some(Priority::High), queue.highest_priority());
+        assert_eq!(Some(1), queue.pop());
+        assert_eq!(Some(2), queue.pop());
+        assert_eq!(Some(0), queue.pop());
+        assert_eq!(None, queue.pop());
+    }
+
+    #[test_case]
+    fn test_threads_of_a_level_take_turns() {
+        let mut queue = RunQueue::new();
+
+        queue.push(3, Priority::Normal);
+        queue.push(1, Priority::Normal);
+        assert_eq!(Some(1), queue.pop());
+
+        // the thread picked last goes after the others of its level.
+        queue.push(1, Priority::Normal);
+        assert_eq!(Some(3), queue.pop());
+        assert_eq!(Some(1), queue.pop());
+    }
+
+    #[test_case]
+    fn test_removed_threads_are_not_popped() {
+        let mut queue = RunQueue::new();
+
+        queue.push(2, Priority::High);
+        queue.push(4, Priority::Normal);
+
+        assert!(queue.remove(2));
+        assert!(!queue.remove(2));
+        assert_eq!(Some(Priority::Normal), queue.highest_priority());
+        assert_eq!(Some(4), queue.pop());
+    }
+}
@@ -1,26 +1,54 @@
+use core::mem::size_of;
+
 use crate::arch::x86_64::init::GDT;
+use crate::arch::x86_64::smp::CpuId;
+use crate::memory::heap::MAX_SLAB_OBJECT_SIZE;
 use crate::multi_tasking::scheduler::stack::ThreadStack;
+use crate::multi_tasking::scheduler::Tick;
+use crate::multi_tasking::scheduler::{Accounting, ExtendedState, ThreadStats};
 use crate::service::Id;
 use essentials::address::VirtualAddress;
+use syscall::{InfoName, Priority, ThreadInfo, ThreadInfoState};
 use x86_64::interrupts::context::{InterruptStackFrame, InterruptedContext};
 use x86_64::RFlags;
 
 pub type ThreadId = usize;
 
+// the threads are boxed, and have to fit a slab cache of the heap.
+const _: () = assert!(size_of::<Thread>() <= MAX_SLAB_OBJECT_SIZE);
+
 #[derive(Debug)]
 pub enum ThreadState {
     Running,
     Waiting,
-    Blocked { next: Option<ThreadId> },
+    Blocked { deadline: Option<Tick> },
+    Exited,
 }
 
 #[derive(Debug)]
 pub struct Thread {
-    #[allow(dead_code)] // impl Debug ignores the usage of this field
     name: Option<&'static str>,
     context: InterruptedContext,
+    /// The FPU and SIMD registers, which are not part of the interrupted context.
+    ///
+    /// Kernel code is built without floating point and SIMD instructions, so only service threads need them saved.
+    extended_state: Option<ExtendedState>,
     state: ThreadState,
+    /// The CPU whose run queue the thread is in.
+    cpu: CpuId,
+    /// Whether a CPU is still executing the thread, it cannot be picked up by another CPU until its context is saved.
+    ///
+    /// This is kept outside of [`ThreadState`] because a running thread can block and be woken up before it gets saved.
+    on_cpu: bool,
     service_id: Option<Id>,
+    priority: Priority,
+    /// The number of outstanding donations per priority level, see [`Thread::donate`].
+    donations: [u16; Priority::LEVELS],
+    /// The number of times the thread blocked, which tells the current wait apart from earlier ones, see [`crate::multi_tasking::wait_queue::Waiter`].
+    wait_id: u64,
+    /// Whether the thread was switched out in the middle of a syscall, see [`crate::multi_tasking::scheduler::Scheduler::in_syscall`].
+    in_syscall: bool,
+    accounting: Accounting,
 }
 
 impl Thread {
@@ -39,12 +67,23 @@ impl Thread {
                 GDT.kernel_code,
                 GDT.kernel_data,
             )),
+            extended_state: service_id.map(|_| ExtendedState::new()),
             state: ThreadState::Waiting,
+            cpu: 0,
+            on_cpu: false,
             service_id,
+            priority: Priority::default(),
+            donations: [0; Priority::LEVELS],
+            wait_id: 0,
+            in_syscall: false,
+            accounting: Accounting::default(),
         }
     }
 
     pub fn finish_tick(&mut self) {
+        self.on_cpu = false;
+        self.accounting.stop_running();
+
         match self.state {
             ThreadState::Running => self.state = ThreadState::Waiting,
             _ => {}
@@ -52,52 +91,187 @@ impl Thread {
     }
 
     pub fn start_tick(&mut self) {
+        self.on_cpu = true;
+        self.accounting.start_running();
+
         match self.state {
             ThreadState::Waiting => self.state = ThreadState::Running,
             _ => {}
         }
     }
 
-    pub fn block(&mut self) {
-        self.state = ThreadState::Blocked { next: None }
+    /// Block the thread, returns the id of this wait.
+    pub fn block(&mut self, deadline: Option<Tick>) -> u64 {
+        self.state = ThreadState::Blocked { deadline };
+        self.wait_id += 1;
+        self.accounting.start_blocking();
+        self.wait_id
+    }
+
+    /// Wake the thread when it is still blocked in the wait with the given id.
+    pub fn unblock(&mut self, wait_id: u64) -> bool {
+        if !self.is_blocked() || self.wait_id != wait_id {
+            return false;
+        }
+
+        self.state = ThreadState::Waiting;
+        self.accounting.stop_blocking();
+        true
+    }
+
+    pub fn exit(&mut self) {
+        self.state = ThreadState::Exited;
+    }
+
+    pub fn has_exited(&self) -> bool {
+        matches!(self.state, ThreadState::Exited)
+    }
+
+    /// Whether the slot of the thread can be given to a new thread.
+    ///
+    /// The thread must have exited and been switched out, and no client may still revoke a donation from it.
+    pub fn is_reusable(&self) -> bool {
+        self.has_exited() && !self.is_on_cpu() && self.donations.iter().all(|count| *count == 0)
     }
 
-    pub fn unblock(&mut self) -> Option<ThreadId> {
+    /// Take over the slot of the exited `previous` thread.
+    ///
+    /// The waits continue after the ones of `previous`, so a [`crate::multi_tasking::wait_queue::Waiter`] it left behind
+    /// never wakes this thread.
+    pub fn continue_waits_of(&mut self, previous: &Thread) {
+        self.wait_id = previous.wait_id;
+    }
+
+    /// Wake the thread when it is blocked with a deadline at or before `now`.
+    pub fn expire(&mut self, now: Tick) -> bool {
         match self.state {
-            ThreadState::Blocked { next } => {
+            ThreadState::Blocked {
+                deadline: Some(deadline),
+            } if deadline <= now => {
                 self.state = ThreadState::Waiting;
-                next
+                self.accounting.stop_blocking();
+                true
             }
-            _ => None,
+            _ => false,
         }
     }
 
-    pub fn set_next_block(&mut self, next_id: ThreadId) {
-        match &mut self.state {
-            ThreadState::Blocked { next } => {
-                *next = Some(next_id);
-            }
-            _ => {}
-        }
+    pub fn is_blocked(&self) -> bool {
+        matches!(self.state, ThreadState::Blocked { .. })
+    }
+
+    /// Whether the thread is blocked and has no deadline that would wake it up.
+    pub fn is_blocked_forever(&self) -> bool {
+        matches!(self.state, ThreadState::Blocked { deadline: None })
     }
 
     pub fn can_run(&self) -> bool {
+        if self.on_cpu {
+            return false;
+        }
+
         match self.state {
             ThreadState::Running => false,
             ThreadState::Waiting => true,
             ThreadState::Blocked { .. } => false,
+            ThreadState::Exited => false,
         }
     }
 
+    /// Save the state of the executing CPU, which was interrupted while running this thread.
     pub fn save(&mut self, ctx: InterruptedContext) {
         self.context = ctx;
+
+        if let Some(extended_state) = &mut self.extended_state {
+            extended_state.save();
+        }
     }
 
-    pub fn context_ptr(&self) -> *const InterruptedContext {
+    pub fn is_on_cpu(&self) -> bool {
+        self.on_cpu
+    }
+
+    pub fn in_syscall(&self) -> bool {
+        self.in_syscall
+    }
+
+    pub fn set_in_syscall(&mut self, in_syscall: bool) {
+        self.in_syscall = in_syscall;
+    }
+
+    /// Load the extended state into the executing CPU, and return the context to continue the thread with.
+    pub fn restore(&self) -> *const InterruptedContext {
+        if let Some(extended_state) = &self.extended_state {
+            extended_state.restore();
+        }
+
         &self.context
     }
 
+    /// The priority the thread is scheduled with, its own or the highest one donated to it.
+    pub fn priority(&self) -> Priority {
+        Priority::DESCENDING
+            .into_iter()
+            .find(|p| *p > self.priority && self.donations[p.level()] > 0)
+            .unwrap_or(self.priority)
+    }
+
+    pub fn base_priority(&self) -> Priority {
+        self.priority
+    }
+
+    pub fn set_priority(&mut self, priority: Priority) {
+        self.priority = priority;
+    }
+
+    /// Temporarily run with at least `priority`, until the donation is revoked.
+    pub fn donate(&mut self, priority: Priority) {
+        self.donations[priority.level()] += 1;
+    }
+
+    pub fn revoke(&mut self, priority: Priority) {
+        self.donations[priority.level()] -= 1;
+    }
+
+    pub fn cpu(&self) -> CpuId {
+        self.cpu
+    }
+
+    pub fn set_cpu(&mut self, cpu: CpuId) {
+        self.cpu = cpu;
+    }
+
+    /// The CPU time accounting of the thread up to now.
+    pub fn stats(&self) -> ThreadStats {
+        self.accounting.stats()
+    }
+
+    pub fn name(&self) -> Option<&'static str> {
+        self.name
+    }
+
     pub fn service_id(&self) -> Option<Id> {
         self.service_id
     }
+
+    /// A snapshot of the thread for introspection, `id` being the thread's own id.
+    pub fn info(&self, id: ThreadId) -> ThreadInfo {
+        let state = match self.state {
+            ThreadState::Running => ThreadInfoState::Running,
+            ThreadState::Waiting => ThreadInfoState::Waiting,
+            ThreadState::Blocked { .. } => ThreadInfoState::Blocked,
+            ThreadState::Exited => ThreadInfoState::Exited,
+        };
+
+        ThreadInfo {
+            id: id as u64,
+            has_service: self.service_id.is_some(),
+            service: self.service_id.unwrap_or(0),
+            state,
+            priority: self.priority(),
+            cpu: self.cpu as u8,
+            running_cycles: self.stats().running_cycles,
+            name: InfoName::new(self.name.unwrap_or("")),
+        }
+    }
 }
@@ -0,0 +1,55 @@
+//! Blocking concurrency primitives, which put the current thread to sleep instead of spinning.
+//!
+//! ## Note:
+//!
+//! These can only be used from a scheduled thread, never from an interrupt handler.
+//! A thread must not go to sleep while it holds a spin lock, so these locks are always taken before any spin lock.
+
+pub use condvar::Condvar;
+pub use mutex::{Mutex, MutexGuard};
+pub use rw_lock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use semaphore::Semaphore;
+
+use essentials::sync::SpinMutex;
+use x86_64::interrupts::atomic_block;
+
+use crate::multi_tasking::scheduler::SCHEDULER;
+use crate::multi_tasking::wait_queue::WaitQueue;
+
+mod condvar;
+mod mutex;
+mod rw_lock;
+mod semaphore;
+
+/// Run `try_acquire` on the locked `state` until it succeeds, sleeping on the queue picked by `queue` after every failed attempt.
+///
+/// The `state` is only locked with interrupts disabled, and never while sleeping.
+fn acquire<S, R>(
+    state: &SpinMutex<S>,
+    queue: fn(&mut S) -> &mut WaitQueue,
+    mut try_acquire: impl FnMut(&mut S) -> Option<R>,
+) -> R {
+    loop {
+        let attempt = atomic_block(|| {
+            let mut state = state.lock();
+
+            match try_acquire(&mut state) {
+                Some(acquired) => Ok(acquired),
+                None => {
+                    let waiter = SCHEDULER.block_current(None);
+                    queue(&mut state).push(waiter);
+                    Err(waiter)
+                }
+            }
+        });
+
+        let waiter = match attempt {
+            Ok(acquired) => return acquired,
+            Err(waiter) => waiter,
+        };
+
+        SCHEDULER.yield_current();
+
+        atomic_block(|| queue(&mut state.lock()).remove(waiter));
+    }
+}
@@ -0,0 +1,115 @@
+use essentials::sync::SpinMutex;
+use x86_64::interrupts::atomic_block;
+
+use crate::multi_tasking::scheduler::{Tick, SCHEDULER};
+use crate::multi_tasking::sync::MutexGuard;
+use crate::multi_tasking::wait_queue::WaitQueue;
+
+/// A condition variable, to sleep until another thread signals that the state protected by a [`super::Mutex`] changed.
+///
+/// Like any condition variable, waiting threads can wake up without the condition being met, so they should check it in a loop.
+pub struct Condvar {
+    waiters: SpinMutex<WaitQueue>,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self {
+            waiters: SpinMutex::new(WaitQueue::new()),
+        }
+    }
+
+    /// Unlock the mutex and sleep until notified, the mutex is locked again before returning.
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        self.wait_until(guard, None).0
+    }
+
+    /// Like [`Condvar::wait`], but also wake up when the `deadline` passes.
+    ///
+    /// Returns whether the deadline passed.
+    pub fn wait_until<'a, T>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        deadline: Option<Tick>,
+    ) -> (MutexGuard<'a, T>, bool) {
+        let mutex = guard.mutex();
+
+        // the thread must not be switched out before the mutex is unlocked.
+        let waiter = atomic_block(|| {
+            let waiter = SCHEDULER.block_current(deadline);
+            self.waiters.lock().push(waiter);
+            drop(guard);
+            waiter
+        });
+
+        SCHEDULER.yield_current();
+
+        atomic_block(|| self.waiters.lock().remove(waiter));
+
+        (mutex.lock(), SCHEDULER.deadline_passed(deadline))
+    }
+
+    /// Wake the thread that has been waiting the longest.
+    pub fn notify_one(&self) {
+        atomic_block(|| self.waiters.lock().wake_one());
+    }
+
+    pub fn notify_all(&self) {
+        atomic_block(|| self.waiters.lock().wake_all());
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::multi_tasking::scheduler::tests::{add_blocked_thread, exit_threads, is_blocked};
+
+    #[test_case]
+    fn test_notify_one_wakes_the_oldest_waiter() {
+        let condvar = Condvar::new();
+        let first = add_blocked_thread();
+        let second = add_blocked_thread();
+
+        atomic_block(|| {
+            let mut waiters = condvar.waiters.lock();
+            waiters.push(first);
+            waiters.push(second);
+        });
+
+        condvar.notify_one();
+        assert!(!is_blocked(first));
+        assert!(is_blocked(second));
+
+        condvar.notify_one();
+        assert!(!is_blocked(second));
+
+        exit_threads(&[first, second]);
+    }
+
+    #[test_case]
+    fn test_notify_all_wakes_every_waiter() {
+        let condvar = Condvar::new();
+        let waiters = [
+            add_blocked_thread(),
+            add_blocked_thread(),
+            add_blocked_thread(),
+        ];
+
+        atomic_block(|| {
+            for waiter in waiters {
+                condvar.waiters.lock().push(waiter);
+            }
+        });
+
+        condvar.notify_all();
+        assert!(waiters.iter().all(|waiter| !is_blocked(*waiter)));
+
+        exit_threads(&waiters);
+    }
+}
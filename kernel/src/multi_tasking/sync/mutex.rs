@@ -0,0 +1,152 @@
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use essentials::sync::SpinMutex;
+use x86_64::interrupts::atomic_block;
+
+use crate::multi_tasking::sync::acquire;
+use crate::multi_tasking::wait_queue::WaitQueue;
+
+struct MutexState {
+    locked: bool,
+    waiters: WaitQueue,
+}
+
+/// A mutual exclusion lock that puts contending threads to sleep.
+pub struct Mutex<T> {
+    state: SpinMutex<MutexState>,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for Mutex<T> {}
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: SpinMutex::new(MutexState {
+                locked: false,
+                waiters: WaitQueue::new(),
+            }),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    /// Lock the mutex, sleeping until it is available.
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        acquire(
+            &self.state,
+            |state| &mut state.waiters,
+            Self::try_lock_state,
+        );
+
+        MutexGuard { mutex: self }
+    }
+
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        atomic_block(|| Self::try_lock_state(&mut self.state.lock()))
+            .map(|_| MutexGuard { mutex: self })
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    fn try_lock_state(state: &mut MutexState) -> Option<()> {
+        if state.locked {
+            return None;
+        }
+
+        state.locked = true;
+        Some(())
+    }
+
+    fn unlock(&self) {
+        atomic_block(|| {
+            let mut state = self.state.lock();
+            state.locked = false;
+            state.waiters.wake_one();
+        });
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<'a, T> MutexGuard<'a, T> {
+    /// The mutex this guard locks, used by [`super::Condvar`] to unlock and relock it.
+    pub(super) fn mutex(&self) -> &'a Mutex<T> {
+        self.mutex
+    }
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // Safety: the guard proves the mutex is locked.
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // Safety: the guard proves the mutex is locked.
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::multi_tasking::scheduler::tests::{add_blocked_thread, exit_threads, is_blocked};
+
+    #[test_case]
+    fn test_contended_lock_fails() {
+        let mutex = Mutex::new(0);
+
+        let mut guard = mutex.lock();
+        *guard += 1;
+        assert!(mutex.try_lock().is_none());
+
+        drop(guard);
+        assert_eq!(1, *mutex.try_lock().unwrap());
+    }
+
+    #[test_case]
+    fn test_unlock_wakes_waiters_in_order() {
+        let mutex = Mutex::new(());
+        let first = add_blocked_thread();
+        let second = add_blocked_thread();
+
+        let guard = mutex.lock();
+        atomic_block(|| {
+            let mut state = mutex.state.lock();
+            state.waiters.push(first);
+            state.waiters.push(second);
+        });
+
+        drop(guard);
+        assert!(!is_blocked(first));
+        assert!(is_blocked(second));
+
+        // the woken thread leaves the queue and takes the lock, its unlock wakes the next one.
+        atomic_block(|| mutex.state.lock().waiters.remove(first));
+        drop(mutex.try_lock().unwrap());
+        assert!(!is_blocked(second));
+
+        exit_threads(&[first, second]);
+    }
+}
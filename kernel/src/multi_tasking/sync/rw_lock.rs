@@ -0,0 +1,232 @@
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use essentials::sync::SpinMutex;
+use x86_64::interrupts::atomic_block;
+
+use crate::multi_tasking::sync::acquire;
+use crate::multi_tasking::wait_queue::WaitQueue;
+
+struct RwLockState {
+    readers: usize,
+    writer: bool,
+    read_waiters: WaitQueue,
+    write_waiters: WaitQueue,
+}
+
+/// A reader-writer lock that puts contending threads to sleep.
+///
+/// Writers take precedence, new readers wait while a writer is waiting so writers cannot starve.
+pub struct RwLock<T> {
+    state: SpinMutex<RwLockState>,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for RwLock<T> {}
+unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: SpinMutex::new(RwLockState {
+                readers: 0,
+                writer: false,
+                read_waiters: WaitQueue::new(),
+                write_waiters: WaitQueue::new(),
+            }),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    /// Lock for shared access, sleeping while a writer holds or waits for the lock.
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        acquire(
+            &self.state,
+            |state| &mut state.read_waiters,
+            Self::try_read_state,
+        );
+
+        RwLockReadGuard { lock: self }
+    }
+
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        atomic_block(|| Self::try_read_state(&mut self.state.lock()))
+            .map(|_| RwLockReadGuard { lock: self })
+    }
+
+    /// Lock for exclusive access, sleeping while any other thread holds the lock.
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        acquire(
+            &self.state,
+            |state| &mut state.write_waiters,
+            Self::try_write_state,
+        );
+
+        RwLockWriteGuard { lock: self }
+    }
+
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        atomic_block(|| Self::try_write_state(&mut self.state.lock()))
+            .map(|_| RwLockWriteGuard { lock: self })
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    fn try_read_state(state: &mut RwLockState) -> Option<()> {
+        if state.writer || !state.write_waiters.is_empty() {
+            return None;
+        }
+
+        state.readers += 1;
+        Some(())
+    }
+
+    fn try_write_state(state: &mut RwLockState) -> Option<()> {
+        if state.writer || state.readers > 0 {
+            return None;
+        }
+
+        state.writer = true;
+        Some(())
+    }
+
+    fn unlock_read(&self) {
+        atomic_block(|| {
+            let mut state = self.state.lock();
+            state.readers -= 1;
+
+            if state.readers == 0 {
+                state.write_waiters.wake_one();
+            }
+        });
+    }
+
+    fn unlock_write(&self) {
+        atomic_block(|| {
+            let mut state = self.state.lock();
+            state.writer = false;
+
+            if !state.write_waiters.wake_one() {
+                state.read_waiters.wake_all();
+            }
+        });
+    }
+}
+
+pub struct RwLockReadGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // Safety: the guard proves no writer holds the lock.
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.unlock_read();
+    }
+}
+
+pub struct RwLockWriteGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // Safety: the guard proves the lock is held exclusively.
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // Safety: the guard proves the lock is held exclusively.
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.unlock_write();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::multi_tasking::scheduler::tests::{add_blocked_thread, exit_threads, is_blocked};
+
+    #[test_case]
+    fn test_readers_share_the_lock() {
+        let lock = RwLock::new(0);
+
+        let first = lock.read();
+        let second = lock.try_read().unwrap();
+        assert!(lock.try_write().is_none());
+
+        drop(first);
+        assert!(lock.try_write().is_none());
+
+        drop(second);
+        *lock.try_write().unwrap() += 1;
+        assert_eq!(1, *lock.read());
+    }
+
+    #[test_case]
+    fn test_queued_writer_is_preferred() {
+        let lock = RwLock::new(());
+        let writer = add_blocked_thread();
+        let reader = add_blocked_thread();
+
+        let read = lock.read();
+        atomic_block(|| lock.state.lock().write_waiters.push(writer));
+
+        // a waiting writer keeps new readers out, so it cannot starve.
+        assert!(lock.try_read().is_none());
+        atomic_block(|| lock.state.lock().read_waiters.push(reader));
+
+        // the last reader only wakes the writer.
+        drop(read);
+        assert!(!is_blocked(writer));
+        assert!(is_blocked(reader));
+
+        // the woken writer leaves the queue and takes the lock.
+        atomic_block(|| lock.state.lock().write_waiters.remove(writer));
+        let write = lock.try_write().unwrap();
+        assert!(is_blocked(reader));
+
+        // without another writer waiting, the readers are woken.
+        drop(write);
+        assert!(!is_blocked(reader));
+
+        exit_threads(&[writer, reader]);
+    }
+
+    #[test_case]
+    fn test_unlock_write_wakes_writers_first() {
+        let lock = RwLock::new(());
+        let reader = add_blocked_thread();
+        let writer = add_blocked_thread();
+
+        let write = lock.write();
+        atomic_block(|| {
+            let mut state = lock.state.lock();
+            state.read_waiters.push(reader);
+            state.write_waiters.push(writer);
+        });
+
+        drop(write);
+        assert!(!is_blocked(writer));
+        assert!(is_blocked(reader));
+
+        exit_threads(&[reader, writer]);
+    }
+}
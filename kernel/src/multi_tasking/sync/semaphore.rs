@@ -0,0 +1,105 @@
+use essentials::sync::SpinMutex;
+use x86_64::interrupts::atomic_block;
+
+use crate::multi_tasking::sync::acquire;
+use crate::multi_tasking::wait_queue::WaitQueue;
+
+struct SemaphoreState {
+    permits: usize,
+    waiters: WaitQueue,
+}
+
+/// A counting semaphore, threads sleep while no permits are available.
+pub struct Semaphore {
+    state: SpinMutex<SemaphoreState>,
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Self {
+        Self {
+            state: SpinMutex::new(SemaphoreState {
+                permits,
+                waiters: WaitQueue::new(),
+            }),
+        }
+    }
+
+    /// Take a permit, sleeping until one is available.
+    pub fn acquire(&self) {
+        acquire(
+            &self.state,
+            |state| &mut state.waiters,
+            Self::try_acquire_state,
+        )
+    }
+
+    /// Take a permit when one is available, returns whether it did.
+    pub fn try_acquire(&self) -> bool {
+        atomic_block(|| Self::try_acquire_state(&mut self.state.lock())).is_some()
+    }
+
+    /// Return a permit, waking a thread waiting for it.
+    pub fn release(&self) {
+        atomic_block(|| {
+            let mut state = self.state.lock();
+            state.permits += 1;
+            state.waiters.wake_one();
+        });
+    }
+
+    pub fn available_permits(&self) -> usize {
+        atomic_block(|| self.state.lock().permits)
+    }
+
+    fn try_acquire_state(state: &mut SemaphoreState) -> Option<()> {
+        if state.permits == 0 {
+            return None;
+        }
+
+        state.permits -= 1;
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::multi_tasking::scheduler::tests::{add_blocked_thread, exit_threads, is_blocked};
+
+    #[test_case]
+    fn test_permits_are_counted() {
+        let semaphore = Semaphore::new(2);
+
+        semaphore.acquire();
+        assert!(semaphore.try_acquire());
+        assert!(!semaphore.try_acquire());
+        assert_eq!(0, semaphore.available_permits());
+
+        semaphore.release();
+        assert_eq!(1, semaphore.available_permits());
+        assert!(semaphore.try_acquire());
+    }
+
+    #[test_case]
+    fn test_release_wakes_one_waiter() {
+        let semaphore = Semaphore::new(0);
+        let first = add_blocked_thread();
+        let second = add_blocked_thread();
+
+        atomic_block(|| {
+            let mut state = semaphore.state.lock();
+            state.waiters.push(first);
+            state.waiters.push(second);
+        });
+
+        semaphore.release();
+        assert!(!is_blocked(first));
+        assert!(is_blocked(second));
+
+        semaphore.release();
+        assert!(!is_blocked(second));
+        assert_eq!(2, semaphore.available_permits());
+
+        exit_threads(&[first, second]);
+    }
+}
@@ -0,0 +1,89 @@
+use alloc::collections::VecDeque;
+
+use crate::multi_tasking::scheduler::{ThreadId, SCHEDULER};
+
+/// A single wait of a blocked thread, as returned by [`crate::multi_tasking::scheduler::Scheduler::block_current`].
+///
+/// A thread that is woken up stops waiting on every queue it was added to, so a `Waiter` left behind in another queue goes stale.
+/// Stale waiters are skipped when waking, even when the thread has blocked again in the meantime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Waiter {
+    pub(super) thread: ThreadId,
+    /// The id of the wait, as returned by [`crate::multi_tasking::scheduler::Thread::block`].
+    pub(super) wait: u64,
+}
+
+impl Waiter {
+    pub fn thread(&self) -> ThreadId {
+        self.thread
+    }
+}
+
+/// A FIFO queue of threads waiting for an event.
+///
+/// Waiting takes three steps, which let a thread wait on several queues at once:
+/// 1. block the current thread with [`crate::multi_tasking::scheduler::Scheduler::block_current`], and [`WaitQueue::push`] the waiter on every queue.
+/// 2. yield, the thread runs again once a queue wakes it or its deadline passes.
+/// 3. [`WaitQueue::remove`] the waiter from every queue again.
+///
+/// Dropping a queue wakes all of its waiters.
+#[derive(Debug, Default)]
+pub struct WaitQueue {
+    waiters: VecDeque<Waiter>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self {
+            waiters: VecDeque::new(),
+        }
+    }
+
+    pub fn push(&mut self, waiter: Waiter) {
+        self.waiters.push_back(waiter);
+    }
+
+    /// Take a waiter out of the queue without waking it, e.g. after it was woken by its deadline or by another queue.
+    pub fn remove(&mut self, waiter: Waiter) {
+        self.waiters.retain(|w| *w != waiter);
+    }
+
+    /// Wake the thread that has been waiting the longest, returns whether there was one.
+    ///
+    /// The woken thread is handed the rest of the current time slice.
+    pub fn wake_one(&mut self) -> bool {
+        while let Some(waiter) = self.waiters.pop_front() {
+            if SCHEDULER.wake(waiter, true) {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Wake every waiting thread, returns the number of threads woken.
+    ///
+    /// The thread that has been waiting the longest is handed the rest of the current time slice.
+    pub fn wake_all(&mut self) -> usize {
+        let mut woken = 0;
+
+        while let Some(waiter) = self.waiters.pop_front() {
+            if SCHEDULER.wake(waiter, woken == 0) {
+                woken += 1;
+            }
+        }
+
+        woken
+    }
+
+    /// Whether no thread is waiting, stale waiters that were not yet removed count as waiting.
+    pub fn is_empty(&self) -> bool {
+        self.waiters.is_empty()
+    }
+}
+
+impl Drop for WaitQueue {
+    fn drop(&mut self) {
+        self.wake_all();
+    }
+}
@@ -2,12 +2,19 @@ use alloc::borrow::Cow;
 use alloc::collections::VecDeque;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::mem::size_of;
+use core::ops::{Index, IndexMut};
 use essentials::address::VirtualAddress;
 use essentials::collections::FixedVec;
 use essentials::sync::SpinMutex;
 
+use crate::arch::x86_64::init::Fault;
+use crate::memory::heap::MAX_SLAB_OBJECT_SIZE;
 use crate::memory::MemoryMapper;
-use crate::multi_tasking::scheduler::ThreadBlocker;
+use crate::multi_tasking::scheduler::ThreadId;
+use crate::multi_tasking::wait_queue::WaitQueue;
+use syscall::Priority;
+use x86_64::paging::PhysicalPage;
 
 pub type Id = u16;
 pub type CowString = Cow<'static, str>;
@@ -54,6 +61,9 @@ pub struct ServiceSpec {
     /// The service's privilege level.
     pub privilege: Privilege,
 
+    /// The priority the threads of the service start with.
+    pub priority: Priority,
+
     pub intents_start: Id,
     pub intents_end: Id,
 
@@ -62,6 +72,8 @@ pub struct ServiceSpec {
 
     pub service: Option<Id>,
     pub entrypoint: ServiceEntrypoint,
+    /// The memory every instance starts with, shared copy-on-write into each of them, see [`MemoryMapper::share_copy_on_write`].
+    pub image: Option<MemoryMapper>,
     pub discovery_allowed: bool,
 }
 
@@ -79,6 +91,15 @@ pub struct Endpoint {
     pub response: FixedVec<16, EndpointParameter>,
 }
 
+/// The number of bytes a pipe buffers before writers have to wait.
+pub const PIPE_CAPACITY: usize = 2048;
+
+// pipe buffers and connections, with the counts of their `Arc`, come and go with every request and have to fit a slab
+// cache of the heap.
+const _: () = assert!(PIPE_CAPACITY <= MAX_SLAB_OBJECT_SIZE);
+const _: () =
+    assert!(2 * size_of::<usize>() + size_of::<SpinMutex<Connection>>() <= MAX_SLAB_OBJECT_SIZE);
+
 pub struct Pipe {
     pub buffer: VecDeque<u8>,
     pub write_arg_index: u8,
@@ -86,8 +107,8 @@ pub struct Pipe {
     pub current_arg_written: usize,
     pub closed: bool,
     pub reading_closed: bool,
-    pub write_block: Option<ThreadBlocker>,
-    pub read_block: Option<ThreadBlocker>,
+    pub write_waiters: WaitQueue,
+    pub read_waiters: WaitQueue,
 }
 
 impl Default for Pipe {
@@ -96,21 +117,34 @@ impl Default for Pipe {
             read_arg_index: 0,
             write_arg_index: 0,
             current_arg_written: 0,
-            write_block: None,
-            read_block: None,
+            write_waiters: WaitQueue::new(),
+            read_waiters: WaitQueue::new(),
             closed: false,
             reading_closed: false,
-            buffer: VecDeque::with_capacity(1024 * 2),
+            buffer: VecDeque::with_capacity(PIPE_CAPACITY),
         }
     }
 }
 
 pub struct Connection {
+    pub source_service: Id,
     pub target_service: Id,
+    pub target_spec: Id,
     pub current_request: Option<Request>,
-    pub request_close_block: Option<ThreadBlocker>,
+    pub request_close_waiters: WaitQueue,
     pub request: Pipe,
     pub response: Pipe,
+    /// Whether one of the services was terminated, the connection can no longer carry requests.
+    ///
+    /// The id of a terminated service is reused once it is reaped, so the ids of the connection must not be followed.
+    pub severed: bool,
+}
+
+impl Connection {
+    /// The ids of the services on both ends of the connection.
+    pub fn services(&self) -> [Id; 2] {
+        [self.source_service, self.target_service]
+    }
 }
 
 pub struct Service {
@@ -118,12 +152,94 @@ pub struct Service {
     pub spec_id: Id,
     pub connections: Vec<Arc<SpinMutex<Connection>>>,
     pub memory_map: MemoryMapper,
-    pub accept_block: Option<ThreadBlocker>,
+    pub accept_waiters: WaitQueue,
+    pub poll_waiters: WaitQueue,
+    /// The fault the service was terminated for, its threads no longer run once it is set.
+    pub fault: Option<Fault>,
+}
+
+/// The services indexed by their id, the slots of reaped services are reused by new ones.
+#[derive(Default)]
+pub struct Services {
+    slots: Vec<Option<Service>>,
+}
+
+impl Services {
+    pub const fn new() -> Self {
+        Self { slots: Vec::new() }
+    }
+
+    /// The id the next service is inserted with.
+    pub fn next_id(&self) -> Id {
+        self.slots
+            .iter()
+            .position(Option::is_none)
+            .unwrap_or(self.slots.len()) as Id
+    }
+
+    /// Insert `service` at its id, which must be [`Services::next_id`].
+    pub fn insert(&mut self, service: Service) {
+        let id = service.id as usize;
+
+        match self.slots.get_mut(id) {
+            Some(slot) => *slot = Some(service),
+            None => self.slots.push(Some(service)),
+        }
+    }
+
+    pub fn remove(&mut self, id: Id) -> Option<Service> {
+        self.slots.get_mut(id as usize)?.take()
+    }
+
+    pub fn get_mut(&mut self, id: Id) -> Option<&mut Service> {
+        self.slots.get_mut(id as usize)?.as_mut()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Service> {
+        self.slots.iter().flatten()
+    }
+}
+
+impl Index<usize> for Services {
+    type Output = Service;
+
+    fn index(&self, id: usize) -> &Service {
+        self.slots[id]
+            .as_ref()
+            .expect("services should not be used after they were reaped")
+    }
+}
+
+impl IndexMut<usize> for Services {
+    fn index_mut(&mut self, id: usize) -> &mut Service {
+        self.slots[id]
+            .as_mut()
+            .expect("services should not be used after they were reaped")
+    }
+}
+
+/// Frames that several services can map at once, created by the owner and granted to its peers.
+pub struct SharedMemory {
+    pub owner: Id,
+    /// The frames of the memory, the object holds a reference to each so they outlive the mappings.
+    pub frames: Vec<PhysicalPage>,
+    /// The services besides the owner that are allowed to map the memory.
+    pub granted: Vec<Id>,
+    /// The services that mapped the memory, with the start of their mapping.
+    pub mappings: Vec<(Id, VirtualAddress)>,
+}
+
+impl SharedMemory {
+    pub fn may_map(&self, service: Id) -> bool {
+        self.owner == service || self.granted.contains(&service)
+    }
 }
 
 pub struct Request {
     pub endpoint_id: Id,
     pub accepted: bool,
+    /// The thread that accepted the request, which inherits the priority of a client waiting on it.
+    pub handler: Option<ThreadId>,
 }
 
 #[cfg(test)]
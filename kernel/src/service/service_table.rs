@@ -1,26 +1,42 @@
 use alloc::vec::Vec;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 pub use endpoint_ref::*;
 use essentials::address::VirtualAddress;
 use essentials::collections::FixedVec;
-use essentials::sync::{PanicOnce, SpinMutex};
+use essentials::sync::{PanicOnce, SpinMutex, SpinMutexGuard};
 pub use service_ref::*;
 pub use spec_ref::*;
+use syscall::{InfoName, Priority, ServiceInfo};
+pub use user_buffer::*;
 use x86_64::paging::{PageSize, PageTableEntryFlags, VirtualPage};
 
-use crate::memory::{MemoryMapper, NewMappingError};
+use crate::arch::x86_64::smp::{current_cpu, CpuId};
+use crate::arch::x86_64::tlb::Shootdown;
+use crate::memory::{MemoryMapper, NewMappingError, Region, RegionKind};
 use crate::multi_tasking::scheduler::{Thread, ThreadStack, SCHEDULER};
+use crate::multi_tasking::sync::{Mutex, RwLock};
+use crate::multi_tasking::wait_queue::WaitQueue;
 use crate::service::model::*;
 use crate::service::service_table::spec_ref::ServiceSpecRef;
 
 mod endpoint_ref;
 mod service_ref;
 mod spec_ref;
+mod user_buffer;
+
+/// The number of pages a service stack can grow to, they are backed as the stack grows.
+const STACK_PAGES: usize = 256;
+
+/// The number of pages of shared memory a service can own at once.
+pub const SHARED_MEMORY_QUOTA: usize = 1 << 14;
 
 #[derive(Debug)]
 pub enum NewServiceError {
     FailedToCreateNewMemoryMap(NewMappingError),
     FailedToCreateStack(NewMappingError),
+    FailedToShareImage(NewMappingError),
     SpecNotFound,
 }
 
@@ -44,25 +60,109 @@ pub struct NewEndpoint {
     pub response: FixedVec<16, EndpointParameter>,
 }
 
+/// The specs, services and the objects they share.
+///
+/// The locks are always taken in the order of the fields: `intents` and `endpoints`, then `specs`, then
+/// `shared_memory`, then `services`, and finally the lock of a single connection. Application processors use the table
+/// at the same time, so taking two of them in the opposite order could deadlock.
 pub struct ServiceTable {
-    specs: SpinMutex<Vec<ServiceSpec>>,
-    intents: SpinMutex<Vec<Intent>>,
-    endpoints: SpinMutex<Vec<Endpoint>>,
+    /// The intents and endpoints are only changed when registering a spec, so they can be read without spinning.
+    ///
+    /// These locks and the ones of the specs and shared memory can sleep, so they have to be taken before any of the
+    /// spin locks, and never in interrupt or fault context.
+    intents: RwLock<Vec<Intent>>,
+    endpoints: RwLock<Vec<Endpoint>>,
+    /// The specs are mostly read, they are only written when registering one and when its instance changes.
+    specs: RwLock<Vec<ServiceSpec>>,
+    /// The shared memory objects indexed by their id, revoked objects are `None`.
+    shared_memory: Mutex<Vec<Option<SharedMemory>>>,
     root_memory_map: PanicOnce<MemoryMapper>,
-    services: SpinMutex<Vec<Service>>,
+    services: ServicesLock,
+    /// Whether a terminated service may be left to reap, see [`ServiceTable::reap_terminated`].
+    reap_pending: AtomicBool,
+}
+
+/// The spin lock of the services, which remembers the CPU holding it.
+///
+/// Kernel code can fault on service memory while it holds the lock, the page fault handler must not take the lock
+/// again on that CPU, see [`ServiceTable::is_locked_by_current_cpu`].
+struct ServicesLock {
+    services: SpinMutex<Services>,
+    holder: AtomicUsize,
+}
+
+struct ServicesGuard<'a> {
+    services: SpinMutexGuard<'a, Services>,
+    holder: &'a AtomicUsize,
+}
+
+impl ServicesLock {
+    const NO_HOLDER: usize = usize::MAX;
+
+    const fn new() -> Self {
+        Self {
+            services: SpinMutex::new(Services::new()),
+            holder: AtomicUsize::new(Self::NO_HOLDER),
+        }
+    }
+
+    fn lock(&self) -> ServicesGuard<'_> {
+        let services = self.services.lock();
+        self.holder.store(current_cpu(), Ordering::Relaxed);
+
+        ServicesGuard {
+            services,
+            holder: &self.holder,
+        }
+    }
+
+    fn holder(&self) -> Option<CpuId> {
+        Some(self.holder.load(Ordering::Relaxed)).filter(|&cpu| cpu != Self::NO_HOLDER)
+    }
+}
+
+impl Deref for ServicesGuard<'_> {
+    type Target = Services;
+
+    fn deref(&self) -> &Services {
+        &self.services
+    }
+}
+
+impl DerefMut for ServicesGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Services {
+        &mut self.services
+    }
+}
+
+impl Drop for ServicesGuard<'_> {
+    fn drop(&mut self) {
+        // runs before the spin lock is released, so no other CPU can have taken it yet.
+        self.holder
+            .store(ServicesLock::NO_HOLDER, Ordering::Relaxed);
+    }
 }
 
 impl ServiceTable {
     pub const fn new() -> Self {
         Self {
-            specs: SpinMutex::new(Vec::new()),
-            intents: SpinMutex::new(Vec::new()),
-            endpoints: SpinMutex::new(Vec::new()),
+            intents: RwLock::new(Vec::new()),
+            endpoints: RwLock::new(Vec::new()),
+            specs: RwLock::new(Vec::new()),
+            shared_memory: Mutex::new(Vec::new()),
             root_memory_map: PanicOnce::new(),
-            services: SpinMutex::new(Vec::new()),
+            services: ServicesLock::new(),
+            reap_pending: AtomicBool::new(false),
         }
     }
 
+    /// Whether the executing CPU holds the lock on the services, so locking them again would deadlock.
+    ///
+    /// Only the CPU holding the lock sets and clears it as holder, so the answer is exact for the executing CPU.
+    pub fn is_locked_by_current_cpu(&self) -> bool {
+        self.services.holder() == Some(current_cpu())
+    }
+
     pub fn set_root_memory_map(&self, memory_map: MemoryMapper) {
         self.root_memory_map.initialize_with(memory_map);
     }
@@ -88,11 +188,11 @@ impl ServiceTable {
             return Err(NewSpecError::NameTaken);
         }
 
-        let specs = self.specs.lock();
+        let specs = self.specs.read();
         let new_spec_id = specs.len() as Id;
         drop(specs);
 
-        let mut intents = self.intents.lock();
+        let mut intents = self.intents.write();
 
         let intents_start = intents.len() as Id;
         for new_intent in spec_intents {
@@ -109,7 +209,7 @@ impl ServiceTable {
         }
         let intents_end = intents.len() as Id;
 
-        let mut endpoints = self.endpoints.lock();
+        let mut endpoints = self.endpoints.write();
         let endpoints_start = endpoints.len() as Id;
         endpoints.extend(
             spec_endpoints
@@ -126,17 +226,19 @@ impl ServiceTable {
         );
         let endpoints_end = endpoints.len() as Id;
 
-        let mut specs = self.specs.lock();
+        let mut specs = self.specs.write();
         specs.push(ServiceSpec {
             id: new_spec_id,
             name,
             privilege,
+            priority: Priority::default(),
             intents_start,
             intents_end,
             endpoints_start,
             endpoints_end,
             entrypoint,
             service: None,
+            image: None,
             discovery_allowed,
         });
 
@@ -160,51 +262,56 @@ impl ServiceTable {
 
     pub fn resolve_spec_name(&self, name: &str) -> Option<ServiceSpecRef> {
         self.specs
-            .lock()
+            .read()
             .iter()
             .find(|spec| spec.name == name)
             .map(|spec| ServiceSpecRef::new(self, spec.id))
     }
 
+    /// The flags of writable data pages, like stacks and heaps, of a service with `privilege`.
+    fn data_page_flags(privilege: Privilege) -> PageTableEntryFlags {
+        let mut flags = PageTableEntryFlags::default();
+        flags.set_present(true);
+        flags.set_writable(true);
+
+        if privilege != Privilege::Kernel {
+            flags.set_user_accessible(true);
+        }
+
+        flags
+    }
+
     fn create_stack(
         mapper: &mut MemoryMapper,
         privilege: Privilege,
     ) -> Result<ThreadStack, NewMappingError> {
         let size = PageSize::Size4Kib;
-        let initial_pages = 4;
-
-        // begin on the last entry from the l4 index 8
-        let stack_page = VirtualPage::new(VirtualAddress::from_l4_index(9), size).prev();
-
-        let flags = match privilege {
-            Privilege::Kernel => {
-                let mut flags = PageTableEntryFlags::default();
-                flags.set_present(true);
-                flags.set_writable(true);
-                flags
-            }
-            _ => {
-                let mut flags = PageTableEntryFlags::default();
-                flags.set_present(true);
-                flags.set_writable(true);
-                flags.set_user_accessible(true);
-                flags
-            }
-        };
-
-        let mut current_page = stack_page;
-        for _ in 0..initial_pages {
-            // we discard the cache flush since its not mapped.
-            let _ = mapper.new_map(flags, flags, current_page)?;
-            current_page = current_page.prev();
-        }
 
-        Ok(ThreadStack::from_page(stack_page))
+        // end on the last page of the l4 index 8
+        let stack_end = VirtualAddress::from_l4_index(9);
+        let stack_start = stack_end - STACK_PAGES * size.as_usize();
+        let guard_start = stack_start - size.as_usize();
+
+        let flags = Self::data_page_flags(privilege);
+
+        mapper.reserve_lazy_region(Some(stack_start), STACK_PAGES, RegionKind::Stack, flags)?;
+        mapper
+            .reserve_region(Region::new(
+                guard_start,
+                1,
+                RegionKind::Guard,
+                PageTableEntryFlags::default(),
+            ))
+            .map_err(NewMappingError::Region)?;
+
+        Ok(ThreadStack::from_page(
+            VirtualPage::new(stack_end, size).prev(),
+        ))
     }
 
     pub fn start_service(&self, spec_id: Id) -> Result<ServiceRef, NewServiceError> {
+        let mut specs = self.specs.write();
         let mut services = self.services.lock();
-        let mut specs = self.specs.lock();
 
         let spec = specs
             .get_mut(spec_id as usize)
@@ -215,17 +322,25 @@ impl ServiceTable {
             .new_mapper(true)
             .map_err(NewServiceError::FailedToCreateNewMemoryMap)?;
 
-        let id = services.len() as Id;
+        let id = services.next_id();
 
         let stack = Self::create_stack(&mut memory_map, spec.privilege)
             .map_err(NewServiceError::FailedToCreateStack)?;
 
-        services.push(Service {
+        let shootdowns = match &mut spec.image {
+            Some(image) => Self::share_image(image, &mut memory_map)
+                .map_err(NewServiceError::FailedToShareImage)?,
+            None => Vec::new(),
+        };
+
+        services.insert(Service {
             id,
             memory_map,
             spec_id,
             connections: Vec::new(),
-            accept_block: None,
+            accept_waiters: WaitQueue::new(),
+            poll_waiters: WaitQueue::new(),
+            fault: None,
         });
 
         spec.service = Some(id);
@@ -235,16 +350,262 @@ impl ServiceTable {
             ServiceEntrypoint::Elf() => todo!(),
         };
 
-        let main_thread = unsafe { Thread::start_new(Some("Main"), stack, addr, Some(id)) };
+        let mut main_thread = unsafe { Thread::start_new(Some("Main"), stack, addr, Some(id)) };
+        main_thread.set_priority(spec.priority);
+
+        drop(specs);
+        drop(services);
+
+        // the instance must not see writes other CPUs still make through the old writable mappings of the image.
+        for shootdown in shootdowns {
+            shootdown.wait();
+        }
 
         SCHEDULER.add_thread(main_thread);
 
         Ok(ServiceRef::new(self, id))
     }
 
+    /// Share the regions of `image` copy-on-write into the memory map of a new instance.
+    ///
+    /// Returns the shootdowns of the CPUs which may still write to the image through the old mappings.
+    fn share_image(
+        image: &mut MemoryMapper,
+        memory_map: &mut MemoryMapper,
+    ) -> Result<Vec<Shootdown>, NewMappingError> {
+        let regions: Vec<VirtualAddress> = image
+            .regions()
+            .iter()
+            .filter(|region| region.kind() != RegionKind::Inherited)
+            .map(|region| region.start())
+            .collect();
+
+        let mut shootdowns = Vec::new();
+
+        for start in regions {
+            let (_, cache_flush) = image.share_copy_on_write(memory_map, start)?;
+            shootdowns.extend(cache_flush.flush_remote());
+        }
+
+        Ok(shootdowns)
+    }
+
+    /// Fill `entries` with the services starting at index `start`, returns the number of entries written and whether more services follow.
+    pub fn service_info(&self, start: usize, entries: &mut [ServiceInfo]) -> (usize, bool) {
+        let specs = self.specs.read();
+        let services = self.services.lock();
+
+        let mut written = 0;
+        for (service, entry) in services.iter().skip(start).zip(entries.iter_mut()) {
+            *entry = ServiceInfo {
+                id: service.id,
+                spec_id: service.spec_id,
+                connections: service.connections.len() as u32,
+                memory: service.memory_map.owned_memory() as u64,
+                name: InfoName::new(&specs[service.spec_id as usize].name),
+            };
+            written += 1;
+        }
+
+        (written, start + written < services.iter().count())
+    }
+
+    /// Drop the terminated services whose threads all stopped and whose memory map no CPU has active anymore.
+    ///
+    /// Dropping a service frees its memory map, and the next service started takes over its id.
+    /// A CPU drops the translations of a memory map when it switches away from it, so no shootdown is needed once
+    /// no CPU has the map active.
+    /// The services that cannot be dropped yet are tried again on the next call.
+    ///
+    /// The shared memory of the terminated services is released here rather than when they are terminated, because
+    /// its lock sleeps and a service is terminated in fault context.
+    pub fn reap_terminated(&self) {
+        if !self.reap_pending.swap(false, Ordering::AcqRel) {
+            return;
+        }
+
+        let mut specs = self.specs.write();
+
+        let terminated: Vec<Id> = self
+            .services
+            .lock()
+            .iter()
+            .filter(|service| service.fault.is_some())
+            .map(|service| service.id)
+            .collect();
+
+        for &id in &terminated {
+            ServiceRef::new(self, id).release_shared_memory();
+        }
+
+        let mut services = self.services.lock();
+        let mut reaped = Vec::new();
+
+        for id in terminated {
+            if SCHEDULER.runs_service(id) || services[id as usize].memory_map.is_active_anywhere() {
+                self.reap_pending.store(true, Ordering::Release);
+                continue;
+            }
+
+            // the spec may already have started a new instance.
+            let spec = &mut specs[services[id as usize].spec_id as usize];
+            if spec.service == Some(id) {
+                spec.service = None;
+            }
+
+            reaped.extend(services.remove(id));
+        }
+
+        drop(services);
+        drop(reaped);
+    }
+
     pub fn get_service_by_id(&self, id: Id) -> ServiceRef<'_> {
         ServiceRef::new(self, id)
     }
 }
 
 pub static SERVICE_TABLE: ServiceTable = ServiceTable::new();
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use alloc::borrow::Cow;
+    use essentials::address::PhysicalAddress;
+    use x86_64::paging::PhysicalPage;
+
+    use crate::memory::{FRAME_ALLOCATOR, ROOT_MAPPER};
+    use crate::multi_tasking::scheduler::tests::add_blocked_thread_until;
+    use crate::multi_tasking::scheduler::Tick;
+    use crate::multi_tasking::wait_queue::Waiter;
+
+    /// A table of its own for a test, the services started in it are never run.
+    pub fn new_table() -> ServiceTable {
+        let table = ServiceTable::new();
+        let root_memory_map = ROOT_MAPPER
+            .lock()
+            .borrow_to_new_mapper(true)
+            .expect("the root map should be borrowable");
+
+        table.set_root_memory_map(root_memory_map);
+        table
+    }
+
+    /// Register a spec without intents or endpoints.
+    pub fn register_spec(table: &ServiceTable, name: &'static str) -> Id {
+        register_spec_with_privilege(table, name, Privilege::User)
+    }
+
+    /// Register a spec without intents or endpoints, its services may pass kernel memory to syscalls when `privilege`
+    /// is [`Privilege::Kernel`].
+    pub fn register_spec_with_privilege(
+        table: &ServiceTable,
+        name: &'static str,
+        privilege: Privilege,
+    ) -> Id {
+        let entrypoint = ServiceEntrypoint::MappedFunction(VirtualAddress::new(0x1000));
+
+        unsafe { table.register_spec(Cow::Borrowed(name), privilege, false, entrypoint, [], []) }
+            .expect("the spec name should be unique")
+            .id()
+    }
+
+    /// Block a thread that stands in for a poller of `service`, like [`ServiceRef::block_until_poll_event`] would.
+    pub fn add_poller(table: &ServiceTable, service: Id, deadline: Option<Tick>) -> Waiter {
+        let waiter = add_blocked_thread_until(deadline);
+        table.services.lock()[service as usize]
+            .poll_waiters
+            .push(waiter);
+        waiter
+    }
+
+    /// Stop the threads of the `services`, so the scheduler can reuse their slots.
+    pub fn stop_services(services: &[Id]) {
+        for service in services {
+            SCHEDULER.exit_service_threads(*service);
+        }
+    }
+
+    #[test_case]
+    fn test_stacks_are_backed_as_they_grow() {
+        let table = new_table();
+        let service = table.start_service(register_spec(&table, "stack")).unwrap();
+
+        let stack_start = VirtualAddress::from_l4_index(9) - STACK_PAGES * 4096;
+        let guard = stack_start - 4096;
+        let frame_of = |addr| {
+            table.services.lock()[service.id() as usize]
+                .memory_map
+                .translate_virtual_to_physical(addr)
+        };
+
+        // the deepest page of the stack is only backed once it is touched.
+        assert_eq!(None, frame_of(stack_start));
+        assert!(service.populate(stack_start));
+        assert!(frame_of(stack_start).is_some());
+
+        // running into the guard page is an overflow, which is not resolved.
+        assert!(!service.populate(guard));
+        assert_eq!(None, frame_of(guard));
+
+        stop_services(&[service.id()]);
+    }
+
+    #[test_case]
+    fn test_services_lock_remembers_its_holder() {
+        let table = new_table();
+        assert!(!table.is_locked_by_current_cpu());
+
+        let services = table.services.lock();
+        assert!(table.is_locked_by_current_cpu());
+
+        drop(services);
+        assert!(!table.is_locked_by_current_cpu());
+    }
+
+    #[test_case]
+    fn test_instances_share_the_image_copy_on_write() {
+        let table = new_table();
+        let spec = register_spec(&table, "image");
+        let data = b"the image of the service";
+        let addr = table.root_memory_map.regions().find_free(1, 0).unwrap();
+
+        ServiceSpecRef::new(&table, spec)
+            .load_image_segment(addr, data, RegionKind::Anonymous, true)
+            .unwrap();
+
+        let first = table.start_service(spec).unwrap();
+        let second = table.start_service(spec).unwrap();
+
+        let frame_of = |service: &ServiceRef| {
+            table.services.lock()[service.id() as usize]
+                .memory_map
+                .translate_virtual_to_physical(addr)
+                .unwrap()
+        };
+        let contents = |frame: PhysicalAddress| {
+            let offset = table.root_memory_map.physical_memory_offset();
+            let ptr = (frame.as_u64() + offset) as *const u8;
+            unsafe { core::slice::from_raw_parts(ptr, data.len()) }
+        };
+
+        let shared = frame_of(&first);
+        assert_eq!(shared, frame_of(&second));
+        assert_eq!(data, contents(shared));
+
+        // the image and both instances hold a reference.
+        let shared_page = PhysicalPage::new(shared, PageSize::Size4Kib);
+        assert_eq!(3, FRAME_ALLOCATOR.frame_references(shared_page));
+
+        // a write gives the writer its own copy, and returns its reference to the shared frame.
+        assert!(first.copy_on_write(addr));
+
+        let copy = frame_of(&first);
+        assert_ne!(shared, copy);
+        assert_eq!(data, contents(copy));
+        assert_eq!(shared, frame_of(&second));
+        assert_eq!(2, FRAME_ALLOCATOR.frame_references(shared_page));
+
+        stop_services(&[first.id(), second.id()]);
+    }
+}
@@ -16,7 +16,7 @@ impl<'a> EndpointRef<'a> {
     }
 
     pub fn is_allowed(&self, privilege: Privilege) -> bool {
-        let endpoints = self.table.endpoints.lock();
+        let endpoints = self.table.endpoints.read();
         let endpoint_privilege = endpoints[self.id as usize].min_privilege;
 
         privilege >= endpoint_privilege
@@ -1,21 +1,32 @@
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use core::cmp::min;
 use core::fmt::{Debug, Formatter};
+use core::mem::size_of_val;
 use core::ops::{Deref, DerefMut};
+use core::sync::atomic::Ordering;
 use essentials::address::VirtualAddress;
 use essentials::collections::FixedVec;
 use essentials::sync::SpinMutex;
-use x86_64::paging::{PageTableEntryFlags, VirtualPage};
-
-use crate::multi_tasking::scheduler::{ThreadBlocker, SCHEDULER};
-use crate::service::model::{Connection, Endpoint, Id, Pipe, Request};
+use syscall::{PollEntry, PollEvents, Priority};
+use x86_64::paging::PhysicalPage;
+
+use crate::arch::x86_64::init::Fault;
+use crate::memory::{
+    ModifyMappingError, NewMappingError, Region, RegionKind, TableCacheFlush, FRAME_ALLOCATOR,
+};
+use crate::multi_tasking::scheduler::{ThreadId, Tick, SCHEDULER};
+use crate::multi_tasking::wait_queue::{WaitQueue, Waiter};
+use crate::service::model::{Connection, Endpoint, Id, Pipe, Request, Services, SharedMemory};
 use crate::service::service_table::spec_ref::ServiceSpecRef;
-use crate::service::{EndpointParameter, NewServiceError, Privilege, ServiceTable};
+use crate::service::service_table::user_buffer::{UserAccess, UserBuffer, UserBufferError};
+use crate::service::{EndpointParameter, NewServiceError, ServiceTable, SHARED_MEMORY_QUOTA};
 
 #[derive(Debug)]
 pub enum ConnectError {
     SpecDoesNotExist,
     FailedToStartService(NewServiceError),
+    TargetTerminated,
 }
 
 #[derive(Debug)]
@@ -23,6 +34,8 @@ pub enum CreateRequestError {
     InvalidEndpointId,
     ConnectionBusy,
     NotPermitted,
+    /// The service on the other end was terminated because it faulted.
+    TargetTerminated,
 }
 
 #[derive(Debug)]
@@ -31,14 +44,57 @@ pub enum WriteError {
     NoOpenRequest,
     ParameterOverflow,
     RequestClosed,
+    /// The part of the buffer that was about to be written is not readable by the service.
+    InvalidBuffer(UserBufferError),
 }
 
 #[derive(Debug)]
 pub enum ReadError {
     InvalidConnection,
     RequestClosed,
+    /// The part of the buffer that was about to be filled is not writable by the service.
+    InvalidBuffer(UserBufferError),
+}
+
+#[derive(Debug)]
+pub enum PollError {
+    InvalidConnection,
 }
 
+#[derive(Debug)]
+pub enum MapError {
+    OutOfMemory,
+    /// The range is empty, outside the lower half or overlaps a region in use.
+    InvalidRange,
+}
+
+#[derive(Debug)]
+pub enum SharedMemoryError {
+    /// The shared memory does not exist, was revoked, or may not be used by the service.
+    NotFound,
+    /// Only the owner can grant or revoke the shared memory.
+    NotOwner,
+    InvalidConnection,
+    OutOfMemory,
+    /// The service would own more shared memory than [`SHARED_MEMORY_QUOTA`].
+    QuotaExceeded,
+    /// The page count is larger than the shared memory, or the range is outside the lower half or in use.
+    InvalidRange,
+    /// The service already mapped the shared memory.
+    AlreadyMapped,
+    /// The service did not map the shared memory.
+    NotMapped,
+}
+
+#[derive(Debug)]
+pub enum UnmapError {
+    /// No anonymous region starts at the address.
+    RegionNotFound,
+}
+
+/// Anonymous mappings of at least this many pages are backed on demand.
+const LAZY_ANONYMOUS_PAGES: usize = 16;
+
 pub struct ServiceRef<'a> {
     table: &'a ServiceTable,
     id: Id,
@@ -61,51 +117,222 @@ impl<'a> ServiceRef<'a> {
         services[self.id as usize].memory_map.set_active()
     }
 
-    pub fn deref_incoming_pointer<'b>(&self, address: VirtualAddress) -> Option<&'b mut [u8]> {
-        let specs = self.table.specs.lock();
-        let services = self.table.services.lock();
-        let service = &services[self.id as usize];
-        let spec = &specs[service.spec_id as usize];
+    /// Copy the bytes of `buffer` from `offset` into `destination`, after checking the service may read every page they span.
+    pub fn copy_from_user(
+        &self,
+        buffer: UserBuffer,
+        offset: usize,
+        destination: &mut [u8],
+    ) -> Result<(), UserBufferError> {
+        let specs = self.table.specs.read();
+        let mut services = self.table.services.lock();
+        let service = &mut services[self.id as usize];
+        let privilege = specs[service.spec_id as usize].privilege;
+
+        let source = buffer.checked_slice(
+            &mut service.memory_map,
+            privilege,
+            offset,
+            destination.len(),
+            UserAccess::Read,
+        )?;
+
+        destination.copy_from_slice(source);
+        Ok(())
+    }
 
-        let is_page_safe = |flags: PageTableEntryFlags| -> bool {
-            match spec.privilege {
-                Privilege::Kernel => flags.present(),
-                _ => flags.present() && flags.user_accessible(),
+    /// Copy `source` into `buffer` from `offset`, after checking the service may write every page it spans.
+    pub fn copy_to_user<T: Copy>(
+        &self,
+        buffer: UserBuffer,
+        offset: usize,
+        source: &[T],
+    ) -> Result<(), UserBufferError> {
+        loop {
+            match self.try_copy_to_user(buffer, offset, source) {
+                Err(UserBufferError::ReadOnly(addr)) => self.copy_user_page_on_write(addr)?,
+                result => return result,
             }
+        }
+    }
+
+    fn try_copy_to_user<T: Copy>(
+        &self,
+        buffer: UserBuffer,
+        offset: usize,
+        source: &[T],
+    ) -> Result<(), UserBufferError> {
+        let specs = self.table.specs.read();
+        let mut services = self.table.services.lock();
+        let service = &mut services[self.id as usize];
+        let privilege = specs[service.spec_id as usize].privilege;
+
+        let destination = buffer.checked_slice(
+            &mut service.memory_map,
+            privilege,
+            offset,
+            size_of_val(source),
+            UserAccess::Write,
+        )?;
+
+        // Safety: the destination is exactly as large as the source, and copying bytes keeps any padding untyped.
+        unsafe {
+            destination
+                .as_mut_ptr()
+                .copy_from_nonoverlapping(source.as_ptr() as *const u8, destination.len())
         };
 
-        let (flags, size) = service.memory_map.effective_flags(address)?;
-        let page = VirtualPage::new(address, size);
+        Ok(())
+    }
 
-        if !is_page_safe(flags) {
-            return None;
+    /// Copy the read-only page of a user buffer containing `addr` when it is shared copy-on-write, so the kernel can
+    /// write to it on the next try.
+    fn copy_user_page_on_write(&self, addr: VirtualAddress) -> Result<(), UserBufferError> {
+        if self.copy_on_write(addr) {
+            Ok(())
+        } else {
+            Err(UserBufferError::NotAccessible)
         }
+    }
 
-        let mut len = (page.end_addr() - address).as_usize();
+    /// Back the page containing `addr` when it lies in a lazy region of the service, returns whether it did.
+    pub fn populate(&self, addr: VirtualAddress) -> bool {
+        let mut services = self.table.services.lock();
 
-        if let Some((next_flags, next_size)) = service.memory_map.effective_flags(address) {
-            if is_page_safe(next_flags) {
-                len += next_size.as_usize();
+        match services[self.id as usize].memory_map.populate(addr) {
+            Ok(cache_flush) => {
+                cache_flush.flush();
+                true
             }
+            // another thread of the service backed it first.
+            Err(NewMappingError::AlreadyMapped) => true,
+            Err(_) => false,
         }
+    }
 
-        unsafe { Some(core::slice::from_raw_parts_mut(address.as_mut_ptr(), len)) }
+    /// Give the page containing `addr` its own copy of a frame shared copy-on-write, returns whether it did.
+    ///
+    /// Returns once no other CPU reads the shared frame through the old mapping anymore, so threads of the service do not
+    /// miss the writes to the copy.
+    pub fn copy_on_write(&self, addr: VirtualAddress) -> bool {
+        let mut services = self.table.services.lock();
+        let memory_map = &mut services[self.id as usize].memory_map;
+
+        let cache_flush = match memory_map.copy_on_write(addr) {
+            Ok(cache_flush) => cache_flush,
+            // another thread of the service copied it first.
+            Err(ModifyMappingError::NotCopyOnWrite) => {
+                return memory_map
+                    .effective_flags(addr)
+                    .is_some_and(|(flags, _)| flags.writable());
+            }
+            Err(_) => return false,
+        };
+
+        drop(services);
+
+        if let Some(shootdown) = cache_flush.flush_remote() {
+            shootdown.wait();
+        }
+
+        true
+    }
+
+    /// Map `pages` zeroed pages in the address space of the service, at `addr` when given or at a free range otherwise.
+    ///
+    /// Large mappings are only reserved, their pages are backed when they are first touched.
+    pub fn map_anonymous(
+        &self,
+        addr: Option<VirtualAddress>,
+        pages: usize,
+    ) -> Result<VirtualAddress, MapError> {
+        let specs = self.table.specs.read();
+        let mut services = self.table.services.lock();
+        let service = &mut services[self.id as usize];
+        let flags = ServiceTable::data_page_flags(specs[service.spec_id as usize].privilege);
+
+        if let Some(addr) = addr {
+            let region = Region::new(addr, pages, RegionKind::Anonymous, flags);
+
+            if !service.memory_map.regions().in_window(&region) {
+                return Err(MapError::InvalidRange);
+            }
+        }
+
+        let to_map_error = |err| match err {
+            NewMappingError::OutOfFrames => MapError::OutOfMemory,
+            _ => MapError::InvalidRange,
+        };
+
+        if pages >= LAZY_ANONYMOUS_PAGES {
+            let region = service
+                .memory_map
+                .reserve_lazy_region(addr, pages, RegionKind::Anonymous, flags)
+                .map_err(to_map_error)?;
+
+            return Ok(region.start());
+        }
+
+        let (region, cache_flush) = service
+            .memory_map
+            .map_region(addr, pages, RegionKind::Anonymous, flags)
+            .map_err(to_map_error)?;
+
+        cache_flush.flush();
+        Ok(region.start())
+    }
+
+    /// Unmap the anonymous region starting at `addr`, see [`ServiceRef::map_anonymous`].
+    pub fn unmap_anonymous(&self, addr: VirtualAddress) -> Result<(), UnmapError> {
+        let mut services = self.table.services.lock();
+        let memory_map = &mut services[self.id as usize].memory_map;
+
+        let is_anonymous = memory_map
+            .regions()
+            .find(addr)
+            .is_some_and(|region| region.start() == addr && region.kind() == RegionKind::Anonymous);
+
+        if !is_anonymous {
+            return Err(UnmapError::RegionNotFound);
+        }
+
+        let (_, cache_flush) = memory_map
+            .unmap_region(addr)
+            .expect("anonymous regions are owned by the service");
+
+        cache_flush.flush();
+        Ok(())
     }
 
     pub fn connect_to(&self, target_spec: Id) -> Result<Id, ConnectError> {
-        let specs = self.table.specs.lock();
+        let mut specs = self.table.specs.read();
 
         let src = specs
             .get(target_spec as usize)
             .ok_or(ConnectError::SpecDoesNotExist)?;
 
-        let target_service = match src.service {
+        // a terminated instance stays in its spec until it is reaped.
+        let running = src
+            .service
+            .filter(|id| self.table.services.lock()[*id as usize].fault.is_none());
+
+        let target_service = match running {
             Some(service_id) => ServiceRef::new(self.table, service_id),
             None => {
                 drop(specs);
-                self.table
+                let service = self
+                    .table
                     .start_service(target_spec)
-                    .map_err(ConnectError::FailedToStartService)?
+                    .map_err(ConnectError::FailedToStartService)?;
+
+                // the spec lock keeps the reaper away, but the instance may be gone before it is taken again.
+                specs = self.table.specs.read();
+
+                if specs[target_spec as usize].service != Some(service.id()) {
+                    return Err(ConnectError::TargetTerminated);
+                }
+
+                service
             }
         };
 
@@ -114,11 +341,14 @@ impl<'a> ServiceRef<'a> {
         let handle = service.connections.len() as Id;
 
         let new_conn = Arc::new(SpinMutex::new(Connection {
+            source_service: self.id,
             target_service: target_service.id(),
+            target_spec,
             current_request: None,
             request: Pipe::default(),
             response: Pipe::default(),
-            request_close_block: None,
+            request_close_waiters: WaitQueue::new(),
+            severed: false,
         }));
 
         service.connections.push(new_conn.clone());
@@ -133,26 +363,60 @@ impl<'a> ServiceRef<'a> {
         let services = self.table.services.lock();
         let service = &services[self.id as usize];
 
-        return service
+        let conn = service.connections.get(connection_id as usize)?.lock();
+
+        // the id of a terminated target may already belong to another service.
+        if conn.severed {
+            return None;
+        }
+
+        Some(ServiceRef {
+            id: conn.target_service,
+            table: self.table,
+        })
+    }
+
+    /// The spec of the service on the other end of the connection, which stays valid after that service was terminated.
+    pub fn get_spec_from_connection(&self, connection_id: Id) -> Option<ServiceSpecRef> {
+        let services = self.table.services.lock();
+        let service = &services[self.id as usize];
+
+        let target_spec = service
             .connections
-            .get(connection_id as usize)
-            .map(|conn| ServiceRef {
-                id: conn.lock().target_service,
-                table: self.table,
-            });
+            .get(connection_id as usize)?
+            .lock()
+            .target_spec;
+        Some(ServiceSpecRef::new(self.table, target_spec))
     }
 
+    /// Read from the connection into `buffer` from `start`, only the part that is filled is checked.
     pub fn read(
         &self,
         connection: Id,
-        buffer: &mut [u8],
+        buffer: UserBuffer,
         start: usize,
     ) -> Result<usize, ReadError> {
-        let total_len = buffer.len();
-        let buffer = &mut buffer[start..total_len];
+        loop {
+            match self.try_read(connection, buffer, start) {
+                Err(ReadError::InvalidBuffer(UserBufferError::ReadOnly(addr))) => self
+                    .copy_user_page_on_write(addr)
+                    .map_err(ReadError::InvalidBuffer)?,
+                result => return result,
+            }
+        }
+    }
 
-        let services = self.table.services.lock();
-        let service = &services[self.id as usize];
+    /// A single try of [`Self::read`], which takes nothing out of the pipe when the buffer is not writable yet.
+    fn try_read(
+        &self,
+        connection: Id,
+        buffer: UserBuffer,
+        start: usize,
+    ) -> Result<usize, ReadError> {
+        let specs = self.table.specs.read();
+        let mut services = self.table.services.lock();
+        let service = &mut services[self.id as usize];
+        let privilege = specs[service.spec_id as usize].privilege;
 
         if connection as usize >= service.connections.len() {
             return Err(ReadError::InvalidConnection);
@@ -169,39 +433,58 @@ impl<'a> ServiceRef<'a> {
             };
         }
 
-        let read = min(buffer.len(), pipe.buffer.len());
-
-        for i in 0..read {
-            buffer[i] = pipe.buffer.pop_front().unwrap();
+        let read = min(buffer.len() - start, pipe.buffer.len());
+        let destination = buffer
+            .checked_slice(
+                &mut service.memory_map,
+                privilege,
+                start,
+                read,
+                UserAccess::Write,
+            )
+            .map_err(ReadError::InvalidBuffer)?;
+
+        for byte in destination.iter_mut() {
+            *byte = pipe.buffer.pop_front().unwrap();
         }
 
         if !pipe.buffer.is_empty() {
-            pipe.read_block = pipe.read_block.take().and_then(|b| b.unblock_one());
+            pipe.read_waiters.wake_one();
         }
 
-        pipe.write_block = pipe.write_block.take().and_then(|b| b.unblock_one());
+        pipe.write_waiters.wake_one();
 
         // this is the last read call, we should clean up after ourselves.
         if pipe.buffer.is_empty() && pipe.closed {
-            pipe.read_block = None;
-            conn.request_close_block = None;
+            pipe.read_waiters.wake_all();
+            conn.request_close_waiters.wake_all();
             conn.current_request = None;
         }
 
+        let sides = conn.services();
+        drop(conn);
+        Self::notify_pollers(&mut services, sides);
+
         Ok(read)
     }
 
-    pub fn write(&self, connection: Id, buffer: &[u8], start: usize) -> Result<usize, WriteError> {
-        let buffer = &buffer[start..buffer.len()];
-
-        let services = self.table.services.lock();
-        let service = &services[self.id as usize];
+    /// Write `buffer` from `start` to the connection, only the part that fits in the pipe is checked.
+    pub fn write(
+        &self,
+        connection: Id,
+        buffer: UserBuffer,
+        start: usize,
+    ) -> Result<usize, WriteError> {
+        let endpoints = self.table.endpoints.read();
+        let specs = self.table.specs.read();
+        let mut services = self.table.services.lock();
+        let service = &mut services[self.id as usize];
+        let privilege = specs[service.spec_id as usize].privilege;
 
         if connection as usize >= service.connections.len() {
             return Err(WriteError::InvalidConnection);
         }
 
-        let endpoints = self.table.endpoints.lock();
         let mut conn = service.connections[connection as usize].lock();
 
         let endpoint = conn
@@ -217,8 +500,20 @@ impl<'a> ServiceRef<'a> {
             return Err(WriteError::RequestClosed);
         }
 
-        let written = min(buffer.len(), pipe.buffer.capacity() - pipe.buffer.len());
-        let write_iter = buffer[0..written].iter();
+        let written = min(
+            buffer.len() - start,
+            pipe.buffer.capacity() - pipe.buffer.len(),
+        );
+        let write_iter = buffer
+            .checked_slice(
+                &mut service.memory_map,
+                privilege,
+                start,
+                written,
+                UserAccess::Read,
+            )
+            .map_err(WriteError::InvalidBuffer)?
+            .iter();
 
         let next_sizes = |index: usize| -> Result<Option<usize>, WriteError> {
             let current_param = params.get(index).ok_or(WriteError::ParameterOverflow)?;
@@ -249,16 +544,20 @@ impl<'a> ServiceRef<'a> {
         }
 
         if pipe.buffer.len() < pipe.buffer.capacity() {
-            pipe.write_block = pipe.write_block.take().and_then(|b| b.unblock_one());
+            pipe.write_waiters.wake_one();
         }
 
-        pipe.read_block = pipe.read_block.take().and_then(|b| b.unblock_one());
+        pipe.read_waiters.wake_one();
+
+        let sides = conn.services();
+        drop(conn);
+        Self::notify_pollers(&mut services, sides);
 
         Ok(written)
     }
 
     pub fn close_write(&self, connection: Id) -> Result<(), WriteError> {
-        let services = self.table.services.lock();
+        let mut services = self.table.services.lock();
         let service = &services[self.id as usize];
 
         if connection as usize >= service.connections.len() {
@@ -273,68 +572,107 @@ impl<'a> ServiceRef<'a> {
         }
 
         pipe.closed = true;
-        pipe.read_block = None;
-        conn.request_close_block = None;
+        pipe.read_waiters.wake_all();
+        conn.request_close_waiters.wake_all();
         conn.current_request = None;
 
+        let sides = conn.services();
+        drop(conn);
+        Self::notify_pollers(&mut services, sides);
+
         Ok(())
     }
 
-    fn add_current_to_block(blocker: &mut Option<ThreadBlocker>) {
-        match blocker {
-            None => {
-                *blocker = Some(SCHEDULER.block_current());
-            }
-            Some(block) => {
-                block.block_current();
+    /// Wake all threads polling in the given services, so they can re-evaluate their interests.
+    fn notify_pollers(services: &mut Services, service_ids: [Id; 2]) {
+        // a side that was reaped has no pollers left.
+        for id in service_ids {
+            if let Some(service) = services.get_mut(id) {
+                service.poll_waiters.wake_all();
             }
         }
     }
 
+    /// Block the current thread on `queue`, it goes to sleep once it yields.
+    fn wait_on(queue: &mut WaitQueue, deadline: Option<Tick>) -> Waiter {
+        let waiter = SCHEDULER.block_current(deadline);
+        queue.push(waiter);
+        waiter
+    }
+
     fn block_until_pipe_event(
         &self,
         connection: Id,
-        pipe_selector: impl FnOnce(&mut Connection) -> &mut Pipe,
-        block_selector: impl FnOnce(&mut Pipe) -> &mut Option<ThreadBlocker>,
+        deadline: Option<Tick>,
+        pipe_selector: impl Fn(&mut Connection) -> &mut Pipe,
+        queue_selector: impl Fn(&mut Pipe) -> &mut WaitQueue,
     ) {
-        {
-            let services = self.table.services.lock();
-            let service = &services[self.id as usize];
+        let (waiter, donation) = self.with_connection(connection, |conn| {
+            let donation = self.donate_to_handler(conn);
+
+            let waiter = Self::wait_on(queue_selector(pipe_selector(conn)), deadline);
+            (waiter, donation)
+        });
+
+        SCHEDULER.yield_current();
 
-            let mut conn = service.connections[connection as usize].lock();
-            let pipe = pipe_selector(conn.deref_mut());
+        self.with_connection(connection, |conn| {
+            queue_selector(pipe_selector(conn)).remove(waiter);
+        });
 
-            let blocker = block_selector(pipe);
-            Self::add_current_to_block(blocker);
+        if let Some((handler, priority)) = donation {
+            SCHEDULER.revoke_priority(handler, priority);
         }
+    }
 
-        SCHEDULER.yield_current();
+    /// When the current thread is a client waiting on its request,
+    /// let the thread handling that request inherit the client's priority.
+    fn donate_to_handler(&self, connection: &Connection) -> Option<(ThreadId, Priority)> {
+        if connection.target_service == self.id {
+            return None;
+        }
+
+        let handler = connection.current_request.as_ref()?.handler?;
+        let priority = SCHEDULER.donate_priority(handler)?;
+        Some((handler, priority))
     }
 
-    pub fn block_until_write_available(&self, connection: Id) {
+    fn with_connection<R>(&self, connection: Id, f: impl FnOnce(&mut Connection) -> R) -> R {
+        let services = self.table.services.lock();
+        let service = &services[self.id as usize];
+
+        let mut conn = service.connections[connection as usize].lock();
+        f(conn.deref_mut())
+    }
+
+    pub fn block_until_write_available(&self, connection: Id, deadline: Option<Tick>) {
         self.block_until_pipe_event(
             connection,
+            deadline,
             |c| self.get_write_pipe(c),
-            |p| &mut p.write_block,
+            |p| &mut p.write_waiters,
         )
     }
 
-    pub fn block_until_request_close(&self, connection: Id) {
-        {
-            let services = self.table.services.lock();
-            let service = &services[self.id as usize];
-
-            let mut conn = service.connections[connection as usize].lock();
-
-            let blocker = &mut conn.request_close_block;
-            Self::add_current_to_block(blocker);
-        }
+    pub fn block_until_request_close(&self, connection: Id, deadline: Option<Tick>) {
+        let waiter = self.with_connection(connection, |conn| {
+            Self::wait_on(&mut conn.request_close_waiters, deadline)
+        });
 
         SCHEDULER.yield_current();
+
+        self.with_connection(connection, |conn| {
+            conn.request_close_waiters.remove(waiter);
+        });
     }
 
-    pub fn block_until_read_available(&self, connection: Id) {
-        self.block_until_pipe_event(connection, |c| self.get_read_pipe(c), |p| &mut p.read_block)
+    pub fn block_until_read_available(&self, connection: Id, deadline: Option<Tick>) {
+        self.block_until_pipe_event(
+            connection,
+            deadline,
+            |c| self.get_read_pipe(c),
+            |p| &mut p.read_waiters,
+        )
     }
 
     fn get_write_pipe<'b>(&self, connection: &'b mut Connection) -> &'b mut Pipe {
@@ -371,6 +709,323 @@ impl<'a> ServiceRef<'a> {
         ServiceSpecRef::new(self.table, service.spec_id)
     }
 
+    /// Create a shared memory object of `pages` zeroed frames owned by the service, returns its id.
+    ///
+    /// The frames are allocated and zeroed before the table is locked, without disabling interrupts, so this is not
+    /// called in an atomic block. The shared memory lock sleeps, so it is not held meanwhile either.
+    /// The id of a revoked object is reused.
+    pub fn create_shared_memory(&self, pages: usize) -> Result<Id, SharedMemoryError> {
+        let owned = self.owned_shared_pages(&self.table.shared_memory.lock());
+
+        if pages > SHARED_MEMORY_QUOTA.saturating_sub(owned) {
+            return Err(SharedMemoryError::QuotaExceeded);
+        }
+
+        let mut frames = Vec::with_capacity(pages);
+
+        for _ in 0..pages {
+            let Some(frame) = FRAME_ALLOCATOR.allocate_frame() else {
+                Self::free_frames(frames);
+                return Err(SharedMemoryError::OutOfMemory);
+            };
+
+            self.table.root_memory_map.zero_frame(frame);
+            frames.push(frame);
+        }
+
+        self.insert_shared_memory(frames).map_err(|(err, frames)| {
+            Self::free_frames(frames);
+            err
+        })
+    }
+
+    /// Insert a shared memory object owned by the service, gives the frames back if the quota is exceeded.
+    fn insert_shared_memory(
+        &self,
+        frames: Vec<PhysicalPage>,
+    ) -> Result<Id, (SharedMemoryError, Vec<PhysicalPage>)> {
+        let mut shared_memory = self.table.shared_memory.lock();
+
+        // another thread of the service may have created shared memory in the meantime.
+        if frames.len()
+            > SHARED_MEMORY_QUOTA.saturating_sub(self.owned_shared_pages(&shared_memory))
+        {
+            return Err((SharedMemoryError::QuotaExceeded, frames));
+        }
+
+        let index = shared_memory
+            .iter()
+            .position(Option::is_none)
+            .unwrap_or(shared_memory.len());
+
+        let Ok(id) = Id::try_from(index) else {
+            return Err((SharedMemoryError::OutOfMemory, frames));
+        };
+
+        let object = Some(SharedMemory {
+            owner: self.id,
+            frames,
+            granted: Vec::new(),
+            mappings: Vec::new(),
+        });
+
+        if index == shared_memory.len() {
+            shared_memory.push(object);
+        } else {
+            shared_memory[index] = object;
+        }
+
+        Ok(id)
+    }
+
+    /// The number of pages of the shared memory objects owned by the service.
+    fn owned_shared_pages(&self, shared_memory: &[Option<SharedMemory>]) -> usize {
+        shared_memory
+            .iter()
+            .flatten()
+            .filter(|object| object.owner == self.id)
+            .map(|object| object.frames.len())
+            .sum()
+    }
+
+    fn free_frames(frames: Vec<PhysicalPage>) {
+        frames
+            .into_iter()
+            .for_each(|frame| FRAME_ALLOCATOR.free_page(frame));
+    }
+
+    /// Allow the service on the other end of `connection` to map the shared memory.
+    pub fn grant_shared_memory(&self, id: Id, connection: Id) -> Result<(), SharedMemoryError> {
+        let mut shared_memory = self.table.shared_memory.lock();
+        let services = self.table.services.lock();
+
+        let object = shared_memory
+            .get_mut(id as usize)
+            .and_then(Option::as_mut)
+            .ok_or(SharedMemoryError::NotFound)?;
+
+        if object.owner != self.id {
+            return Err(SharedMemoryError::NotOwner);
+        }
+
+        let peer = services[self.id as usize]
+            .connections
+            .get(connection as usize)
+            .ok_or(SharedMemoryError::InvalidConnection)?
+            .lock()
+            .services()
+            .into_iter()
+            .find(|service| *service != self.id)
+            .ok_or(SharedMemoryError::InvalidConnection)?;
+
+        if !object.granted.contains(&peer) {
+            object.granted.push(peer);
+        }
+
+        Ok(())
+    }
+
+    /// Map the first `pages` pages of the shared memory, at `addr` when given or at a free range otherwise.
+    pub fn map_shared_memory(
+        &self,
+        id: Id,
+        pages: usize,
+        addr: Option<VirtualAddress>,
+        writable: bool,
+    ) -> Result<VirtualAddress, SharedMemoryError> {
+        let specs = self.table.specs.read();
+        let mut shared_memory = self.table.shared_memory.lock();
+        let mut services = self.table.services.lock();
+
+        let service = &mut services[self.id as usize];
+
+        let object = shared_memory
+            .get_mut(id as usize)
+            .and_then(Option::as_mut)
+            .filter(|object| object.may_map(self.id))
+            .ok_or(SharedMemoryError::NotFound)?;
+
+        if object
+            .mappings
+            .iter()
+            .any(|(service, _)| *service == self.id)
+        {
+            return Err(SharedMemoryError::AlreadyMapped);
+        }
+
+        if pages == 0 || pages > object.frames.len() {
+            return Err(SharedMemoryError::InvalidRange);
+        }
+
+        let mut flags = ServiceTable::data_page_flags(specs[service.spec_id as usize].privilege);
+        flags.set_writable(writable);
+
+        if let Some(addr) = addr {
+            let region = Region::new(addr, pages, RegionKind::Shared, flags);
+
+            if !service.memory_map.regions().in_window(&region) {
+                return Err(SharedMemoryError::InvalidRange);
+            }
+        }
+
+        let (region, cache_flush) = service
+            .memory_map
+            .map_shared_frames(addr, &object.frames[..pages], flags)
+            .map_err(|err| match err {
+                NewMappingError::OutOfFrames => SharedMemoryError::OutOfMemory,
+                _ => SharedMemoryError::InvalidRange,
+            })?;
+
+        cache_flush.flush();
+        object.mappings.push((self.id, region.start()));
+
+        Ok(region.start())
+    }
+
+    /// Unmap the mapping of the shared memory of this service, the memory itself stays available.
+    pub fn unmap_shared_memory(&self, id: Id) -> Result<(), SharedMemoryError> {
+        let mut shared_memory = self.table.shared_memory.lock();
+        let mut services = self.table.services.lock();
+
+        let object = shared_memory
+            .get_mut(id as usize)
+            .and_then(Option::as_mut)
+            .ok_or(SharedMemoryError::NotFound)?;
+
+        let index = object
+            .mappings
+            .iter()
+            .position(|(service, _)| *service == self.id)
+            .ok_or(SharedMemoryError::NotMapped)?;
+
+        let (service, addr) = object.mappings.swap_remove(index);
+        Self::unmap_shared_mapping(&mut services, service, addr);
+
+        Ok(())
+    }
+
+    /// Unmap the shared memory from every service that mapped it and free it, only the owner can revoke it.
+    pub fn revoke_shared_memory(&self, id: Id) -> Result<(), SharedMemoryError> {
+        let mut shared_memory = self.table.shared_memory.lock();
+        let mut services = self.table.services.lock();
+
+        let entry = shared_memory
+            .get_mut(id as usize)
+            .ok_or(SharedMemoryError::NotFound)?;
+
+        match entry {
+            Some(object) if object.owner == self.id => {}
+            Some(_) => return Err(SharedMemoryError::NotOwner),
+            None => return Err(SharedMemoryError::NotFound),
+        }
+
+        if let Some(object) = entry.take() {
+            Self::destroy_shared_memory(&mut services, object);
+        }
+
+        Ok(())
+    }
+
+    /// Unmap all shared memory mapped by the service and revoke the shared memory it owns, used when the service exits.
+    pub fn release_shared_memory(&self) {
+        let mut shared_memory = self.table.shared_memory.lock();
+        let mut services = self.table.services.lock();
+
+        for entry in shared_memory.iter_mut() {
+            let Some(object) = entry else {
+                continue;
+            };
+
+            if object.owner == self.id {
+                if let Some(object) = entry.take() {
+                    Self::destroy_shared_memory(&mut services, object);
+                }
+
+                continue;
+            }
+
+            object.granted.retain(|service| *service != self.id);
+
+            if let Some(index) = object
+                .mappings
+                .iter()
+                .position(|(service, _)| *service == self.id)
+            {
+                let (service, addr) = object.mappings.swap_remove(index);
+                Self::unmap_shared_mapping(&mut services, service, addr);
+            }
+        }
+    }
+
+    /// Terminate the service because of `fault`, the kernel keeps running without it.
+    ///
+    /// The threads of the service are stopped and the requests on its connections are closed, so peers blocked on the
+    /// service wake up. The next connection to its spec starts a new instance.
+    /// This may run in fault context, so it only takes spin locks: the shared memory of the service is released and
+    /// the service is dropped later by [`ServiceTable::reap_terminated`], once no CPU uses its memory map anymore.
+    pub fn terminate(&self, fault: Fault) {
+        SCHEDULER.exit_service_threads(self.id);
+
+        {
+            let mut services = self.table.services.lock();
+            let service = &mut services[self.id as usize];
+
+            if service.fault.is_some() {
+                return;
+            }
+
+            service.fault = Some(fault);
+
+            let connections = service.connections.clone();
+
+            for connection in connections {
+                let sides = {
+                    let mut guard = connection.lock();
+                    let conn = guard.deref_mut();
+
+                    for pipe in [&mut conn.request, &mut conn.response] {
+                        pipe.closed = true;
+                        pipe.read_waiters.wake_all();
+                        pipe.write_waiters.wake_all();
+                    }
+
+                    conn.request_close_waiters.wake_all();
+                    conn.current_request = None;
+                    conn.severed = true;
+                    conn.services()
+                };
+
+                Self::notify_pollers(&mut services, sides);
+            }
+        }
+
+        self.table.reap_pending.store(true, Ordering::Release);
+    }
+
+    /// The fault the service was terminated for, if any.
+    pub fn fault(&self) -> Option<Fault> {
+        self.table.services.lock()[self.id as usize].fault
+    }
+
+    fn destroy_shared_memory(services: &mut Services, object: SharedMemory) {
+        for (service, addr) in object.mappings {
+            Self::unmap_shared_mapping(services, service, addr);
+        }
+
+        // the mappings hold their own references, which are only returned once every CPU of the services flushed
+        // the mappings, so the frames are not reused before that.
+        Self::free_frames(object.frames);
+    }
+
+    fn unmap_shared_mapping(services: &mut Services, service: Id, addr: VirtualAddress) {
+        let (_, cache_flush) = services[service as usize]
+            .memory_map
+            .unmap_region(addr)
+            .expect("shared memory mappings are owned by the service");
+
+        cache_flush.flush();
+    }
+
     pub fn create_request_to(
         &self,
         connection_id: Id,
@@ -378,12 +1033,20 @@ impl<'a> ServiceRef<'a> {
     ) -> Result<(), CreateRequestError> {
         // TODO: check if the endpoint id is valid for the connection.
 
+        let intents = self.table.intents.read();
+        let specs = self.table.specs.read();
         let mut services = self.table.services.lock();
-        let specs = self.table.specs.lock();
+
+        if services[self.id as usize].connections[connection_id as usize]
+            .lock()
+            .severed
+        {
+            return Err(CreateRequestError::TargetTerminated);
+        }
+
         let service = &mut services[self.id as usize];
         let spec = &specs[service.spec_id as usize];
 
-        let intents = self.table.intents.lock();
         let satisfying_intent = (spec.intents_start..spec.intents_end)
             .map(|id| &intents[id as usize])
             .find(|intent| intent.endpoint_id == endpoint_id);
@@ -402,19 +1065,19 @@ impl<'a> ServiceRef<'a> {
         *current_request = Some(Request {
             endpoint_id,
             accepted: false,
+            handler: None,
         });
 
         let target_service_id = conn.target_service as usize;
 
         Self::reset_pipe(&mut conn.request);
         Self::reset_pipe(&mut conn.response);
+
+        let sides = conn.services();
         drop(conn);
+        Self::notify_pollers(&mut services, sides);
 
-        let target_service = &mut services[target_service_id];
-        target_service.accept_block = target_service
-            .accept_block
-            .take()
-            .and_then(|b| b.unblock_one());
+        services[target_service_id].accept_waiters.wake_one();
 
         Ok(())
     }
@@ -423,8 +1086,8 @@ impl<'a> ServiceRef<'a> {
         pipe.closed = false;
         pipe.current_arg_written = 0;
         pipe.buffer.clear();
-        pipe.read_block = None;
-        pipe.write_block = None;
+        pipe.read_waiters.wake_all();
+        pipe.write_waiters.wake_all();
         pipe.write_arg_index = 0;
         pipe.read_arg_index = 0;
         pipe.reading_closed = false;
@@ -440,6 +1103,7 @@ impl<'a> ServiceRef<'a> {
             if let Some(req) = connection.current_request.as_mut() {
                 if !req.accepted {
                     req.accepted = true;
+                    req.handler = SCHEDULER.current_thread();
                     return Some((id as Id, req.endpoint_id));
                 }
             }
@@ -448,14 +1112,83 @@ impl<'a> ServiceRef<'a> {
         None
     }
 
-    pub fn block_until_next_request(&self) {
-        {
+    pub fn block_until_next_request(&self, deadline: Option<Tick>) {
+        let waiter = {
             let mut services = self.table.services.lock();
             let service = &mut services[self.id as usize];
-            Self::add_current_to_block(&mut service.accept_block);
+            Self::wait_on(&mut service.accept_waiters, deadline)
+        };
+
+        SCHEDULER.yield_current();
+
+        let mut services = self.table.services.lock();
+        services[self.id as usize].accept_waiters.remove(waiter);
+    }
+
+    /// Fill in the `ready` events of each entry, and return the amount of ready entries.
+    /// When `accept` is set, also report whether a new request is waiting to be accepted.
+    pub fn poll(
+        &self,
+        entries: &mut [PollEntry],
+        accept: bool,
+    ) -> Result<(usize, bool), PollError> {
+        let services = self.table.services.lock();
+        let service = &services[self.id as usize];
+
+        let mut ready_entries = 0;
+
+        for entry in entries.iter_mut() {
+            let mut conn = service
+                .connections
+                .get(entry.connection as usize)
+                .ok_or(PollError::InvalidConnection)?
+                .lock();
+
+            let mut ready = PollEvents::NONE;
+
+            let read_pipe = self.get_read_pipe(conn.deref_mut());
+            if !read_pipe.buffer.is_empty() || read_pipe.closed {
+                ready = ready | PollEvents::READABLE;
+            }
+
+            let has_request = conn.current_request.is_some();
+            let write_pipe = self.get_write_pipe(conn.deref_mut());
+            if !has_request
+                || write_pipe.closed
+                || write_pipe.buffer.len() < write_pipe.buffer.capacity()
+            {
+                ready = ready | PollEvents::WRITABLE;
+            }
+
+            entry.ready = ready & entry.interest;
+
+            if !entry.ready.is_empty() {
+                ready_entries += 1;
+            }
         }
 
+        let request_pending = accept
+            && service.connections.iter().any(|conn| {
+                let conn = conn.lock();
+
+                conn.target_service == self.id
+                    && conn.current_request.as_ref().is_some_and(|r| !r.accepted)
+            });
+
+        Ok((ready_entries, request_pending))
+    }
+
+    pub fn block_until_poll_event(&self, deadline: Option<Tick>) {
+        let waiter = {
+            let mut services = self.table.services.lock();
+            let service = &mut services[self.id as usize];
+            Self::wait_on(&mut service.poll_waiters, deadline)
+        };
+
         SCHEDULER.yield_current();
+
+        let mut services = self.table.services.lock();
+        services[self.id as usize].poll_waiters.remove(waiter);
     }
 }
 
@@ -472,3 +1205,284 @@ impl Debug for ServiceRef<'_> {
             .finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use essentials::address::PhysicalAddress;
+    use x86_64::paging::PageSize;
+
+    use crate::arch::x86_64::init::FaultKind;
+    use crate::service::service_table::tests::{new_table, register_spec, stop_services};
+
+    fn frame_of(service: &ServiceRef, addr: VirtualAddress) -> Option<PhysicalAddress> {
+        let services = service.table.services.lock();
+        services[service.id() as usize]
+            .memory_map
+            .translate_virtual_to_physical(addr)
+    }
+
+    #[test_case]
+    fn test_grant_map_and_revoke() {
+        let table = new_table();
+        let owner = table.start_service(register_spec(&table, "owner")).unwrap();
+        let connection = owner.connect_to(register_spec(&table, "peer")).unwrap();
+        let peer = owner.get_service_from_connection(connection).unwrap();
+
+        let id = owner.create_shared_memory(2).unwrap();
+
+        assert!(matches!(
+            peer.map_shared_memory(id, 1, None, false),
+            Err(SharedMemoryError::NotFound)
+        ));
+        assert!(matches!(
+            peer.grant_shared_memory(id, 0),
+            Err(SharedMemoryError::NotOwner)
+        ));
+
+        owner.grant_shared_memory(id, connection).unwrap();
+
+        let owner_addr = owner.map_shared_memory(id, 2, None, true).unwrap();
+        let peer_addr = peer.map_shared_memory(id, 1, None, false).unwrap();
+        let frame = frame_of(&owner, owner_addr).unwrap();
+
+        assert_eq!(Some(frame), frame_of(&peer, peer_addr));
+        // the object and both mappings hold a reference.
+        let frame = PhysicalPage::new(frame, PageSize::Size4Kib);
+        assert_eq!(3, FRAME_ALLOCATOR.frame_references(frame));
+
+        assert!(matches!(
+            peer.revoke_shared_memory(id),
+            Err(SharedMemoryError::NotOwner)
+        ));
+        owner.revoke_shared_memory(id).unwrap();
+
+        assert_eq!(None, frame_of(&owner, owner_addr));
+        assert_eq!(None, frame_of(&peer, peer_addr));
+        assert!(matches!(
+            peer.map_shared_memory(id, 1, None, false),
+            Err(SharedMemoryError::NotFound)
+        ));
+
+        // the id of the revoked object is reused.
+        assert_eq!(id, owner.create_shared_memory(1).unwrap());
+
+        stop_services(&[owner.id(), peer.id()]);
+    }
+
+    #[test_case]
+    fn test_release_and_quota() {
+        let table = new_table();
+        let owner = table.start_service(register_spec(&table, "owner")).unwrap();
+        let connection = owner.connect_to(register_spec(&table, "peer")).unwrap();
+        let peer = owner.get_service_from_connection(connection).unwrap();
+
+        assert!(matches!(
+            owner.create_shared_memory(SHARED_MEMORY_QUOTA + 1),
+            Err(SharedMemoryError::QuotaExceeded)
+        ));
+
+        let id = owner.create_shared_memory(1).unwrap();
+        owner.grant_shared_memory(id, connection).unwrap();
+        let owner_addr = owner.map_shared_memory(id, 1, None, true).unwrap();
+        let peer_addr = peer.map_shared_memory(id, 1, None, true).unwrap();
+
+        // a peer releasing the memory only loses its own mapping and grant.
+        peer.release_shared_memory();
+
+        assert_eq!(None, frame_of(&peer, peer_addr));
+        assert!(frame_of(&owner, owner_addr).is_some());
+        assert!(matches!(
+            peer.map_shared_memory(id, 1, None, true),
+            Err(SharedMemoryError::NotFound)
+        ));
+
+        // the owner releasing it revokes it.
+        owner.release_shared_memory();
+
+        assert_eq!(None, frame_of(&owner, owner_addr));
+        assert!(matches!(
+            owner.map_shared_memory(id, 1, None, true),
+            Err(SharedMemoryError::NotFound)
+        ));
+
+        stop_services(&[owner.id(), peer.id()]);
+    }
+
+    #[test_case]
+    fn test_large_anonymous_mappings_are_lazy() {
+        let table = new_table();
+        let service = table
+            .start_service(register_spec(&table, "mapper"))
+            .unwrap();
+        let last_page = |addr: VirtualAddress, pages: usize| addr + (pages - 1) * 4096;
+
+        let eager = service
+            .map_anonymous(None, LAZY_ANONYMOUS_PAGES - 1)
+            .unwrap();
+        assert!(frame_of(&service, eager).is_some());
+        assert!(frame_of(&service, last_page(eager, LAZY_ANONYMOUS_PAGES - 1)).is_some());
+
+        let lazy = service.map_anonymous(None, LAZY_ANONYMOUS_PAGES).unwrap();
+        assert_eq!(None, frame_of(&service, lazy));
+
+        // a page of a lazy mapping is backed on its own once it is touched.
+        let touched = last_page(lazy, LAZY_ANONYMOUS_PAGES);
+        assert!(service.populate(touched));
+        assert!(frame_of(&service, touched).is_some());
+        assert_eq!(None, frame_of(&service, lazy));
+
+        service.unmap_anonymous(eager).unwrap();
+        service.unmap_anonymous(lazy).unwrap();
+        assert_eq!(None, frame_of(&service, eager));
+        assert_eq!(None, frame_of(&service, touched));
+
+        stop_services(&[service.id()]);
+    }
+
+    #[test_case]
+    fn test_fixed_anonymous_mappings_stay_in_the_window() {
+        let table = new_table();
+        let service = table
+            .start_service(register_spec(&table, "mapper"))
+            .unwrap();
+
+        // the first l4 entry, the higher half, and a range running past the end of the lower half.
+        for (addr, pages) in [
+            (0x1000, 1),
+            (0xffff_8000_0000_0000, 1),
+            (0x7fff_ffff_f000, 2),
+        ] {
+            assert!(matches!(
+                service.map_anonymous(Some(VirtualAddress::new(addr)), pages),
+                Err(MapError::InvalidRange)
+            ));
+        }
+
+        let addr = VirtualAddress::new(0x7fff_ffff_f000);
+        assert_eq!(addr, service.map_anonymous(Some(addr), 1).unwrap());
+        assert!(frame_of(&service, addr).is_some());
+
+        stop_services(&[service.id()]);
+    }
+
+    #[test_case]
+    fn test_unmap_anonymous_leaves_other_regions() {
+        let table = new_table();
+        let service = table
+            .start_service(register_spec(&table, "mapper"))
+            .unwrap();
+
+        let id = service.create_shared_memory(1).unwrap();
+        let shared = service.map_shared_memory(id, 1, None, true).unwrap();
+        let anonymous = service.map_anonymous(None, 2).unwrap();
+
+        assert!(matches!(
+            service.unmap_anonymous(shared),
+            Err(UnmapError::RegionNotFound)
+        ));
+        assert!(frame_of(&service, shared).is_some());
+
+        // only the start of a region names it.
+        assert!(matches!(
+            service.unmap_anonymous(anonymous + 4096),
+            Err(UnmapError::RegionNotFound)
+        ));
+        assert!(frame_of(&service, anonymous).is_some());
+
+        service.unmap_anonymous(anonymous).unwrap();
+        assert_eq!(None, frame_of(&service, anonymous));
+
+        service.release_shared_memory();
+        stop_services(&[service.id()]);
+    }
+
+    const FAULT: Fault = Fault {
+        kind: FaultKind::InvalidOpcode,
+        instruction_pointer: VirtualAddress::new(0x1000),
+    };
+
+    #[test_case]
+    fn test_terminated_services_are_reaped() {
+        let table = new_table();
+        let spec = register_spec(&table, "faulty");
+
+        // more instances than the scheduler has thread slots, so the slots of their threads have to be reused too.
+        for _ in 0..12 {
+            let service = table.start_service(spec).unwrap();
+            assert_eq!(0, service.id());
+
+            service.terminate(FAULT);
+            assert!(table.services.lock().get_mut(0).is_some());
+
+            table.reap_terminated();
+            assert!(table.services.lock().get_mut(0).is_none());
+        }
+    }
+
+    #[test_case]
+    fn test_connections_to_terminated_services_are_severed() {
+        let table = new_table();
+        let client = table
+            .start_service(register_spec(&table, "client"))
+            .unwrap();
+        let target_spec = register_spec(&table, "target");
+        let connection = client.connect_to(target_spec).unwrap();
+        let target = client.get_service_from_connection(connection).unwrap();
+
+        target.terminate(FAULT);
+        table.reap_terminated();
+
+        assert!(client.get_service_from_connection(connection).is_none());
+        assert!(matches!(
+            client.create_request_to(connection, 0),
+            Err(CreateRequestError::TargetTerminated)
+        ));
+
+        // the next instance of the spec takes over the id of the reaped one.
+        let connection = client.connect_to(target_spec).unwrap();
+        let next = client.get_service_from_connection(connection).unwrap();
+        assert_eq!(target.id(), next.id());
+
+        stop_services(&[client.id(), next.id()]);
+    }
+
+    #[test_case]
+    fn test_terminated_services_are_released_by_the_reaper() {
+        let table = new_table();
+        let client = table
+            .start_service(register_spec(&table, "client"))
+            .unwrap();
+        let target_spec = register_spec(&table, "target");
+        let connection = client.connect_to(target_spec).unwrap();
+        let target = client.get_service_from_connection(connection).unwrap();
+
+        let id = target.create_shared_memory(1).unwrap();
+        target.grant_shared_memory(id, 0).unwrap();
+        let client_addr = client.map_shared_memory(id, 1, None, false).unwrap();
+
+        // terminating runs in fault context, so the shared memory is left to the reaper.
+        target.terminate(FAULT);
+        assert!(frame_of(&client, client_addr).is_some());
+
+        // a terminated instance is not connected to, even before it is reaped.
+        let connection = client.connect_to(target_spec).unwrap();
+        let next = client.get_service_from_connection(connection).unwrap();
+        assert_ne!(target.id(), next.id());
+
+        table.reap_terminated();
+
+        assert_eq!(None, frame_of(&client, client_addr));
+        assert!(matches!(
+            client.map_shared_memory(id, 1, None, false),
+            Err(SharedMemoryError::NotFound)
+        ));
+        // the reaper leaves the spec with its new instance.
+        assert_eq!(
+            Some(next.id()),
+            table.specs.read()[target_spec as usize].service
+        );
+
+        stop_services(&[client.id(), next.id()]);
+    }
+}
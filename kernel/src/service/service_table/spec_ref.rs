@@ -1,5 +1,9 @@
+use crate::memory::{NewMappingError, RegionKind, TableCacheFlush};
 use crate::service::model::Id;
-use crate::service::{EndpointRef, ServiceTable};
+use crate::service::{EndpointRef, Privilege, ServiceTable};
+use essentials::address::VirtualAddress;
+use syscall::Priority;
+use x86_64::paging::PageSize;
 
 pub struct ServiceSpecRef<'a> {
     table: &'a ServiceTable,
@@ -15,9 +19,55 @@ impl<'a> ServiceSpecRef<'a> {
         self.id
     }
 
+    pub fn privilege(&self) -> Privilege {
+        self.table.specs.read()[self.id as usize].privilege
+    }
+
+    pub fn priority(&self) -> Priority {
+        self.table.specs.read()[self.id as usize].priority
+    }
+
+    /// Set the priority for threads of the service, this only affects threads that are started afterwards.
+    pub fn set_priority(&self, priority: Priority) {
+        self.table.specs.write()[self.id as usize].priority = priority;
+    }
+
+    /// Map `data` into the image of the spec at `addr`, each instance started afterwards begins with a copy-on-write view of it.
+    ///
+    /// This is how a loader places the segments of the program of the service, e.g. those of an ELF file.
+    pub fn load_image_segment(
+        &self,
+        addr: VirtualAddress,
+        data: &[u8],
+        kind: RegionKind,
+        writable: bool,
+    ) -> Result<(), NewMappingError> {
+        let mut specs = self.table.specs.write();
+        let spec = &mut specs[self.id as usize];
+
+        let mut flags = ServiceTable::data_page_flags(spec.privilege);
+        flags.set_writable(writable);
+
+        let image = match &mut spec.image {
+            Some(image) => image,
+            None => spec
+                .image
+                .insert(self.table.root_memory_map.new_mapper(true)?),
+        };
+
+        let pages = data.len().div_ceil(PageSize::Size4Kib.as_usize());
+        let (region, cache_flush) = image.map_region(Some(addr), pages, kind, flags)?;
+
+        // the image is never active, so no CPU caches its translations.
+        cache_flush.discard();
+        image.write_bytes(region.start(), data);
+
+        Ok(())
+    }
+
     pub fn get_endpoint_by_name(&self, endpoint_name: &str) -> Option<EndpointRef> {
-        let specs = self.table.specs.lock();
-        let endpoints = self.table.endpoints.lock();
+        let endpoints = self.table.endpoints.read();
+        let specs = self.table.specs.read();
         let spec = &specs[self.id as usize];
 
         for endpoint in (spec.endpoints_start..spec.endpoints_end).map(|i| &endpoints[i as usize]) {
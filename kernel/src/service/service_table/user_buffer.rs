@@ -0,0 +1,212 @@
+use core::mem::size_of;
+use essentials::address::VirtualAddress;
+use x86_64::paging::{PageSize, PageTableEntryFlags, VirtualPage};
+
+use crate::memory::{MemoryMapper, TableCacheFlush};
+use crate::service::Privilege;
+
+#[derive(Debug, Clone, Copy)]
+pub enum UserBufferError {
+    /// The range wraps around, is not canonical or lies outside the buffer.
+    InvalidRange,
+    /// A page of the range is not mapped, or the service may not access it the way the kernel wants to.
+    NotAccessible,
+    /// A page of the range is mapped read-only while the kernel wants to write to it.
+    ///
+    /// It may be shared copy-on-write, which [`super::ServiceRef::copy_on_write`] resolves once the service table is
+    /// unlocked again.
+    ReadOnly(VirtualAddress),
+}
+
+/// How the kernel accesses a user buffer on behalf of the service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserAccess {
+    Read,
+    Write,
+}
+
+/// A buffer passed to a syscall, in the address space of the calling service.
+///
+/// The buffer is never dereferenced as a whole, every copy in or out of it checks the pages of exactly the range it
+/// touches, while the service table is locked so the pages cannot be unmapped in between.
+/// See [`super::ServiceRef::copy_from_user`] and [`super::ServiceRef::copy_to_user`].
+#[derive(Debug, Clone, Copy)]
+pub struct UserBuffer {
+    addr: VirtualAddress,
+    len: usize,
+}
+
+impl UserBuffer {
+    pub fn new(addr: VirtualAddress, len: usize) -> Result<Self, UserBufferError> {
+        let buffer = Self { addr, len };
+        buffer.range(0, len)?;
+
+        Ok(buffer)
+    }
+
+    /// A buffer of `count` values of `T`.
+    pub fn new_array<T>(addr: VirtualAddress, count: usize) -> Result<Self, UserBufferError> {
+        let len = count
+            .checked_mul(size_of::<T>())
+            .ok_or(UserBufferError::InvalidRange)?;
+
+        Self::new(addr, len)
+    }
+
+    pub fn addr(&self) -> VirtualAddress {
+        self.addr
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The bytes `[offset, offset + len)` of the buffer, once every page they span is accessible to a service with
+    /// `privilege` for `access`.
+    ///
+    /// Lazy pages are backed first, so the kernel does not fault on them while it holds the lock on `memory_map`.
+    /// Copy-on-write pages are not copied here, since the other CPUs have to drop the shared frame before the copy is
+    /// safe to use and waiting for them with the lock held could deadlock, see [`UserBufferError::ReadOnly`].
+    pub(super) fn checked_slice<'m>(
+        &self,
+        memory_map: &'m mut MemoryMapper,
+        privilege: Privilege,
+        offset: usize,
+        len: usize,
+        access: UserAccess,
+    ) -> Result<&'m mut [u8], UserBufferError> {
+        let (start, end) = self.range(offset, len)?;
+        let mut addr = start;
+
+        while addr < end {
+            let (flags, size) = resolve_page(memory_map, VirtualAddress::new(addr))
+                .ok_or(UserBufferError::NotAccessible)?;
+
+            let accessible =
+                flags.present() && (privilege == Privilege::Kernel || flags.user_accessible());
+
+            if !accessible {
+                return Err(UserBufferError::NotAccessible);
+            }
+
+            if access == UserAccess::Write && !flags.writable() {
+                return Err(UserBufferError::ReadOnly(VirtualAddress::new(addr)));
+            }
+
+            let page = VirtualPage::new(VirtualAddress::new(addr), size);
+            addr = page.addr().as_usize().saturating_add(size.as_usize());
+        }
+
+        // Safety: every page of the range is mapped and accessible, and stays mapped while `memory_map` is borrowed.
+        unsafe {
+            Ok(core::slice::from_raw_parts_mut(
+                VirtualAddress::new(start).as_mut_ptr(),
+                len,
+            ))
+        }
+    }
+
+    /// The start and end of `[offset, offset + len)` in the address space, as long as the range lies in the buffer and
+    /// in one canonical half of the address space.
+    fn range(&self, offset: usize, len: usize) -> Result<(usize, usize), UserBufferError> {
+        let in_buffer = offset
+            .checked_add(len)
+            .is_some_and(|range_end| range_end <= self.len);
+
+        if !in_buffer {
+            return Err(UserBufferError::InvalidRange);
+        }
+
+        let start = self
+            .addr
+            .as_usize()
+            .checked_add(offset)
+            .ok_or(UserBufferError::InvalidRange)?;
+        let end = start
+            .checked_add(len)
+            .ok_or(UserBufferError::InvalidRange)?;
+
+        if len > 0
+            && !(is_canonical(start) && is_canonical(end - 1) && start >> 47 == (end - 1) >> 47)
+        {
+            return Err(UserBufferError::InvalidRange);
+        }
+
+        Ok((start, end))
+    }
+}
+
+fn is_canonical(addr: usize) -> bool {
+    ((addr << 16) as isize >> 16) as usize == addr
+}
+
+/// The flags and size of the page containing `addr`, after backing it when it is lazy.
+fn resolve_page(
+    memory_map: &mut MemoryMapper,
+    addr: VirtualAddress,
+) -> Option<(PageTableEntryFlags, PageSize)> {
+    match memory_map.effective_flags(addr) {
+        Some(page) => Some(page),
+        None => {
+            memory_map.populate(addr).ok()?.flush();
+            memory_map.effective_flags(addr)
+        }
+    }
+}
+
+/// The bytes of `values`, to copy a user buffer into them.
+///
+/// # Safety
+///
+/// Every bit pattern must be a valid `T`, since the service controls the bytes copied in.
+pub unsafe fn as_bytes_mut<T: Copy>(values: &mut [T]) -> &mut [u8] {
+    // Safety: the bytes cover exactly the values, and the caller guarantees any bytes are valid values.
+    unsafe {
+        core::slice::from_raw_parts_mut(
+            values.as_mut_ptr() as *mut u8,
+            core::mem::size_of_val(values),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_new_rejects_invalid_ranges() {
+        assert!(UserBuffer::new(VirtualAddress::new(0x1000), 0x2000).is_ok());
+        assert!(UserBuffer::new(VirtualAddress::new(0x7fff_ffff_f000), 0x1000).is_ok());
+
+        // crosses into the non-canonical hole
+        assert!(matches!(
+            UserBuffer::new(VirtualAddress::new(0x7fff_ffff_f000), 0x1001),
+            Err(UserBufferError::InvalidRange)
+        ));
+        // wraps around the end of the address space
+        assert!(matches!(
+            UserBuffer::new(VirtualAddress::new(0xffff_ffff_ffff_f000), 0x2000),
+            Err(UserBufferError::InvalidRange)
+        ));
+        assert!(matches!(
+            UserBuffer::new_array::<u64>(VirtualAddress::new(0x1000), usize::MAX / 4),
+            Err(UserBufferError::InvalidRange)
+        ));
+    }
+
+    #[test_case]
+    fn test_range_stays_in_buffer() {
+        let buffer = UserBuffer::new(VirtualAddress::new(0x1000), 0x100).unwrap();
+
+        assert_eq!(
+            Ok((0x1080, 0x1100)),
+            buffer.range(0x80, 0x80).map_err(|_| ())
+        );
+        assert!(buffer.range(0x80, 0x81).is_err());
+        assert!(buffer.range(usize::MAX, 2).is_err());
+    }
+}
@@ -36,8 +36,12 @@ pub fn test_panic_handler(info: &PanicInfo) -> ! {
 }
 
 #[cfg(test)]
-#[no_mangle]
-pub extern "C" fn _start() -> ! {
+bootloader::entry_point!(test_kernel_main);
+
+/// The entry point of the unit tests, they run with the memory of the kernel set up but without any threads.
+#[cfg(test)]
+fn test_kernel_main(boot_info: &'static bootloader::BootInfo) -> ! {
+    crate::init::init_kernel(boot_info);
     crate::test_main();
     halt_loop()
 }
@@ -7,7 +7,7 @@
 pub use expected::Expected;
 pub use panic_once::PanicOnce;
 pub use singleton::Singleton;
-pub use spin::{SpinMutex, SpinRwLock};
+pub use spin::{SpinMutex, SpinMutexGuard, SpinRwLock};
 pub use spin_once::SpinOnce;
 
 mod expected;
@@ -1,2 +1,3 @@
 pub use spin::Mutex as SpinMutex;
+pub use spin::MutexGuard as SpinMutexGuard;
 pub use spin::RwLock as SpinRwLock;
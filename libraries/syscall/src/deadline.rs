@@ -0,0 +1,44 @@
+use core::time::Duration;
+
+/// Encode an optional deadline, measured as the time since boot, into a single syscall argument.
+///
+/// Deadlines are passed with millisecond precision, where `0` is reserved to indicate the absence of a deadline.
+/// Therefore a deadline of less than a millisecond is rounded up to one millisecond,
+/// and a deadline beyond `u64::MAX` milliseconds saturates, instead of wrapping around to one that already passed.
+pub fn encode_deadline(deadline: Option<Duration>) -> u64 {
+    match deadline {
+        None => 0,
+        Some(deadline) => u64::try_from(deadline.as_millis())
+            .unwrap_or(u64::MAX)
+            .max(1),
+    }
+}
+
+pub fn decode_deadline(value: u64) -> Option<Duration> {
+    match value {
+        0 => None,
+        millis => Some(Duration::from_millis(millis)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_no_deadline_is_zero() {
+        assert_eq!(0, encode_deadline(None));
+        assert!(decode_deadline(0).is_none());
+    }
+
+    #[test_case]
+    fn test_zero_deadline_is_rounded_up() {
+        let encoded = encode_deadline(Some(Duration::ZERO));
+        assert_eq!(Some(Duration::from_millis(1)), decode_deadline(encoded));
+    }
+
+    #[test_case]
+    fn test_huge_deadline_saturates() {
+        assert_eq!(u64::MAX, encode_deadline(Some(Duration::MAX)));
+    }
+}
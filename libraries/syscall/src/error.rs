@@ -29,4 +29,13 @@ pub enum SyscallError {
 
     /// The operation tried to write or read more bytes than was allowed by the endpoint parameters.
     ParameterOverflow,
+
+    /// The operation did not complete before the passed deadline.
+    TimedOut,
+
+    /// The operation was non-blocking and could not complete without waiting.
+    WouldBlock,
+
+    /// An argument was passed that is not valid for the syscall, e.g. an unknown enum value.
+    InvalidArgument,
 }
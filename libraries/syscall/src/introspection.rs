@@ -0,0 +1,131 @@
+use crate::Priority;
+
+/// The longest name an introspection entry can hold, longer names are truncated.
+pub const INFO_NAME_LENGTH: usize = 32;
+
+/// A name copied out of the kernel, stored inline so entries can be passed as a flat buffer.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct InfoName {
+    length: u8,
+    bytes: [u8; INFO_NAME_LENGTH],
+}
+
+impl InfoName {
+    pub const EMPTY: Self = Self {
+        length: 0,
+        bytes: [0; INFO_NAME_LENGTH],
+    };
+
+    /// Copy `name`, truncated to [`INFO_NAME_LENGTH`] bytes without splitting a character.
+    pub fn new(name: &str) -> Self {
+        let mut length = name.len().min(INFO_NAME_LENGTH);
+
+        while !name.is_char_boundary(length) {
+            length -= 1;
+        }
+
+        let mut bytes = [0; INFO_NAME_LENGTH];
+        bytes[..length].copy_from_slice(&name.as_bytes()[..length]);
+
+        Self {
+            length: length as u8,
+            bytes,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        let length = (self.length as usize).min(INFO_NAME_LENGTH);
+        core::str::from_utf8(&self.bytes[..length]).unwrap_or("")
+    }
+}
+
+impl Default for InfoName {
+    fn default() -> Self {
+        Self::EMPTY
+    }
+}
+
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ThreadInfoState {
+    Running,
+    /// Ready to run, waiting for a CPU.
+    #[default]
+    Waiting,
+    Blocked,
+    Exited,
+}
+
+/// A snapshot of a single thread, as returned by the thread introspection syscall.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ThreadInfo {
+    pub id: u64,
+
+    /// Whether the thread belongs to a service, kernel threads do not.
+    pub has_service: bool,
+    pub service: u16,
+
+    pub state: ThreadInfoState,
+    pub priority: Priority,
+
+    /// The CPU whose run queue the thread is in.
+    pub cpu: u8,
+
+    /// Cycles spent running, see [`crate::ServiceStats::cycles_per_second`] to convert them.
+    pub running_cycles: u64,
+
+    pub name: InfoName,
+}
+
+impl ThreadInfo {
+    /// The id of the service the thread belongs to.
+    pub fn service(&self) -> Option<u16> {
+        self.has_service.then_some(self.service)
+    }
+}
+
+/// A snapshot of a single running service, as returned by the service introspection syscall.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ServiceInfo {
+    pub id: u16,
+    pub spec_id: u16,
+
+    /// The number of connections the service opened to other services.
+    pub connections: u32,
+
+    /// Bytes of memory mapped in the service's own address space, the shared kernel mappings are not included.
+    pub memory: u64,
+
+    /// The name of the spec the service was started from.
+    pub name: InfoName,
+}
+
+/// The result of reading one page of introspection entries.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct InfoPage {
+    /// The number of entries written to the start of the buffer.
+    pub entries: usize,
+
+    /// Whether there are entries beyond the page, to be read starting at the index after the last entry.
+    pub more: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_info_name_truncates_on_char_boundary() {
+        let name = InfoName::new("a very long service name that is truncated");
+        assert_eq!("a very long service name that is", name.as_str());
+
+        // 'é' takes two bytes, the second one would not fit.
+        let name = InfoName::new("0123456789012345678901234567890é");
+        assert_eq!("0123456789012345678901234567890", name.as_str());
+
+        assert_eq!("Main", InfoName::new("Main").as_str());
+    }
+}
@@ -1,8 +1,13 @@
 #![no_std]
 #![feature(doc_cfg)]
 
+mod deadline;
 mod error;
+mod introspection;
+mod poll;
+mod priority;
 mod result;
+mod stats;
 
 #[doc(cfg(feature = "user"))]
 #[cfg(feature = "user")]
@@ -12,5 +17,10 @@ mod user;
 #[cfg(feature = "user")]
 pub use user::*;
 
+pub use deadline::*;
 pub use error::*;
+pub use introspection::*;
+pub use poll::*;
+pub use priority::*;
 pub use result::*;
+pub use stats::*;
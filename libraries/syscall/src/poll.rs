@@ -0,0 +1,67 @@
+use core::ops::{BitAnd, BitOr};
+
+/// A set of readiness events on a connection.
+#[repr(transparent)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PollEvents {
+    value: u8,
+}
+
+impl PollEvents {
+    pub const NONE: Self = Self { value: 0 };
+
+    /// Reading from the connection will not block.
+    pub const READABLE: Self = Self { value: 1 << 0 };
+
+    /// Writing to the connection, or opening a new request on it, will not block.
+    pub const WRITABLE: Self = Self { value: 1 << 1 };
+
+    pub fn contains(&self, other: Self) -> bool {
+        (self.value & other.value) == other.value
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.value == 0
+    }
+}
+
+impl BitOr for PollEvents {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self {
+            value: self.value | rhs.value,
+        }
+    }
+}
+
+impl BitAnd for PollEvents {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self::Output {
+        Self {
+            value: self.value & rhs.value,
+        }
+    }
+}
+
+/// A single interest passed to the poll syscall.
+///
+/// The kernel only reads `connection` and `interest`, and overwrites `ready` with the subset of `interest` that is ready.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct PollEntry {
+    pub connection: u16,
+    pub interest: PollEvents,
+    pub ready: PollEvents,
+}
+
+impl PollEntry {
+    pub const fn new(connection: u16, interest: PollEvents) -> Self {
+        Self {
+            connection,
+            interest,
+            ready: PollEvents::NONE,
+        }
+    }
+}
@@ -0,0 +1,54 @@
+/// The scheduling priority of a thread.
+///
+/// Runnable threads with a higher priority always run before those with a lower one.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Bulk work that should only use otherwise idle time.
+    Low = 0,
+
+    #[default]
+    Normal,
+
+    /// Latency sensitive work, e.g. handling user input.
+    High,
+}
+
+impl Priority {
+    /// The number of distinct priority levels.
+    pub const LEVELS: usize = 3;
+
+    /// All priorities, from the highest to the lowest.
+    pub const DESCENDING: [Priority; Self::LEVELS] =
+        [Priority::High, Priority::Normal, Priority::Low];
+
+    pub const fn level(&self) -> usize {
+        *self as usize
+    }
+}
+
+impl TryFrom<u64> for Priority {
+    type Error = ();
+
+    fn try_from(value: u64) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Priority::Low),
+            1 => Ok(Priority::Normal),
+            2 => Ok(Priority::High),
+            _ => Err(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_priority_round_trips_through_u64() {
+        for priority in Priority::DESCENDING {
+            assert_eq!(Ok(priority), Priority::try_from(priority as u64));
+        }
+
+        assert_eq!(Err(()), Priority::try_from(Priority::LEVELS as u64));
+    }
+}
@@ -0,0 +1,69 @@
+use core::time::Duration;
+
+/// CPU time accounting of a service, summed over all of its threads.
+///
+/// Times are measured in cycles of the time stamp counter, see [`ServiceStats::cycles_per_second`].
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ServiceStats {
+    pub threads: u64,
+
+    /// Cycles spent running.
+    pub running_cycles: u64,
+
+    /// Cycles spent blocked on IPC.
+    pub blocked_cycles: u64,
+
+    /// The number of times one of the threads was switched to.
+    pub context_switches: u64,
+
+    /// The frequency of the cycle counter as measured by the kernel, `0` while it is not yet measured.
+    pub cycles_per_second: u64,
+}
+
+impl ServiceStats {
+    pub fn running_time(&self) -> Option<Duration> {
+        self.cycles_to_duration(self.running_cycles)
+    }
+
+    pub fn blocked_time(&self) -> Option<Duration> {
+        self.cycles_to_duration(self.blocked_cycles)
+    }
+
+    fn cycles_to_duration(&self, cycles: u64) -> Option<Duration> {
+        if self.cycles_per_second == 0 {
+            return None;
+        }
+
+        let nanos = cycles as u128 * 1_000_000_000 / self.cycles_per_second as u128;
+        Some(Duration::from_nanos(nanos as u64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_cycles_to_duration() {
+        let stats = ServiceStats {
+            running_cycles: 3_000_000_000,
+            blocked_cycles: 500_000,
+            cycles_per_second: 2_000_000_000,
+            ..ServiceStats::default()
+        };
+
+        assert_eq!(Some(Duration::from_millis(1500)), stats.running_time());
+        assert_eq!(Some(Duration::from_micros(250)), stats.blocked_time());
+    }
+
+    #[test_case]
+    fn test_uncalibrated_has_no_duration() {
+        let stats = ServiceStats {
+            running_cycles: 100,
+            ..ServiceStats::default()
+        };
+
+        assert_eq!(None, stats.running_time());
+    }
+}
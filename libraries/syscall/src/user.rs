@@ -3,16 +3,28 @@
 use core::arch::asm;
 use core::fmt::Debug;
 use core::mem::size_of;
+use core::time::Duration;
 
-use crate::{decode_syscall_result, SyscallError, SyscallResult};
+use crate::{
+    decode_syscall_result, encode_deadline, InfoPage, PollEntry, Priority, ServiceInfo,
+    ServiceStats, SyscallError, SyscallResult, ThreadInfo,
+};
 
-type KernelSyscall = extern "C" fn(syscall: u64, arg0: u64, arg1: u64, arg2: u64, arg3: u64) -> u64;
+type KernelSyscall =
+    extern "C" fn(syscall: u64, arg0: u64, arg1: u64, arg2: u64, arg3: u64, arg4: u64) -> u64;
 
 /// # Safety
 ///
 /// This function is unsafe because arguments can be interpreted as pointers.
 /// The caller must ensure that the rust borrow checker rule's are respected on order to guarantee safety.
-pub unsafe fn syscall(syscall: u64, arg0: u64, arg1: u64, arg2: u64, arg3: u64) -> SyscallResult {
+pub unsafe fn syscall(
+    syscall: u64,
+    arg0: u64,
+    arg1: u64,
+    arg2: u64,
+    arg3: u64,
+    arg4: u64,
+) -> SyscallResult {
     let segment: u16;
     asm!("mov {0:x}, cs", out(reg) segment, options(nomem, nostack, preserves_flags));
 
@@ -33,12 +45,13 @@ pub unsafe fn syscall(syscall: u64, arg0: u64, arg1: u64, arg2: u64, arg3: u64)
             in("rsi") arg1,
             in("rdx") arg2,
             in("r10") arg3,
+            in("r8") arg4,
         );
 
         asm!("", out("rax") raw_result, options(nomem, nostack, preserves_flags));
     } else {
         let kernel_syscall_location = 0x3fffffff000 as *const KernelSyscall;
-        raw_result = (*kernel_syscall_location)(syscall, arg0, arg1, arg2, arg3);
+        raw_result = (*kernel_syscall_location)(syscall, arg0, arg1, arg2, arg3, arg4);
     }
 
     decode_syscall_result(raw_result)
@@ -50,7 +63,7 @@ pub fn thread_exit() -> ! {
 
 pub fn hello() {
     unsafe {
-        let _ = syscall(0, 0, 0, 0, 0);
+        let _ = syscall(0, 0, 0, 0, 0, 0);
     }
 }
 
@@ -59,6 +72,7 @@ pub type ConnectionHandle = Handle;
 
 pub type EndpointId = Handle;
 pub type SpecId = Handle;
+pub type ServiceId = Handle;
 
 #[derive(Copy, Clone, Debug)]
 pub enum ConnectError {
@@ -71,7 +85,16 @@ fn unexpected_error(err: SyscallError) -> ! {
 }
 
 pub fn connect(spec_name: &str) -> Result<ConnectionHandle, ConnectError> {
-    let result = unsafe { syscall(1, spec_name.len() as u64, spec_name.as_ptr() as u64, 0, 0) };
+    let result = unsafe {
+        syscall(
+            1,
+            spec_name.len() as u64,
+            spec_name.as_ptr() as u64,
+            0,
+            0,
+            0,
+        )
+    };
 
     match result {
         Ok(id) => Ok(id as ConnectionHandle),
@@ -87,19 +110,31 @@ pub fn connect(spec_name: &str) -> Result<ConnectionHandle, ConnectError> {
 pub enum RequestError {
     OperationNotPermitted,
     ResourceNotFound,
+    TimedOut,
+    WouldBlock,
 }
 
+/// Open a new request on the connection, waiting until the connection is no longer busy.
+///
+/// When a `deadline` (time since boot, see [`uptime`]) is given, the call gives up with [`RequestError::TimedOut`] once it has passed.
+/// When `nonblocking` is set, the call fails with [`RequestError::WouldBlock`] instead of waiting.
 pub unsafe fn request(
     connection: ConnectionHandle,
     endpoint_name: &str,
+    nonblocking: bool,
+    deadline: Option<Duration>,
 ) -> Result<(), RequestError> {
+    let mut flags = 0;
+    flags |= (nonblocking as u64) << 0;
+
     let result = unsafe {
         syscall(
             2,
             connection as u64,
             endpoint_name.len() as u64,
             endpoint_name.as_ptr() as u64,
-            0,
+            flags,
+            encode_deadline(deadline),
         )
     };
 
@@ -108,6 +143,8 @@ pub unsafe fn request(
         Err(err) => match err {
             SyscallError::ResourceNotFound => Err(RequestError::ResourceNotFound),
             SyscallError::OperationNotPermitted => Err(RequestError::OperationNotPermitted),
+            SyscallError::TimedOut => Err(RequestError::TimedOut),
+            SyscallError::WouldBlock => Err(RequestError::WouldBlock),
             e => unexpected_error(e),
         },
     }
@@ -118,15 +155,20 @@ pub enum WriteError {
     ResourceNotFound,
     RequestClosed,
     ParameterOverflow,
+    TimedOut,
+    WouldBlock,
 }
 
 pub unsafe fn write(
     connection: ConnectionHandle,
     buffer: &[u8],
     end: bool,
+    nonblocking: bool,
+    deadline: Option<Duration>,
 ) -> Result<usize, WriteError> {
     let mut flags = 0;
     flags |= (end as u64) << 0;
+    flags |= (nonblocking as u64) << 1;
 
     let result = unsafe {
         syscall(
@@ -135,6 +177,7 @@ pub unsafe fn write(
             buffer.len() as u64,
             buffer.as_ptr() as u64,
             flags,
+            encode_deadline(deadline),
         )
     };
 
@@ -144,6 +187,8 @@ pub unsafe fn write(
             SyscallError::ParameterOverflow => Err(WriteError::ParameterOverflow),
             SyscallError::ResourceNotFound => Err(WriteError::ResourceNotFound),
             SyscallError::RequestClosed => Err(WriteError::RequestClosed),
+            SyscallError::TimedOut => Err(WriteError::TimedOut),
+            SyscallError::WouldBlock => Err(WriteError::WouldBlock),
             e => unexpected_error(e),
         },
     }
@@ -152,16 +197,27 @@ pub unsafe fn write(
 #[derive(Copy, Clone, Debug)]
 pub enum ReadError {
     ResourceNotFound,
+    TimedOut,
+    WouldBlock,
 }
 
-pub unsafe fn read(connection: ConnectionHandle, buffer: &mut [u8]) -> Result<usize, ReadError> {
+pub unsafe fn read(
+    connection: ConnectionHandle,
+    buffer: &mut [u8],
+    nonblocking: bool,
+    deadline: Option<Duration>,
+) -> Result<usize, ReadError> {
+    let mut flags = 0;
+    flags |= (nonblocking as u64) << 0;
+
     let result = unsafe {
         syscall(
             4,
             connection as u64,
             buffer.len() as u64,
             buffer.as_ptr() as u64,
-            0,
+            flags,
+            encode_deadline(deadline),
         )
     };
 
@@ -169,31 +225,49 @@ pub unsafe fn read(connection: ConnectionHandle, buffer: &mut [u8]) -> Result<us
         Ok(read) => Ok(read as usize),
         Err(err) => match err {
             SyscallError::ResourceNotFound => Err(ReadError::ResourceNotFound),
+            SyscallError::TimedOut => Err(ReadError::TimedOut),
+            SyscallError::WouldBlock => Err(ReadError::WouldBlock),
             e => unexpected_error(e),
         },
     }
 }
 
+#[derive(Copy, Clone, Debug)]
+pub enum AcceptError {
+    TimedOut,
+    WouldBlock,
+}
+
 /// # Safety
 ///
 /// This function is unsafe to prevent unowned access to this global "resource"
-pub unsafe fn accept() -> Option<(ConnectionHandle, EndpointId)> {
-    let result = unsafe { syscall(5, 0, 0, 0, 0) };
+pub unsafe fn accept(
+    nonblocking: bool,
+    deadline: Option<Duration>,
+) -> Result<Option<(ConnectionHandle, EndpointId)>, AcceptError> {
+    let mut flags = 0;
+    flags |= (nonblocking as u64) << 0;
+
+    let result = unsafe { syscall(5, 0, 0, 0, flags, encode_deadline(deadline)) };
 
     match result {
         Ok(data) => {
             let is_some = (data & (1 << (size_of::<Handle>() * 2 * 8))) != 0;
 
             if !is_some {
-                return None;
+                return Ok(None);
             }
 
             let connection_id = data as ConnectionHandle;
             let endpoint_id = (data >> (size_of::<Handle>() * 8)) as Handle;
 
-            Some((connection_id, endpoint_id))
+            Ok(Some((connection_id, endpoint_id)))
         }
-        Err(e) => unexpected_error(e),
+        Err(err) => match err {
+            SyscallError::TimedOut => Err(AcceptError::TimedOut),
+            SyscallError::WouldBlock => Err(AcceptError::WouldBlock),
+            e => unexpected_error(e),
+        },
     }
 }
 
@@ -209,6 +283,7 @@ pub fn stat_endpoint(_spec_id: Option<SpecId>, endpoint_name: &str) -> Option<En
             endpoint_name.as_ptr() as u64,
             0,
             0,
+            0,
         )
     };
 
@@ -223,6 +298,324 @@ pub fn stat_endpoint(_spec_id: Option<SpecId>, endpoint_name: &str) -> Option<En
     }
 }
 
+/// The time elapsed since the system booted, with the precision of the kernel's timer.
+///
+/// This is the clock that deadlines passed to other syscalls are measured against.
+pub fn uptime() -> Duration {
+    let result = unsafe { syscall(7, 0, 0, 0, 0, 0) };
+
+    match result {
+        Ok(millis) => Duration::from_millis(millis),
+        Err(e) => unexpected_error(e),
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct PollResult {
+    /// The number of entries with a non-empty `ready` set.
+    pub ready_entries: usize,
+
+    /// Whether a new request is waiting to be accepted.
+    pub request_pending: bool,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum PollError {
+    ResourceNotFound,
+    TimedOut,
+}
+
+/// Block until any of the `entries` is ready or, when `accept` is set, a new request is waiting to be accepted.
+///
+/// On return, the `ready` field of each entry is updated.
+pub unsafe fn poll(
+    entries: &mut [PollEntry],
+    accept: bool,
+    deadline: Option<Duration>,
+) -> Result<PollResult, PollError> {
+    let mut flags = 0;
+    flags |= (accept as u64) << 0;
+
+    let result = unsafe {
+        syscall(
+            8,
+            entries.len() as u64,
+            entries.as_mut_ptr() as u64,
+            0,
+            flags,
+            encode_deadline(deadline),
+        )
+    };
+
+    match result {
+        Ok(data) => Ok(PollResult {
+            ready_entries: data as u32 as usize,
+            request_pending: (data & (1 << 32)) != 0,
+        }),
+        Err(err) => match err {
+            SyscallError::ResourceNotFound => Err(PollError::ResourceNotFound),
+            SyscallError::TimedOut => Err(PollError::TimedOut),
+            e => unexpected_error(e),
+        },
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum SetPriorityError {
+    OperationNotPermitted,
+}
+
+/// Change the priority of the calling thread.
+///
+/// Services with the user privilege cannot raise it above the priority of their spec.
+pub fn set_priority(priority: Priority) -> Result<(), SetPriorityError> {
+    let result = unsafe { syscall(9, priority as u64, 0, 0, 0, 0) };
+
+    match result {
+        Ok(_) => Ok(()),
+        Err(err) => match err {
+            SyscallError::OperationNotPermitted => Err(SetPriorityError::OperationNotPermitted),
+            e => unexpected_error(e),
+        },
+    }
+}
+
+/// Give up the rest of the time slice, so other threads can run before the timer ends it.
+pub fn yield_now() {
+    let result = unsafe { syscall(10, 0, 0, 0, 0, 0) };
+
+    if let Err(e) = result {
+        unexpected_error(e)
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum ServiceStatsError {
+    ResourceNotFound,
+    /// Only System privileged services can read the stats of other services.
+    OperationNotPermitted,
+}
+
+/// The CPU time accounting of `service`, or of the calling service when `None`.
+pub fn service_stats(service: Option<ServiceId>) -> Result<ServiceStats, ServiceStatsError> {
+    let mut stats = ServiceStats::default();
+
+    let mut flags = 0;
+    flags |= (service.is_none() as u64) << 0;
+
+    let result = unsafe {
+        syscall(
+            11,
+            service.unwrap_or(0) as u64,
+            &mut stats as *mut ServiceStats as u64,
+            0,
+            flags,
+            0,
+        )
+    };
+
+    match result {
+        Ok(_) => Ok(stats),
+        Err(err) => match err {
+            SyscallError::ResourceNotFound => Err(ServiceStatsError::ResourceNotFound),
+            SyscallError::OperationNotPermitted => Err(ServiceStatsError::OperationNotPermitted),
+            e => unexpected_error(e),
+        },
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum IntrospectError {
+    /// Only System privileged services can list the threads and services.
+    OperationNotPermitted,
+}
+
+/// Fill `entries` with the threads starting at index `start`.
+pub fn list_threads(start: usize, entries: &mut [ThreadInfo]) -> Result<InfoPage, IntrospectError> {
+    unsafe { introspect(false, start, entries.as_mut_ptr() as u64, entries.len()) }
+}
+
+/// Fill `entries` with the running services starting at index `start`.
+pub fn list_services(
+    start: usize,
+    entries: &mut [ServiceInfo],
+) -> Result<InfoPage, IntrospectError> {
+    unsafe { introspect(true, start, entries.as_mut_ptr() as u64, entries.len()) }
+}
+
+unsafe fn introspect(
+    services: bool,
+    start: usize,
+    entries: u64,
+    capacity: usize,
+) -> Result<InfoPage, IntrospectError> {
+    let mut flags = 0;
+    flags |= (services as u64) << 0;
+
+    let result = unsafe { syscall(12, capacity as u64, entries, start as u64, flags, 0) };
+
+    match result {
+        Ok(data) => Ok(InfoPage {
+            entries: data as u32 as usize,
+            more: (data & (1 << 32)) != 0,
+        }),
+        Err(err) => match err {
+            SyscallError::OperationNotPermitted => Err(IntrospectError::OperationNotPermitted),
+            e => unexpected_error(e),
+        },
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum MapError {
+    OutOfMemory,
+    /// The page count is zero or too large, or the fixed address is unaligned, outside the lower half or already in use.
+    InvalidArgument,
+}
+
+/// Map `pages` zeroed 4KiB pages in the address space of the calling service, returns the address of the first page.
+///
+/// The pages are placed at `addr` when given, which must be page aligned, otherwise at a free range chosen by the kernel.
+pub fn map_anonymous(addr: Option<*mut u8>, pages: usize) -> Result<*mut u8, MapError> {
+    let mut flags = 0;
+    flags |= (addr.is_some() as u64) << 0;
+
+    let result = unsafe {
+        syscall(
+            13,
+            pages as u64,
+            addr.map_or(0, |addr| addr as u64),
+            0,
+            flags,
+            0,
+        )
+    };
+
+    match result {
+        Ok(addr) => Ok(addr as *mut u8),
+        Err(err) => match err {
+            SyscallError::OutOfMemory => Err(MapError::OutOfMemory),
+            SyscallError::InvalidArgument => Err(MapError::InvalidArgument),
+            e => unexpected_error(e),
+        },
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum UnmapError {
+    /// No region mapped with [`map_anonymous`] starts at the address.
+    ResourceNotFound,
+}
+
+/// Unmap the pages mapped by the [`map_anonymous`] call that returned `addr`.
+///
+/// # Safety
+///
+/// The memory must not be referenced anymore.
+pub unsafe fn unmap_anonymous(addr: *mut u8) -> Result<(), UnmapError> {
+    let result = unsafe { syscall(14, addr as u64, 0, 0, 0, 0) };
+
+    match result {
+        Ok(_) => Ok(()),
+        Err(err) => match err {
+            SyscallError::ResourceNotFound => Err(UnmapError::ResourceNotFound),
+            e => unexpected_error(e),
+        },
+    }
+}
+
+pub type SharedMemoryId = Handle;
+
+#[derive(Copy, Clone, Debug)]
+pub enum SharedMemoryError {
+    /// The shared memory or connection does not exist, or the memory is not mapped by the calling service.
+    ResourceNotFound,
+    /// Only the owner of the shared memory can grant or revoke it.
+    OperationNotPermitted,
+    OutOfMemory,
+    /// The page count is zero or too large, or the fixed address is unaligned or already in use.
+    InvalidArgument,
+}
+
+impl SharedMemoryError {
+    fn from_syscall(err: SyscallError) -> Self {
+        match err {
+            SyscallError::ResourceNotFound => Self::ResourceNotFound,
+            SyscallError::OperationNotPermitted => Self::OperationNotPermitted,
+            SyscallError::OutOfMemory => Self::OutOfMemory,
+            SyscallError::InvalidArgument => Self::InvalidArgument,
+            e => unexpected_error(e),
+        }
+    }
+}
+
+/// Create `pages` zeroed 4KiB pages of memory that can be shared with other services.
+///
+/// The calling service owns the memory, it is not mapped until [`map_shared_memory`] is called.
+pub fn create_shared_memory(pages: usize) -> Result<SharedMemoryId, SharedMemoryError> {
+    let result = unsafe { syscall(15, pages as u64, 0, 0, 0, 0) };
+
+    result
+        .map(|id| id as SharedMemoryId)
+        .map_err(SharedMemoryError::from_syscall)
+}
+
+/// Allow the service on the other end of `connection` to map the shared memory `id`.
+pub fn grant_shared_memory(
+    id: SharedMemoryId,
+    connection: ConnectionHandle,
+) -> Result<(), SharedMemoryError> {
+    let result = unsafe { syscall(16, id as u64, connection as u64, 0, 0, 0) };
+
+    result.map(|_| ()).map_err(SharedMemoryError::from_syscall)
+}
+
+/// Map the first `pages` pages of the shared memory `id`, returns the address of the first page.
+///
+/// The pages are placed at `addr` when given, which must be page aligned, otherwise at a free range chosen by the kernel.
+pub fn map_shared_memory(
+    id: SharedMemoryId,
+    pages: usize,
+    addr: Option<*mut u8>,
+    writable: bool,
+) -> Result<*mut u8, SharedMemoryError> {
+    let mut flags = 0;
+    flags |= (addr.is_some() as u64) << 0;
+    flags |= (!writable as u64) << 1;
+
+    let result = unsafe {
+        syscall(
+            17,
+            id as u64,
+            pages as u64,
+            addr.map_or(0, |addr| addr as u64),
+            flags,
+            0,
+        )
+    };
+
+    result
+        .map(|addr| addr as *mut u8)
+        .map_err(SharedMemoryError::from_syscall)
+}
+
+/// Unmap the shared memory `id` from the calling service, or from every service when `revoke` is set.
+///
+/// Revoking also frees the memory once it is unmapped, only the owner can revoke it.
+///
+/// # Safety
+///
+/// The memory must not be referenced anymore.
+pub unsafe fn unmap_shared_memory(
+    id: SharedMemoryId,
+    revoke: bool,
+) -> Result<(), SharedMemoryError> {
+    let flags = revoke as u64;
+    let result = unsafe { syscall(18, id as u64, 0, 0, flags, 0) };
+
+    result.map(|_| ()).map_err(SharedMemoryError::from_syscall)
+}
+
 #[derive(Copy, Clone, Debug)]
 pub enum FuseError {
     RequestClosed,
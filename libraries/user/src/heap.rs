@@ -0,0 +1,89 @@
+//! The global allocator of services, backed by anonymous pages from the kernel.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{null_mut, NonNull};
+use linked_list_allocator::{Heap, LockedHeap};
+use syscall::MapError;
+
+/// The heap grows upwards from here, far away from the ranges the kernel picks for other anonymous mappings.
+const HEAP_START: usize = 0x_2000_0000_0000;
+const PAGE_SIZE: usize = 4096;
+
+/// The minimal number of bytes the heap grows by, to avoid a syscall for every small allocation.
+const MIN_GROW_SIZE: usize = 16 * PAGE_SIZE;
+
+/// A linked list heap that maps more pages when it runs out of memory.
+pub struct ServiceHeap {
+    heap: LockedHeap,
+}
+
+impl ServiceHeap {
+    pub const fn new() -> Self {
+        Self {
+            heap: LockedHeap::empty(),
+        }
+    }
+
+    /// The number of bytes currently allocated.
+    pub fn used(&self) -> usize {
+        self.heap.lock().used()
+    }
+
+    /// The number of bytes mapped for the heap.
+    pub fn size(&self) -> usize {
+        self.heap.lock().size()
+    }
+
+    /// Map enough pages at the end of the heap to fit `layout`.
+    fn grow(heap: &mut Heap, layout: Layout) -> Result<(), MapError> {
+        // the new memory may not be merged with the last free block, so it has to fit the layout on its own.
+        let bytes = (layout.size() + layout.align())
+            .max(MIN_GROW_SIZE)
+            .next_multiple_of(PAGE_SIZE);
+
+        let top = match heap.size() {
+            0 => HEAP_START as *mut u8,
+            _ => heap.top(),
+        };
+
+        syscall::map_anonymous(Some(top), bytes / PAGE_SIZE)?;
+
+        unsafe {
+            match heap.size() {
+                0 => heap.init(top, bytes),
+                _ => heap.extend(bytes),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for ServiceHeap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl GlobalAlloc for ServiceHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut heap = self.heap.lock();
+
+        if let Ok(ptr) = heap.allocate_first_fit(layout) {
+            return ptr.as_ptr();
+        }
+
+        if Self::grow(&mut heap, layout).is_err() {
+            return null_mut();
+        }
+
+        heap.allocate_first_fit(layout)
+            .map_or(null_mut(), |ptr| ptr.as_ptr())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.heap
+            .lock()
+            .deallocate(NonNull::new_unchecked(ptr), layout)
+    }
+}
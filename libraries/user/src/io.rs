@@ -2,6 +2,15 @@ mod error;
 mod read;
 mod write;
 
+use core::time::Duration;
+
 pub use error::*;
 pub use read::*;
 pub use write::*;
+
+/// Turn a relative timeout into a deadline for the syscall interface.
+///
+/// A timeout too large to be represented as a deadline is treated as no timeout at all.
+pub(crate) fn deadline_after(timeout: Option<Duration>) -> Option<Duration> {
+    timeout.and_then(|timeout| syscall::uptime().checked_add(timeout))
+}
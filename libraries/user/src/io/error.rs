@@ -2,6 +2,33 @@ pub enum IoError {
     WriteError(syscall::WriteError),
     ReadError(syscall::ReadError),
     RequestError(syscall::RequestError),
+    AcceptError(syscall::AcceptError),
+    PollError(syscall::PollError),
+}
+
+impl IoError {
+    /// Whether the operation failed because its timeout elapsed.
+    pub fn is_timed_out(&self) -> bool {
+        matches!(
+            self,
+            IoError::WriteError(syscall::WriteError::TimedOut)
+                | IoError::ReadError(syscall::ReadError::TimedOut)
+                | IoError::RequestError(syscall::RequestError::TimedOut)
+                | IoError::AcceptError(syscall::AcceptError::TimedOut)
+                | IoError::PollError(syscall::PollError::TimedOut)
+        )
+    }
+
+    /// Whether the operation failed because it was non-blocking and would have had to wait.
+    pub fn is_would_block(&self) -> bool {
+        matches!(
+            self,
+            IoError::WriteError(syscall::WriteError::WouldBlock)
+                | IoError::ReadError(syscall::ReadError::WouldBlock)
+                | IoError::RequestError(syscall::RequestError::WouldBlock)
+                | IoError::AcceptError(syscall::AcceptError::WouldBlock)
+        )
+    }
 }
 
 impl From<syscall::WriteError> for IoError {
@@ -22,4 +49,16 @@ impl From<syscall::RequestError> for IoError {
     }
 }
 
+impl From<syscall::AcceptError> for IoError {
+    fn from(value: syscall::AcceptError) -> Self {
+        Self::AcceptError(value)
+    }
+}
+
+impl From<syscall::PollError> for IoError {
+    fn from(value: syscall::PollError) -> Self {
+        Self::PollError(value)
+    }
+}
+
 pub type Result<T> = core::result::Result<T, IoError>;
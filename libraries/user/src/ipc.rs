@@ -1,9 +1,11 @@
 mod connection;
 mod endpoint;
 mod listener;
+mod poll;
 mod request;
 
 pub use connection::*;
 pub use endpoint::*;
 pub use listener::*;
+pub use poll::*;
 pub use request::*;
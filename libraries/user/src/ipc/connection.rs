@@ -1,23 +1,76 @@
+use crate::io::deadline_after;
 use crate::ipc::Request;
+use core::time::Duration;
 use syscall::ConnectionHandle;
 
 pub struct Connection {
     handle: ConnectionHandle,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    nonblocking: bool,
 }
 
 impl Connection {
     pub const unsafe fn from_handle(handle: ConnectionHandle) -> Self {
-        Self { handle }
+        Self {
+            handle,
+            read_timeout: None,
+            write_timeout: None,
+            nonblocking: false,
+        }
     }
 
     pub fn handle(&self) -> ConnectionHandle {
         self.handle
     }
 
+    /// Set the timeout for reads on requests made through this connection.
+    ///
+    /// When set to `None`, reads block until data is available.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+    }
+
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.read_timeout
+    }
+
+    /// Set the timeout for opening requests and writing to requests made through this connection.
+    ///
+    /// When set to `None`, writes block until the other side has made room.
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) {
+        self.write_timeout = timeout;
+    }
+
+    pub fn write_timeout(&self) -> Option<Duration> {
+        self.write_timeout
+    }
+
+    /// When set, opening a request on a busy connection fails with a would-block error instead of waiting.
+    ///
+    /// Requests made through this connection inherit the setting.
+    pub fn set_nonblocking(&mut self, nonblocking: bool) {
+        self.nonblocking = nonblocking;
+    }
+
+    pub fn is_nonblocking(&self) -> bool {
+        self.nonblocking
+    }
+
     pub fn request<E: AsRef<str>>(&mut self, endpoint: E) -> crate::io::Result<Request<'_>> {
         unsafe {
-            syscall::request(self.handle, endpoint.as_ref())?;
-            Ok(Request::from_handle(self.handle))
+            syscall::request(
+                self.handle,
+                endpoint.as_ref(),
+                self.nonblocking,
+                deadline_after(self.write_timeout),
+            )?;
+
+            let mut request = Request::from_handle(self.handle);
+            request.set_read_timeout(self.read_timeout);
+            request.set_write_timeout(self.write_timeout);
+            request.set_nonblocking(self.nonblocking);
+            Ok(request)
         }
     }
 }
@@ -1,21 +1,48 @@
+use crate::io::deadline_after;
 use crate::ipc::request::Request;
 use crate::ipc::Endpoint;
+use core::time::Duration;
 
 pub type EndpointHandler = fn(Request<'_>);
 
 #[repr(C)]
 pub struct Listener {
-    _phantom: u8,
+    nonblocking: bool,
 }
 
 impl Listener {
     pub unsafe fn new() -> Self {
-        Self { _phantom: 0 }
+        Self { nonblocking: false }
+    }
+
+    /// When set, [`Listener::accept`] returns `None` instead of waiting when no request is pending.
+    pub fn set_nonblocking(&mut self, nonblocking: bool) {
+        self.nonblocking = nonblocking;
+    }
+
+    pub fn is_nonblocking(&self) -> bool {
+        self.nonblocking
     }
 
     pub fn accept(&mut self) -> Option<(Request<'_>, Endpoint)> {
-        unsafe {
-            syscall::accept().map(|(c, e)| (Request::from_handle(c), Endpoint::from_handle(e)))
-        }
+        // without a deadline, accepting can only fail when it would block.
+        self.accept_until(None).ok().flatten()
+    }
+
+    /// Like [`Listener::accept`], but gives up when no request came in within `timeout`.
+    pub fn accept_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> crate::io::Result<Option<(Request<'_>, Endpoint)>> {
+        self.accept_until(deadline_after(Some(timeout)))
+    }
+
+    fn accept_until(
+        &mut self,
+        deadline: Option<Duration>,
+    ) -> crate::io::Result<Option<(Request<'_>, Endpoint)>> {
+        let accepted = unsafe { syscall::accept(self.nonblocking, deadline)? };
+
+        Ok(accepted.map(|(c, e)| unsafe { (Request::from_handle(c), Endpoint::from_handle(e)) }))
     }
 }
@@ -0,0 +1,15 @@
+use crate::io::deadline_after;
+use core::time::Duration;
+pub use syscall::{PollEntry, PollEvents, PollResult};
+
+/// Wait until at least one of the entries is ready, or, when `accept` is set, a new request can be accepted.
+///
+/// The `ready` field of every entry is overwritten with the events that are ready.
+/// A `timeout` of `None` waits indefinitely.
+pub fn poll(
+    entries: &mut [PollEntry],
+    accept: bool,
+    timeout: Option<Duration>,
+) -> crate::io::Result<PollResult> {
+    unsafe { Ok(syscall::poll(entries, accept, deadline_after(timeout))?) }
+}
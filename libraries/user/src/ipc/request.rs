@@ -1,9 +1,13 @@
-use crate::io::{Read, Write};
+use crate::io::{deadline_after, Read, Write};
 use core::marker::PhantomData;
+use core::time::Duration;
 use syscall::ConnectionHandle;
 
 pub struct Request<'a> {
     handle: ConnectionHandle,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    nonblocking: bool,
     _phantom: PhantomData<&'a ()>,
 }
 
@@ -11,27 +15,72 @@ impl<'a> Request<'a> {
     pub const unsafe fn from_handle(handle: ConnectionHandle) -> Self {
         Self {
             handle,
+            read_timeout: None,
+            write_timeout: None,
+            nonblocking: false,
             _phantom: PhantomData,
         }
     }
+
+    pub fn handle(&self) -> ConnectionHandle {
+        self.handle
+    }
+
+    /// Set the timeout for each [`Read::read`] call, `None` blocks until data is available.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+    }
+
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.read_timeout
+    }
+
+    /// Set the timeout for each [`Write::write`] call, `None` blocks until the other side has made room.
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) {
+        self.write_timeout = timeout;
+    }
+
+    pub fn write_timeout(&self) -> Option<Duration> {
+        self.write_timeout
+    }
+
+    /// When set, reads and writes that would have to wait fail with a would-block error instead.
+    pub fn set_nonblocking(&mut self, nonblocking: bool) {
+        self.nonblocking = nonblocking;
+    }
+
+    pub fn is_nonblocking(&self) -> bool {
+        self.nonblocking
+    }
 }
 
 impl Read for Request<'_> {
     fn read(&mut self, buf: &mut [u8]) -> crate::io::Result<usize> {
-        unsafe { Ok(syscall::read(self.handle, buf)?) }
+        let deadline = deadline_after(self.read_timeout);
+        unsafe { Ok(syscall::read(self.handle, buf, self.nonblocking, deadline)?) }
     }
 }
 
 impl Write for Request<'_> {
     fn write(&mut self, buf: &[u8]) -> crate::io::Result<usize> {
-        unsafe { Ok(syscall::write(self.handle, buf, false)?) }
+        let deadline = deadline_after(self.write_timeout);
+        unsafe {
+            Ok(syscall::write(
+                self.handle,
+                buf,
+                false,
+                self.nonblocking,
+                deadline,
+            )?)
+        }
     }
 }
 
 impl Drop for Request<'_> {
     fn drop(&mut self) {
         unsafe {
-            syscall::write(self.handle, &[], true).expect("request should be closable");
+            syscall::write(self.handle, &[], true, false, None)
+                .expect("request should be closable");
         }
     }
 }
@@ -2,27 +2,17 @@
 
 extern crate alloc;
 
+use crate::heap::ServiceHeap;
 #[cfg(not(test))]
 use crate::ipc::Listener;
-use core::alloc::{GlobalAlloc, Layout};
 
+pub mod heap;
 pub mod io;
 pub mod ipc;
-
-struct NullAlloc;
-
-unsafe impl GlobalAlloc for NullAlloc {
-    unsafe fn alloc(&self, _layout: Layout) -> *mut u8 {
-        todo!()
-    }
-
-    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
-        todo!()
-    }
-}
+pub mod system;
 
 #[global_allocator]
-static GLOBAL_ALLOC: NullAlloc = NullAlloc;
+static GLOBAL_ALLOC: ServiceHeap = ServiceHeap::new();
 
 #[cfg(not(test))]
 extern "C" {
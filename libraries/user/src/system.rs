@@ -0,0 +1,87 @@
+//! Listing the threads and services of the whole system, which requires the System privilege.
+
+use syscall::InfoPage;
+pub use syscall::{InfoName, IntrospectError, ServiceInfo, ThreadInfo, ThreadInfoState};
+
+/// The number of entries read from the kernel at once.
+const PAGE_SIZE: usize = 16;
+
+type ListFn<T> = fn(usize, &mut [T]) -> Result<InfoPage, IntrospectError>;
+
+/// Iterate over all threads, including the threads of the kernel.
+///
+/// The threads are read a page at a time, so the result is not a snapshot of a single moment.
+pub fn threads() -> Entries<ThreadInfo> {
+    Entries::new(syscall::list_threads)
+}
+
+/// Iterate over all running services.
+///
+/// The services are read a page at a time, so the result is not a snapshot of a single moment.
+pub fn services() -> Entries<ServiceInfo> {
+    Entries::new(syscall::list_services)
+}
+
+/// An iterator that reads introspection entries from the kernel as it goes.
+///
+/// After an error, the iterator ends.
+pub struct Entries<T> {
+    list: ListFn<T>,
+    page: [T; PAGE_SIZE],
+    page_len: usize,
+    position: usize,
+    next_start: usize,
+    done: bool,
+}
+
+impl<T: Copy + Default> Entries<T> {
+    fn new(list: ListFn<T>) -> Self {
+        Self {
+            list,
+            page: [T::default(); PAGE_SIZE],
+            page_len: 0,
+            position: 0,
+            next_start: 0,
+            done: false,
+        }
+    }
+
+    fn read_page(&mut self) -> Result<(), IntrospectError> {
+        self.position = 0;
+        self.page_len = 0;
+
+        let page = (self.list)(self.next_start, &mut self.page)?;
+
+        self.page_len = page.entries;
+        self.next_start += page.entries;
+        self.done = !page.more || page.entries == 0;
+
+        Ok(())
+    }
+}
+
+impl<T: Copy + Default> Iterator for Entries<T> {
+    type Item = Result<T, IntrospectError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.position == self.page_len {
+            if self.done {
+                return None;
+            }
+
+            if let Err(e) = self.read_page() {
+                self.done = true;
+                return Some(Err(e));
+            }
+
+            if self.page_len == 0 {
+                return None;
+            }
+        }
+
+        let entry = self.page[self.position];
+        self.position += 1;
+
+        Some(Ok(entry))
+    }
+}
@@ -0,0 +1,133 @@
+//! Discovery of the ACPI tables through the physical memory mapping.
+
+use core::mem::size_of;
+use core::ptr::read_unaligned;
+use essentials::address::PhysicalAddress;
+
+pub use madt::*;
+
+mod madt;
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+
+/// The root system description pointer, the `xsdt_address` is only present from revision 2.
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+#[allow(dead_code)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    length: u32,
+    xsdt_address: u64,
+}
+
+/// The header every system description table starts with.
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+#[allow(dead_code)]
+struct SdtHeader {
+    signature: [u8; 4],
+    length: u32,
+    revision: u8,
+    checksum: u8,
+    oem_id: [u8; 6],
+    oem_table_id: [u8; 8],
+    oem_revision: u32,
+    creator_id: u32,
+    creator_revision: u32,
+}
+
+/// The ACPI tables, as provided by the firmware.
+pub struct Acpi {
+    physical_offset: u64,
+    root_table: PhysicalAddress,
+    /// Whether the root table is the XSDT with 64-bit entries, instead of the RSDT with 32-bit entries.
+    extended: bool,
+}
+
+impl Acpi {
+    /// Search the BIOS memory areas for the root system description pointer.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that all physical memory is mapped at `physical_offset`.
+    pub unsafe fn find(physical_offset: u64) -> Option<Self> {
+        let ebda_segment = read_unaligned((physical_offset + 0x40E) as *const u16) as u64;
+        let ebda = ebda_segment << 4;
+
+        let rsdp = [(ebda, ebda + 1024), (0xE0000, 0x100000)]
+            .into_iter()
+            .flat_map(|(start, end)| (start..end).step_by(16))
+            .find(|addr| Self::is_rsdp(physical_offset + addr))?;
+
+        let rsdp = read_unaligned((physical_offset + rsdp) as *const Rsdp);
+        let extended = rsdp.revision >= 2 && rsdp.xsdt_address != 0;
+
+        let root_table = if extended {
+            rsdp.xsdt_address
+        } else {
+            rsdp.rsdt_address as u64
+        };
+
+        Some(Self {
+            physical_offset,
+            root_table: PhysicalAddress::from(root_table),
+            extended,
+        })
+    }
+
+    unsafe fn is_rsdp(addr: u64) -> bool {
+        let signature = read_unaligned(addr as *const [u8; 8]);
+
+        // the checksum only covers the fields of the first revision.
+        let bytes = read_unaligned(addr as *const [u8; 20]);
+        let sum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+
+        &signature == RSDP_SIGNATURE && sum == 0
+    }
+
+    unsafe fn header(&self, table: PhysicalAddress) -> SdtHeader {
+        read_unaligned((self.physical_offset + table.as_u64()) as *const SdtHeader)
+    }
+
+    /// Find the table with the given signature, e.g. `APIC` for the MADT.
+    pub fn find_table(&self, signature: &[u8; 4]) -> Option<PhysicalAddress> {
+        let entry_size = if self.extended { 8 } else { 4 };
+
+        unsafe {
+            let header = self.header(self.root_table);
+            let entries = (header.length as usize - size_of::<SdtHeader>()) / entry_size;
+            let entries_start =
+                self.physical_offset + self.root_table.as_u64() + size_of::<SdtHeader>() as u64;
+
+            (0..entries)
+                .map(|i| {
+                    let entry = entries_start + (i * entry_size) as u64;
+
+                    if self.extended {
+                        read_unaligned(entry as *const u64)
+                    } else {
+                        read_unaligned(entry as *const u32) as u64
+                    }
+                })
+                .map(PhysicalAddress::from)
+                .find(|table| &self.header(*table).signature == signature)
+        }
+    }
+
+    /// The multiple APIC description table, which describes the interrupt controllers.
+    pub fn madt(&self) -> Option<Madt> {
+        let table = self.find_table(MADT_SIGNATURE)?;
+
+        unsafe {
+            let header = self.header(table);
+            let start = (self.physical_offset + table.as_u64()) as *const u8;
+            let bytes = core::slice::from_raw_parts(start, header.length as usize);
+
+            Some(Madt::from_bytes(&bytes[size_of::<SdtHeader>()..]))
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use core::ptr::read_unaligned;
+use essentials::address::PhysicalAddress;
+
+pub(super) const MADT_SIGNATURE: &[u8; 4] = b"APIC";
+
+/// The multiple APIC description table, listing the interrupt controllers of the system.
+pub struct Madt {
+    local_apic_address: PhysicalAddress,
+    flags: u32,
+    entries: &'static [u8],
+}
+
+/// An interrupt controller structure in the [`Madt`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MadtEntry {
+    /// A processor, with its local APIC.
+    LocalApic {
+        processor_id: u8,
+        apic_id: u8,
+        enabled: bool,
+    },
+    /// An I/O APIC, which handles the global system interrupts starting at `gsi_base`.
+    IoApic {
+        id: u8,
+        address: PhysicalAddress,
+        gsi_base: u32,
+    },
+    /// An ISA IRQ which is connected to a different global system interrupt than its own number.
+    InterruptOverride {
+        irq: u8,
+        gsi: u32,
+        active_low: bool,
+        level_triggered: bool,
+    },
+    /// An entry type the kernel does not use.
+    Other(u8),
+}
+
+/// Where an ISA IRQ arrives at the I/O APICs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IrqRoute {
+    pub gsi: u32,
+    pub active_low: bool,
+    pub level_triggered: bool,
+}
+
+impl Madt {
+    /// Parse the table from its contents after the standard header.
+    pub(super) fn from_bytes(bytes: &'static [u8]) -> Self {
+        unsafe {
+            Self {
+                local_apic_address: PhysicalAddress::from(read_unaligned(
+                    bytes.as_ptr() as *const u32
+                ) as u64),
+                flags: read_unaligned(bytes[4..].as_ptr() as *const u32),
+                entries: &bytes[8..],
+            }
+        }
+    }
+
+    pub fn local_apic_address(&self) -> PhysicalAddress {
+        self.local_apic_address
+    }
+
+    /// Whether the system also has the legacy 8259 PICs, which should be masked when the APICs are used.
+    pub fn has_8259(&self) -> bool {
+        self.flags & 1 != 0
+    }
+
+    pub fn entries(&self) -> MadtEntries {
+        MadtEntries {
+            bytes: self.entries,
+        }
+    }
+
+    /// The number of processors that can be started.
+    pub fn processor_count(&self) -> usize {
+        self.entries()
+            .filter(|entry| matches!(entry, MadtEntry::LocalApic { enabled: true, .. }))
+            .count()
+    }
+
+    /// Find the global system interrupt an ISA IRQ is connected to.
+    pub fn route_irq(&self, irq: u8) -> IrqRoute {
+        self.entries()
+            .find_map(|entry| match entry {
+                MadtEntry::InterruptOverride {
+                    irq: source,
+                    gsi,
+                    active_low,
+                    level_triggered,
+                } if source == irq => Some(IrqRoute {
+                    gsi,
+                    active_low,
+                    level_triggered,
+                }),
+                _ => None,
+            })
+            .unwrap_or(IrqRoute {
+                gsi: irq as u32,
+                active_low: false,
+                level_triggered: false,
+            })
+    }
+}
+
+/// Iterator over the entries of the [`Madt`].
+pub struct MadtEntries {
+    bytes: &'static [u8],
+}
+
+impl MadtEntries {
+    fn u32_at(&self, offset: usize) -> u32 {
+        unsafe { read_unaligned(self.bytes[offset..offset + 4].as_ptr() as *const u32) }
+    }
+
+    fn u16_at(&self, offset: usize) -> u16 {
+        unsafe { read_unaligned(self.bytes[offset..offset + 2].as_ptr() as *const u16) }
+    }
+}
+
+impl Iterator for MadtEntries {
+    type Item = MadtEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.bytes.len() < 2 {
+            return None;
+        }
+
+        let kind = self.bytes[0];
+        let length = self.bytes[1] as usize;
+
+        if length < 2 || length > self.bytes.len() {
+            return None;
+        }
+
+        let entry = match (kind, length) {
+            (0, 8..) => MadtEntry::LocalApic {
+                processor_id: self.bytes[2],
+                apic_id: self.bytes[3],
+                enabled: self.u32_at(4) & 1 != 0,
+            },
+            (1, 12..) => MadtEntry::IoApic {
+                id: self.bytes[2],
+                address: PhysicalAddress::from(self.u32_at(4) as u64),
+                gsi_base: self.u32_at(8),
+            },
+            (2, 10..) => {
+                let flags = self.u16_at(8);
+
+                // a polarity or trigger mode of 0 means it conforms to the bus, which is active high and edge triggered for ISA.
+                MadtEntry::InterruptOverride {
+                    irq: self.bytes[3],
+                    gsi: self.u32_at(4),
+                    active_low: flags & 0b11 == 0b11,
+                    level_triggered: (flags >> 2) & 0b11 == 0b11,
+                }
+            }
+            _ => MadtEntry::Other(kind),
+        };
+
+        self.bytes = &self.bytes[length..];
+        Some(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static TABLE: [u8; 38] = [
+        // local apic address and flags
+        0x00, 0x00, 0xE0, 0xFE, 0x01, 0x00, 0x00, 0x00, //
+        // processor 0 with apic 0, enabled
+        0, 8, 0, 0, 1, 0, 0, 0, //
+        // io apic 1 at 0xFEC00000 with gsi base 0
+        1, 12, 1, 0, 0x00, 0x00, 0xC0, 0xFE, 0, 0, 0, 0, //
+        // irq 0 overridden to gsi 2, active low & level triggered
+        2, 10, 0, 0, 2, 0, 0, 0, 0x0F, 0x00,
+    ];
+
+    #[test_case]
+    fn test_madt_entries() {
+        let madt = Madt::from_bytes(&TABLE);
+
+        assert_eq!(
+            madt.local_apic_address(),
+            PhysicalAddress::from(0xFEE00000u64)
+        );
+        assert!(madt.has_8259());
+        assert_eq!(madt.processor_count(), 1);
+        assert_eq!(
+            madt.entries().nth(1),
+            Some(MadtEntry::IoApic {
+                id: 1,
+                address: PhysicalAddress::from(0xFEC00000u64),
+                gsi_base: 0,
+            })
+        );
+        assert_eq!(
+            madt.route_irq(0),
+            IrqRoute {
+                gsi: 2,
+                active_low: true,
+                level_triggered: true,
+            }
+        );
+        assert_eq!(madt.route_irq(1).gsi, 1);
+    }
+}
@@ -1,5 +1,8 @@
 //! Abstraction around devices
 
+pub mod io_apic;
+pub mod local_apic;
 pub mod pic_8259;
+pub mod pit_8253;
 pub mod qemu;
 pub mod uart_16550;
@@ -0,0 +1,104 @@
+use core::ptr::{read_volatile, write_volatile};
+use essentials::address::VirtualAddress;
+
+const REGISTER_SELECT: usize = 0x00;
+const REGISTER_WINDOW: usize = 0x10;
+
+const VERSION_REGISTER: u32 = 0x01;
+const REDIRECTION_TABLE_REGISTER: u32 = 0x10;
+
+const MASKED: u64 = 1 << 16;
+
+/// Where and how a global system interrupt is delivered.
+#[derive(Copy, Clone, Debug)]
+pub struct Redirection {
+    pub vector: u8,
+    /// The local APIC id of the CPU receiving the interrupt.
+    pub destination: u8,
+    pub active_low: bool,
+    pub level_triggered: bool,
+}
+
+impl Redirection {
+    fn as_u64(&self) -> u64 {
+        let mut value = self.vector as u64 | ((self.destination as u64) << 56);
+
+        if self.active_low {
+            value |= 1 << 13;
+        }
+
+        if self.level_triggered {
+            value |= 1 << 15;
+        }
+
+        value
+    }
+}
+
+/// The I/O Advanced Programmable Interrupt Controller, which routes device interrupts to the local APICs.
+///
+/// Each I/O APIC handles a range of global system interrupts, starting at its `gsi_base`.
+pub struct IoApic {
+    base: VirtualAddress,
+    gsi_base: u32,
+}
+
+impl IoApic {
+    /// # Safety
+    ///
+    /// The caller must ensure that `base` maps the registers of the I/O APIC with caching disabled,
+    /// and that only one instance exists for each I/O APIC.
+    pub const unsafe fn new(base: VirtualAddress, gsi_base: u32) -> Self {
+        Self { base, gsi_base }
+    }
+
+    unsafe fn read(&mut self, register: u32) -> u32 {
+        write_volatile((self.base + REGISTER_SELECT).as_mut_ptr(), register);
+        read_volatile((self.base + REGISTER_WINDOW).as_ptr())
+    }
+
+    unsafe fn write(&mut self, register: u32, value: u32) {
+        write_volatile((self.base + REGISTER_SELECT).as_mut_ptr(), register);
+        write_volatile((self.base + REGISTER_WINDOW).as_mut_ptr(), value)
+    }
+
+    unsafe fn write_redirection(&mut self, input: u32, value: u64) {
+        let register = REDIRECTION_TABLE_REGISTER + input * 2;
+
+        // mask the entry while it is half written.
+        self.write(register, MASKED as u32);
+        self.write(register + 1, (value >> 32) as u32);
+        self.write(register, value as u32);
+    }
+
+    /// The number of interrupt inputs of this I/O APIC.
+    pub fn input_count(&mut self) -> u32 {
+        let version = unsafe { self.read(VERSION_REGISTER) };
+        ((version >> 16) & 0xFF) + 1
+    }
+
+    /// Whether the global system interrupt `gsi` is handled by this I/O APIC.
+    pub fn handles(&mut self, gsi: u32) -> bool {
+        self.gsi_base <= gsi && gsi < self.gsi_base + self.input_count()
+    }
+
+    /// Deliver the global system interrupt `gsi` as described by `redirection`, and unmask it.
+    pub fn redirect(&mut self, gsi: u32, redirection: Redirection) {
+        assert!(self.handles(gsi), "The I/O APIC does not handle GSI {gsi}");
+
+        unsafe { self.write_redirection(gsi - self.gsi_base, redirection.as_u64()) }
+    }
+
+    /// Stop delivering the global system interrupt `gsi`.
+    pub fn mask(&mut self, gsi: u32) {
+        assert!(self.handles(gsi), "The I/O APIC does not handle GSI {gsi}");
+
+        unsafe { self.write_redirection(gsi - self.gsi_base, MASKED) }
+    }
+
+    pub fn mask_all(&mut self) {
+        for input in 0..self.input_count() {
+            unsafe { self.write_redirection(input, MASKED) }
+        }
+    }
+}
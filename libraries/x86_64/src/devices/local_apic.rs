@@ -0,0 +1,115 @@
+use crate::msr::read_msr;
+use core::ptr::{read_volatile, write_volatile};
+use essentials::address::{PhysicalAddress, VirtualAddress};
+
+const APIC_BASE_MSR: u32 = 0x1B;
+
+#[repr(usize)]
+#[derive(Copy, Clone)]
+enum Register {
+    Id = 0x20,
+    EndOfInterrupt = 0xB0,
+    SpuriousInterruptVector = 0xF0,
+    InterruptCommandLow = 0x300,
+    InterruptCommandHigh = 0x310,
+}
+
+/// The CPUs an inter-processor interrupt is sent to.
+#[derive(Copy, Clone, Debug)]
+pub enum IpiDestination {
+    /// The CPU with the given local APIC id.
+    Apic(u8),
+    /// Every CPU except for the sending one.
+    AllExcludingSelf,
+}
+
+/// The kind of inter-processor interrupt.
+#[derive(Copy, Clone, Debug)]
+pub enum IpiKind {
+    /// A regular interrupt on the given vector.
+    Fixed(u8),
+    /// Reset the target CPUs, after which they wait for a [`IpiKind::Startup`].
+    Init,
+    /// Start the target CPUs in real mode at the physical address `page * 0x1000`.
+    Startup(u8),
+}
+
+/// The local Advanced Programmable Interrupt Controller, every CPU has its own.
+///
+/// All local APICs are mapped at the same address, and each CPU only accesses its own through it.
+pub struct LocalApic {
+    base: VirtualAddress,
+}
+
+impl LocalApic {
+    /// The physical address of the local APIC registers, as configured by the firmware.
+    pub fn physical_base() -> PhysicalAddress {
+        let value = unsafe { read_msr(APIC_BASE_MSR) };
+        PhysicalAddress::from(value & 0x000f_ffff_ffff_f000)
+    }
+
+    /// # Safety
+    ///
+    /// The caller must ensure that `base` maps [`LocalApic::physical_base`] with caching disabled.
+    pub const unsafe fn new(base: VirtualAddress) -> Self {
+        Self { base }
+    }
+
+    unsafe fn read(&self, register: Register) -> u32 {
+        read_volatile((self.base + register as usize).as_ptr())
+    }
+
+    unsafe fn write(&self, register: Register, value: u32) {
+        write_volatile((self.base + register as usize).as_mut_ptr(), value)
+    }
+
+    /// The id of the local APIC of the executing CPU.
+    pub fn id(&self) -> u8 {
+        unsafe { (self.read(Register::Id) >> 24) as u8 }
+    }
+
+    /// Enable the local APIC of the executing CPU, so it accepts interrupts.
+    ///
+    /// Spurious interrupts are delivered on `spurious_vector`, and do not need an end of interrupt.
+    pub fn enable(&self, spurious_vector: u8) {
+        unsafe {
+            let value = self.read(Register::SpuriousInterruptVector);
+            self.write(
+                Register::SpuriousInterruptVector,
+                (value & !0xFF) | (1 << 8) | spurious_vector as u32,
+            );
+        }
+    }
+
+    pub fn end_of_interrupt(&self) {
+        unsafe { self.write(Register::EndOfInterrupt, 0) }
+    }
+
+    /// Send an inter-processor interrupt, and wait until it is delivered.
+    pub fn send_ipi(&self, destination: IpiDestination, kind: IpiKind) {
+        let mut low = match kind {
+            IpiKind::Fixed(vector) => vector as u32,
+            // INIT is level triggered and asserted.
+            IpiKind::Init => (0b101 << 8) | (1 << 14) | (1 << 15),
+            IpiKind::Startup(page) => (0b110 << 8) | page as u32,
+        };
+
+        let high = match destination {
+            IpiDestination::Apic(id) => (id as u32) << 24,
+            IpiDestination::AllExcludingSelf => {
+                low |= 0b11 << 18;
+                0
+            }
+        };
+
+        unsafe {
+            self.write(Register::InterruptCommandHigh, high);
+            self.write(Register::InterruptCommandLow, low);
+
+            // the delivery status bit is cleared once the interrupt is accepted.
+            while self.read(Register::InterruptCommandLow) & (1 << 12) != 0 {
+                core::hint::spin_loop();
+            }
+        }
+    }
+}
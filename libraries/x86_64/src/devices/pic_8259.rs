@@ -95,6 +95,16 @@ impl ChainedPic8259 {
         }
     }
 
+    /// Mask every IRQ, used when the interrupts are routed through the APIC instead.
+    ///
+    /// The PICs can still raise spurious interrupts on their last IRQ line afterwards.
+    pub fn disable(&mut self) {
+        unsafe {
+            self.pics[0].write_mask(0xFF);
+            self.pics[1].write_mask(0xFF);
+        }
+    }
+
     pub fn end_of_interrupt(&mut self, interrupt: u8) {
         let pic = self
             .pics
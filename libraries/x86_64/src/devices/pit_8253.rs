@@ -0,0 +1,45 @@
+use crate::port::*;
+
+/// The frequency of the oscillator that drives the PIT.
+pub const BASE_FREQUENCY: u32 = 1_193_182;
+
+#[repr(u8)]
+enum Command {
+    /// Channel 0, access mode lobyte/hibyte, mode 2 (rate generator), binary.
+    Channel0RateGenerator = 0b0011_0100,
+}
+
+/// The legacy Programmable Interval Timer.
+///
+/// Only channel 0 is exposed, which is wired to IRQ 0 of the [`super::pic_8259::ChainedPic8259`].
+pub struct Pit8253 {
+    channel_0: Port<u8, WriteOnly>,
+    command: Port<u8, WriteOnly>,
+}
+
+impl Pit8253 {
+    /// # Safety
+    ///
+    /// The caller must ensure that only one instance exists, since programming the PIT is not atomic.
+    pub const unsafe fn new() -> Self {
+        Self {
+            channel_0: Port::write_only(0x40),
+            command: Port::write_only(0x43),
+        }
+    }
+
+    /// Program channel 0 to fire at (approximately) the given frequency in Hz.
+    ///
+    /// Returns the frequency that was actually programmed, which may deviate because of the integer divisor.
+    pub fn set_frequency(&mut self, frequency: u32) -> u32 {
+        let divisor = (BASE_FREQUENCY / frequency).clamp(1, u16::MAX as u32) as u16;
+
+        unsafe {
+            self.command.write(Command::Channel0RateGenerator as u8);
+            self.channel_0.write(divisor as u8);
+            self.channel_0.write((divisor >> 8) as u8);
+        }
+
+        BASE_FREQUENCY / divisor as u32
+    }
+}
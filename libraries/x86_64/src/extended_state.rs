@@ -0,0 +1,152 @@
+//! Saving and restoring the extended processor state: the x87 FPU, SSE and AVX registers.
+
+use core::arch::asm;
+use core::arch::x86_64::{__cpuid, __cpuid_count};
+
+/// The state components saved with `xsave`: x87, SSE and AVX.
+const SUPPORTED_COMPONENTS: u64 = 0b111;
+
+/// The size of the legacy area used by `fxsave`.
+const FXSAVE_AREA_SIZE: usize = 512;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SaveInstruction {
+    /// Saves the components enabled in XCR0, including the AVX registers.
+    Xsave,
+    /// Saves only the x87 and SSE registers.
+    Fxsave,
+}
+
+/// How the extended state is saved on this system, and how big the save area is.
+#[derive(Clone, Copy, Debug)]
+pub struct ExtendedStateFormat {
+    instruction: SaveInstruction,
+    components: u64,
+    size: usize,
+}
+
+impl ExtendedStateFormat {
+    /// The alignment the save area requires, for both instructions.
+    pub const ALIGNMENT: usize = 64;
+
+    /// Detect the format with `cpuid`, preferring `xsave` when it is available.
+    pub fn detect() -> Self {
+        let features = unsafe { __cpuid(1) };
+        let has_xsave = features.ecx & (1 << 26) != 0;
+
+        if !has_xsave {
+            return Self {
+                instruction: SaveInstruction::Fxsave,
+                components: 0,
+                size: FXSAVE_AREA_SIZE,
+            };
+        }
+
+        let xsave = unsafe { __cpuid_count(0xD, 0) };
+        let supported = (xsave.eax as u64) | ((xsave.edx as u64) << 32);
+
+        Self {
+            instruction: SaveInstruction::Xsave,
+            components: supported & SUPPORTED_COMPONENTS,
+            // the size needed for every supported component, which is at least what the enabled components need.
+            size: xsave.ecx as usize,
+        }
+    }
+
+    pub fn instruction(&self) -> SaveInstruction {
+        self.instruction
+    }
+
+    /// The size in bytes of a save area.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Allow the executing CPU to use the FPU and SIMD instructions, and enable the state components of this format.
+    ///
+    /// # Safety
+    ///
+    /// The extended state must be saved and restored on every context switch afterwards.
+    pub unsafe fn enable(&self) {
+        let mut cr0: u64;
+        asm!("mov {}, cr0", out(reg) cr0, options(nomem, nostack, preserves_flags));
+        // clear emulation and task switched, set monitor coprocessor.
+        cr0 &= !((1 << 2) | (1 << 3));
+        cr0 |= 1 << 1;
+        asm!("mov cr0, {}", in(reg) cr0, options(nostack, preserves_flags));
+
+        let mut cr4: u64;
+        asm!("mov {}, cr4", out(reg) cr4, options(nomem, nostack, preserves_flags));
+        // OSFXSR and OSXMMEXCPT enable SSE with its exceptions.
+        cr4 |= (1 << 9) | (1 << 10);
+
+        if self.instruction == SaveInstruction::Xsave {
+            cr4 |= 1 << 18;
+        }
+
+        asm!("mov cr4, {}", in(reg) cr4, options(nostack, preserves_flags));
+
+        if self.instruction == SaveInstruction::Xsave {
+            asm!(
+                "xsetbv",
+                in("ecx") 0,
+                in("eax") self.components as u32,
+                in("edx") (self.components >> 32) as u32,
+                options(nomem, nostack, preserves_flags)
+            );
+        }
+    }
+
+    /// Fill a new save area with the initial state, which masks all floating point exceptions.
+    pub fn init_area(&self, area: &mut [u8]) {
+        assert!(area.len() >= self.size);
+
+        area.fill(0);
+        // the x87 control word.
+        area[0..2].copy_from_slice(&0x037Fu16.to_le_bytes());
+        // MXCSR, the SSE control and status register.
+        area[24..28].copy_from_slice(&0x1F80u32.to_le_bytes());
+    }
+
+    /// Save the extended state of the executing CPU to `area`.
+    ///
+    /// # Safety
+    ///
+    /// `area` must be at least [`ExtendedStateFormat::size`] bytes, aligned to [`ExtendedStateFormat::ALIGNMENT`],
+    /// and the format must be enabled on the executing CPU.
+    pub unsafe fn save(&self, area: *mut u8) {
+        match self.instruction {
+            SaveInstruction::Xsave => asm!(
+                "xsave64 [{}]",
+                in(reg) area,
+                in("eax") self.components as u32,
+                in("edx") (self.components >> 32) as u32,
+                options(nostack, preserves_flags)
+            ),
+            SaveInstruction::Fxsave => {
+                asm!("fxsave64 [{}]", in(reg) area, options(nostack, preserves_flags))
+            }
+        }
+    }
+
+    /// Restore the extended state of the executing CPU from `area`.
+    ///
+    /// # Safety
+    ///
+    /// The same requirements as [`ExtendedStateFormat::save`] apply,
+    /// and `area` must be initialized with [`ExtendedStateFormat::init_area`] or by a save.
+    pub unsafe fn restore(&self, area: *const u8) {
+        match self.instruction {
+            SaveInstruction::Xsave => asm!(
+                "xrstor64 [{}]",
+                in(reg) area,
+                in("eax") self.components as u32,
+                in("edx") (self.components >> 32) as u32,
+                options(nostack, preserves_flags)
+            ),
+            SaveInstruction::Fxsave => {
+                asm!("fxrstor64 [{}]", in(reg) area, options(nostack, preserves_flags))
+            }
+        }
+    }
+}
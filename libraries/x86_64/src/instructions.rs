@@ -1,4 +1,5 @@
 use core::arch::asm;
+use core::arch::x86_64::{__cpuid, _rdtsc};
 
 /// Run the `hlt` instruction in a loop, ensuring the function will never exit.
 pub fn halt_loop() -> ! {
@@ -13,3 +14,16 @@ pub fn halt_loop() -> ! {
 pub fn halt() {
     unsafe { asm!("hlt") }
 }
+
+/// The initial local APIC id of the executing CPU, as reported by `cpuid`.
+///
+/// Unlike reading the local APIC itself, this works before the APIC is mapped.
+pub fn apic_id() -> u8 {
+    let result = unsafe { __cpuid(1) };
+    (result.ebx >> 24) as u8
+}
+
+/// The number of cycles counted by the time stamp counter since the CPU was reset.
+pub fn read_timestamp_counter() -> u64 {
+    unsafe { _rdtsc() }
+}
@@ -5,10 +5,13 @@
 #![no_std]
 #![feature(abi_x86_interrupt)]
 
+pub mod acpi;
 pub mod constants;
 pub mod devices;
+pub mod extended_state;
 pub mod instructions;
 pub mod interrupts;
+pub mod msr;
 pub mod paging;
 pub mod port;
 mod privilege;
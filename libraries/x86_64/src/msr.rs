@@ -0,0 +1,38 @@
+//! Access to model specific registers.
+
+use core::arch::asm;
+
+/// Read the 64-bit value of the model specific register `msr`.
+///
+/// # Safety
+///
+/// The caller must ensure that the register exists on this CPU, reading an unknown register causes a general protection fault.
+pub unsafe fn read_msr(msr: u32) -> u64 {
+    let low: u32;
+    let high: u32;
+
+    asm!(
+        "rdmsr",
+        in("ecx") msr,
+        out("eax") low,
+        out("edx") high,
+        options(nomem, nostack, preserves_flags)
+    );
+
+    ((high as u64) << 32) | low as u64
+}
+
+/// Write a 64-bit value to the model specific register `msr`.
+///
+/// # Safety
+///
+/// Model specific registers control low-level CPU behaviour, so the caller must check the CPU manual for the effects of the write.
+pub unsafe fn write_msr(msr: u32, value: u64) {
+    asm!(
+        "wrmsr",
+        in("ecx") msr,
+        in("eax") value as u32,
+        in("edx") (value >> 32) as u32,
+        options(nostack, preserves_flags)
+    );
+}
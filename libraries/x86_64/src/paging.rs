@@ -34,6 +34,29 @@ impl PageTableEntryFlags {
         self.set_flag(9, enabled)
     }
 
+    /// Mark that the mapped frame belongs to the mapping, and is freed together with it.
+    ///
+    /// Like [`PageTableEntryFlags::set_borrowed`], this uses a bit that is ignored by the CPU.
+    pub fn set_owns_frame(&mut self, enabled: bool) {
+        self.set_flag(10, enabled)
+    }
+
+    /// Mark a read-only page whose frame is shared, and that gets a private copy of the frame on the first write.
+    ///
+    /// Like [`PageTableEntryFlags::set_borrowed`], this uses a bit that is ignored by the CPU.
+    pub fn set_copy_on_write(&mut self, enabled: bool) {
+        self.set_flag(11, enabled)
+    }
+
+    pub fn set_write_through(&mut self, enabled: bool) {
+        self.set_flag(3, enabled)
+    }
+
+    /// Disable caching for the page, required for memory mapped IO.
+    pub fn set_cache_disabled(&mut self, enabled: bool) {
+        self.set_flag(4, enabled)
+    }
+
     fn set_flag(&mut self, bit: u64, enabled: bool) {
         if enabled {
             self.value |= 1 << bit;
@@ -77,6 +100,14 @@ impl PageTableEntryFlags {
     pub fn borrowed(&self) -> bool {
         self.value & (1 << 9) != 0
     }
+
+    pub fn owns_frame(&self) -> bool {
+        self.value & (1 << 10) != 0
+    }
+
+    pub fn copy_on_write(&self) -> bool {
+        self.value & (1 << 11) != 0
+    }
 }
 
 impl core::ops::BitOr for PageTableEntryFlags {
@@ -148,7 +179,7 @@ impl PageTableEntry {
 
 impl Display for PageTableEntryFlags {
     fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
-        let mut flags = FixedVec::<8, &'static str>::new();
+        let mut flags = FixedVec::<10, &'static str>::new();
 
         if self.present() {
             flags.push("PRESENT");
@@ -182,6 +213,14 @@ impl Display for PageTableEntryFlags {
             flags.push("BORROWED");
         }
 
+        if self.owns_frame() {
+            flags.push("OWNS_FRAME");
+        }
+
+        if self.copy_on_write() {
+            flags.push("COPY_ON_WRITE");
+        }
+
         for (i, flag) in flags.iter().enumerate() {
             let is_last = i == flags.len() - 1;
 
@@ -207,6 +246,8 @@ impl Debug for PageTableEntryFlags {
             .field("noexec", &self.noexec())
             .field("user_accessible", &self.user_accessible())
             .field("borrowed", &self.borrowed())
+            .field("owns_frame", &self.owns_frame())
+            .field("copy_on_write", &self.copy_on_write())
             .finish()
     }
 }
@@ -289,6 +330,15 @@ impl PageSize {
         }
     }
 
+    /// The level of the page table whose entries map pages of this size.
+    pub const fn level(&self) -> u8 {
+        match self {
+            PageSize::Size4Kib => 1,
+            PageSize::Size2Mib => 2,
+            PageSize::Size1Gib => 3,
+        }
+    }
+
     pub fn as_usize(&self) -> usize {
         match self {
             PageSize::Size4Kib => 4096,
@@ -337,6 +387,13 @@ impl Page<VirtualAddressMarker> {
         addr.align_down(size.as_usize());
         Self { addr, size }
     }
+
+    /// Invalidate the translation of the page in the TLB of the executing CPU.
+    pub fn flush(&self) {
+        unsafe {
+            asm!("invlpg [{}]", in(reg) self.addr.as_u64(), options(nostack, preserves_flags));
+        }
+    }
 }
 
 impl Page<PhysicalAddressMarker> {
@@ -371,6 +428,18 @@ impl Page<PhysicalAddressMarker> {
     }
 }
 
+/// Make the executing CPU respect read-only pages in the kernel privilege level as well, by setting the write protect bit of CR0.
+///
+/// # Safety
+///
+/// The kernel must not rely on writing to read-only pages afterwards.
+pub unsafe fn enable_write_protection() {
+    let mut cr0: u64;
+    asm!("mov {}, cr0", out(reg) cr0, options(nomem, nostack, preserves_flags));
+    cr0 |= 1 << 16;
+    asm!("mov cr0, {}", in(reg) cr0, options(nostack, preserves_flags));
+}
+
 pub type VirtualPage = Page<VirtualAddressMarker>;
 pub type PhysicalPage = Page<PhysicalAddressMarker>;
 
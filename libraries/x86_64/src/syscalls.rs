@@ -10,6 +10,7 @@ pub struct SyscallArgs {
     pub arg1: u64,
     pub arg2: u64,
     pub arg3: u64,
+    pub arg4: u64,
 }
 
 static mut SYSCALL_HANDLER: MaybeUninit<fn(SyscallArgs) -> u64> = MaybeUninit::uninit();
@@ -21,6 +22,7 @@ unsafe extern "C" fn syscall_handler_inner() {
     let arg1: u64;
     let arg2: u64;
     let arg3: u64;
+    let arg4: u64;
 
     asm!(
         "",
@@ -29,6 +31,7 @@ unsafe extern "C" fn syscall_handler_inner() {
         out("rsi") arg1,
         out("rdx") arg2,
         out("r10") arg3,
+        out("r8") arg4,
         options()
     );
 
@@ -38,6 +41,7 @@ unsafe extern "C" fn syscall_handler_inner() {
         arg1,
         arg2,
         arg3,
+        arg4,
     };
 
     let return_value = (SYSCALL_HANDLER.assume_init_ref())(args);